@@ -1,7 +1,7 @@
 import { useState, useEffect } from 'react'
 import { Toolbar } from './components/Toolbar'
 import { Timeline } from './components/Timeline'
-import { CpuChart } from './components/CpuChart'
+import { CpuChart, Utilization } from './components/CpuChart'
 import { WebSocketManager } from './services/WebSocketManager'
 import './App.css'
 
@@ -16,6 +16,7 @@ interface AppState {
     dropRate: number
     cpuLoad?: number
   }
+  utilization?: Utilization
 }
 
 interface Device {
@@ -220,6 +221,25 @@ function App() {
           }))
           break
           
+        case 'Utilization':
+          setState(prev => ({
+            ...prev,
+            utilization: {
+              cpuLoad: message.data.cpu_load ?? undefined,
+              tasks: message.data.tasks.map((task: any) => ({
+                id: task.task_id,
+                name: task.name ?? `Task ${task.task_id}`,
+                share: task.share
+              })),
+              isrs: message.data.isrs.map((isr: any) => ({
+                id: isr.exception,
+                name: isr.name,
+                share: isr.share
+              }))
+            }
+          }))
+          break
+          
         case 'Status':
           setState(prev => ({
             ...prev,
@@ -308,7 +328,8 @@ function App() {
         eventsPerSec: 0,
         bytesPerSec: 0,
         dropRate: 0,
-      }
+      },
+      utilization: undefined
     }))
   }
 
@@ -363,7 +384,7 @@ function App() {
         </div>
         
         <div className="bottom-panel">
-          <CpuChart stats={state.stats} />
+          <CpuChart stats={state.stats} utilization={state.utilization} />
         </div>
       </div>
 
@@ -1,5 +1,17 @@
 import { useState, useEffect } from 'react'
 
+export interface ContextShare {
+  id: number
+  name: string
+  share: number
+}
+
+export interface Utilization {
+  cpuLoad?: number
+  tasks: ContextShare[]
+  isrs: ContextShare[]
+}
+
 interface CpuChartProps {
   stats: {
     eventsPerSec: number
@@ -7,6 +19,7 @@ interface CpuChartProps {
     dropRate: number
     cpuLoad?: number
   }
+  utilization?: Utilization
 }
 
 interface DataPoint {
@@ -16,7 +29,7 @@ interface DataPoint {
   dropRate: number
 }
 
-export function CpuChart({ stats }: CpuChartProps) {
+export function CpuChart({ stats, utilization }: CpuChartProps) {
   const [dataPoints, setDataPoints] = useState<DataPoint[]>([])
   const maxDataPoints = 60 // Keep 60 seconds of data
 
@@ -85,13 +98,34 @@ export function CpuChart({ stats }: CpuChartProps) {
     )
   }
 
+  // Time share per context, busiest first
+  const renderShares = (title: string, shares: ContextShare[]) => (
+    <div style={{ flex: 1 }}>
+      <div style={{ fontSize: '12px', color: '#999', marginBottom: '8px' }}>
+        {title}
+      </div>
+      {shares.length === 0 && (
+        <div style={{ fontSize: '12px', color: '#666', fontStyle: 'italic' }}>None</div>
+      )}
+      {[...shares].sort((a, b) => b.share - a.share).map(context => (
+        <div key={context.id} style={{ display: 'flex', alignItems: 'center', gap: '8px', fontSize: '12px' }}>
+          <div style={{ width: '80px', overflow: 'hidden', textOverflow: 'ellipsis' }}>{context.name}</div>
+          <div style={{ flex: 1, height: '6px', backgroundColor: '#333' }}>
+            <div style={{ width: `${context.share * 100}%`, height: '100%', backgroundColor: '#3b82f6' }} />
+          </div>
+          <div style={{ width: '48px', textAlign: 'right' }}>{(context.share * 100).toFixed(1)}%</div>
+        </div>
+      ))}
+    </div>
+  )
+
   return (
     <div className="cpu-chart">
       <div className="chart-header">
         <div className="chart-title">Performance Monitor</div>
         <div className="chart-stats">
           <div className="stat-item">
-            <div className="stat-value">{stats.cpuLoad ? `${(stats.cpuLoad * 100).toFixed(1)}%` : 'N/A'}</div>
+            <div className="stat-value">{stats.cpuLoad != null ? `${(stats.cpuLoad * 100).toFixed(1)}%` : 'N/A'}</div>
             <div className="stat-label">CPU Load</div>
           </div>
           <div className="stat-item">
@@ -146,6 +180,13 @@ export function CpuChart({ stats }: CpuChartProps) {
         </div>
       </div>
 
+      {utilization && (
+        <div style={{ display: 'flex', gap: '24px', marginTop: '16px' }}>
+          {renderShares('Tasks', utilization.tasks)}
+          {renderShares('ISRs', utilization.isrs)}
+        </div>
+      )}
+
       {dataPoints.length === 0 && (
         <div style={{ 
           textAlign: 'center', 
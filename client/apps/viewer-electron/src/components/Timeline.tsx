@@ -19,10 +19,64 @@ export function Timeline({ events }: TimelineProps) {
           className: 'event-marker',
           content: `Marker ${event.event.data.id}${event.event.data.name ? ` (${event.event.data.name})` : ''}`
         }
-      case 'TaskSwitch':
+      case 'SpanBegin':
+        return {
+          className: 'event-marker',
+          content: `Span begin: ${event.event.data.name ?? event.event.data.span_id}`
+        }
+      case 'SpanEnd':
+        return {
+          className: 'event-marker',
+          content: `Span end: ${event.event.data.name ?? event.event.data.span_id}`
+        }
+      case 'Span': {
+        const { span_id, name, context, depth, duration } = event.event.data
+        const where = context.type === 'Task' ? ` in task ${context.task_id}`
+          : context.type === 'Isr' ? ` in ISR ${context.isr_id}`
+          : context.type === 'Exception' ? ` in exception ${context.exception}` : ''
+        return {
+          className: 'event-marker',
+          content: `Span ${name ?? span_id}: ${duration} cycles${where}${depth > 0 ? ` (depth ${depth})` : ''}`
+        }
+      }
+      case 'SpanDiagnostic': {
+        const { span_id, name, problem } = event.event.data
+        return {
+          className: 'event-overflow',
+          content: `Span ${name ?? span_id}: ${problem.type}`
+        }
+      }
+      case 'TaskSwitch': {
+        const { from_task, to_task, from_name, to_name } = event.event.data
+        return {
+          className: 'event-task',
+          content: `Task switch: ${from_name ?? from_task} → ${to_name ?? to_task}`
+        }
+      }
+      case 'TaskCreate':
+        return {
+          className: 'event-task',
+          content: `Task created: ${event.event.data.task_id} (priority ${event.event.data.priority})`
+        }
+      case 'TaskDelete':
+        return {
+          className: 'event-task',
+          content: `Task deleted: ${event.event.data.task_id}`
+        }
+      case 'TaskRename':
+        return {
+          className: 'event-task',
+          content: `Task ${event.event.data.task_id} named ${event.event.data.name}`
+        }
+      case 'TaskPriority':
         return {
           className: 'event-task',
-          content: `Task switch: ${event.event.data.from_task} → ${event.event.data.to_task}`
+          content: `Task ${event.event.data.task_id} priority ${event.event.data.priority}`
+        }
+      case 'TaskReady':
+        return {
+          className: 'event-task',
+          content: `Task ready: ${event.event.data.name ?? event.event.data.task_id}`
         }
       case 'IsrEnter':
         return {
@@ -32,7 +86,7 @@ export function Timeline({ events }: TimelineProps) {
       case 'IsrExit':
         return {
           className: 'event-isr',
-          content: `ISR exit: ${event.event.data.isr_id}`
+          content: `ISR exit: ${event.event.data.name ?? event.event.data.isr_id}`
         }
       case 'IdleEnter':
         return {
@@ -47,7 +101,52 @@ export function Timeline({ events }: TimelineProps) {
       case 'Counter':
         return {
           className: 'event-counter',
-          content: `Counter ${event.event.data.counter_id}: ${event.event.data.value}`
+          content: `Counter ${event.event.data.name ?? event.event.data.counter_id}: ${event.event.data.value}`
+        }
+      case 'ExceptionEnter':
+        return {
+          className: 'event-isr',
+          content: `Exception enter: ${event.event.data.name ?? event.event.data.exception}`
+        }
+      case 'ExceptionExit':
+        return {
+          className: 'event-isr',
+          content: `Exception exit: ${event.event.data.exception}`
+        }
+      case 'ExceptionReturn':
+        return {
+          className: 'event-isr',
+          content: `Exception return: ${event.event.data.exception}`
+        }
+      case 'DataTrace': {
+        const { comparator, access, value, pc } = event.event.data
+        const valueText = value != null ? ` = 0x${value.toString(16)}` : ''
+        const pcText = pc != null ? ` @ 0x${pc.toString(16)}` : ''
+        return {
+          className: 'event-counter',
+          content: `Watch ${comparator} ${access}${valueText}${pcText}`
+        }
+      }
+      case 'Log': {
+        const { level, message, module_path, file, line } = event.event.data
+        const levelText = level ? `[${level.toUpperCase()}] ` : ''
+        const source = file ? ` (${module_path ?? file}:${line})` : ''
+        return {
+          className: level === 'Error' || level === 'Warn' ? 'event-overflow' : 'event-text',
+          content: `${levelText}${message}${source}`
+        }
+      }
+      case 'Record':
+        return {
+          className: 'event-counter',
+          content: event.event.data.fields
+            .map((field: any) => `${field.name}=${JSON.stringify(field.value)}`)
+            .join(' ')
+        }
+      case 'Overflow':
+        return {
+          className: 'event-overflow',
+          content: 'ITM overflow: trace data lost'
         }
       default:
         return {
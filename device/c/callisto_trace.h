@@ -25,6 +25,9 @@
  *   // Idle state tracking
  *   callisto_idle_enter();
  *   callisto_idle_exit();
+ *   
+ *   // Deferred printf, formatted by the server (port 4 set to Printf)
+ *   CALLISTO_DPRINTF(4, "temp=%d.%u C", temp_int, temp_frac);
  * 
  * @author Callisto ITM Viewer
  * @version 0.1.0
@@ -62,6 +65,17 @@ extern "C" {
 #define CALLISTO_EVT_ISR_EXIT       0x03
 #define CALLISTO_EVT_IDLE_ENTER     0x04
 #define CALLISTO_EVT_IDLE_EXIT      0x05
+#define CALLISTO_EVT_TASK_CREATE    0x06
+#define CALLISTO_EVT_TASK_DELETE    0x07
+#define CALLISTO_EVT_TASK_NAME      0x08
+#define CALLISTO_EVT_TASK_PRIORITY  0x09
+#define CALLISTO_EVT_TASK_READY     0x0A
+
+#define CALLISTO_MAX_TASK_NAME      64  ///< Longest task name sent, in bytes
+
+// Span record types for markers port
+#define CALLISTO_SPAN_BEGIN_RECORD  0x01
+#define CALLISTO_SPAN_END_RECORD    0x02
 
 /**
  * @brief Initialize ITM tracing
@@ -142,6 +156,57 @@ static inline void callisto_marker(uint32_t id)
     callisto_write32(CALLISTO_PORT_MARKERS, id);
 }
 
+/**
+ * @brief Marker ID for a name (same hash as the Rust named_marker)
+ * 
+ * @param name Marker name
+ * @return Marker ID
+ */
+static inline uint32_t callisto_marker_hash(const char* name)
+{
+    uint32_t hash = 0;
+    while (name && *name) {
+        hash = hash * 31 + (uint8_t)*name++;
+    }
+    return hash;
+}
+
+/**
+ * @brief Send a named marker (ID derived from name hash)
+ * 
+ * @param name Marker name, shown by the server if it is in its dictionary
+ */
+static inline void callisto_named_marker(const char* name)
+{
+    callisto_marker(callisto_marker_hash(name));
+}
+
+/**
+ * @brief Begin a span; spans nest per task and ISR
+ * 
+ * @param id Span ID, matched by callisto_span_end
+ */
+static inline void callisto_span_begin(uint32_t id)
+{
+    if (callisto_port_ready(CALLISTO_PORT_MARKERS)) {
+        callisto_write8(CALLISTO_PORT_MARKERS, CALLISTO_SPAN_BEGIN_RECORD);
+        callisto_write32(CALLISTO_PORT_MARKERS, id);
+    }
+}
+
+/**
+ * @brief End the span begun with the same ID
+ * 
+ * @param id Span ID
+ */
+static inline void callisto_span_end(uint32_t id)
+{
+    if (callisto_port_ready(CALLISTO_PORT_MARKERS)) {
+        callisto_write8(CALLISTO_PORT_MARKERS, CALLISTO_SPAN_END_RECORD);
+        callisto_write32(CALLISTO_PORT_MARKERS, id);
+    }
+}
+
 /**
  * @brief Send a task switch event
  * 
@@ -157,6 +222,97 @@ static inline void callisto_task_switch(uint32_t from_task, uint32_t to_task)
     }
 }
 
+/**
+ * @brief Send a task's name, four bytes per record
+ * 
+ * Names longer than CALLISTO_MAX_TASK_NAME bytes are truncated.
+ * 
+ * @param task_id Task ID
+ * @param name Task name
+ */
+static inline void callisto_task_rename(uint32_t task_id, const char* name)
+{
+    size_t len = 0;
+    while (name && name[len] && len < CALLISTO_MAX_TASK_NAME) len++;
+
+    // The final chunk always holds a NUL, unless the name is truncated
+    size_t chunks = (len == CALLISTO_MAX_TASK_NAME) ? len / 4 : len / 4 + 1;
+    if (!callisto_port_ready(CALLISTO_PORT_RTOS)) return;
+    for (size_t i = 0; i < chunks; i++) {
+        uint32_t word = 0;
+        for (size_t j = 0; j < 4 && i * 4 + j < len; j++) {
+            word |= (uint32_t)(uint8_t)name[i * 4 + j] << (8 * j);
+        }
+        callisto_write8(CALLISTO_PORT_RTOS, CALLISTO_EVT_TASK_NAME);
+        callisto_write32(CALLISTO_PORT_RTOS, task_id);
+        callisto_write32(CALLISTO_PORT_RTOS, word);
+    }
+}
+
+/**
+ * @brief Send a task created event, followed by its name
+ * 
+ * @param task_id Task ID
+ * @param priority Task priority
+ * @param name Task name (may be NULL)
+ */
+static inline void callisto_task_create(uint32_t task_id, uint32_t priority, const char* name)
+{
+    if (callisto_port_ready(CALLISTO_PORT_RTOS)) {
+        callisto_write8(CALLISTO_PORT_RTOS, CALLISTO_EVT_TASK_CREATE);
+        callisto_write32(CALLISTO_PORT_RTOS, task_id);
+        callisto_write32(CALLISTO_PORT_RTOS, priority);
+        callisto_task_rename(task_id, name);
+    }
+}
+
+/**
+ * @brief Send a task deleted event
+ * 
+ * @param task_id Task ID
+ */
+static inline void callisto_task_delete(uint32_t task_id)
+{
+    if (callisto_port_ready(CALLISTO_PORT_RTOS)) {
+        callisto_write8(CALLISTO_PORT_RTOS, CALLISTO_EVT_TASK_DELETE);
+        callisto_write32(CALLISTO_PORT_RTOS, task_id);
+        callisto_write32(CALLISTO_PORT_RTOS, 0); // Reserved
+    }
+}
+
+/**
+ * @brief Send a task priority change
+ * 
+ * @param task_id Task ID
+ * @param priority New priority
+ */
+static inline void callisto_task_priority(uint32_t task_id, uint32_t priority)
+{
+    if (callisto_port_ready(CALLISTO_PORT_RTOS)) {
+        callisto_write8(CALLISTO_PORT_RTOS, CALLISTO_EVT_TASK_PRIORITY);
+        callisto_write32(CALLISTO_PORT_RTOS, task_id);
+        callisto_write32(CALLISTO_PORT_RTOS, priority);
+    }
+}
+
+/**
+ * @brief Send a task activation
+ * 
+ * Call when a task becomes ready to run after being created or blocked,
+ * e.g. from FreeRTOS's traceMOVED_TASK_TO_READY_STATE. Not for tasks that
+ * were preempted, which stay ready.
+ * 
+ * @param task_id Task ID
+ */
+static inline void callisto_task_ready(uint32_t task_id)
+{
+    if (callisto_port_ready(CALLISTO_PORT_RTOS)) {
+        callisto_write8(CALLISTO_PORT_RTOS, CALLISTO_EVT_TASK_READY);
+        callisto_write32(CALLISTO_PORT_RTOS, task_id);
+        callisto_write32(CALLISTO_PORT_RTOS, 0); // Reserved
+    }
+}
+
 /**
  * @brief Send an ISR enter event
  * 
@@ -241,8 +397,106 @@ static inline void callisto_event(uint8_t port, uint8_t event_type, uint32_t par
     }
 }
 
+/**
+ * @brief Send a deferred printf record
+ * 
+ * The server looks up the format string by ID and formats the arguments,
+ * so no text is formatted or sent by the target. At most 255 argument
+ * words are sent.
+ * 
+ * @param port Port configured with the Printf decoder
+ * @param format_id Format string ID (address in .callisto_fmt, or a
+ *                  dictionary ID)
+ * @param args Argument words
+ * @param count Number of argument words
+ */
+static inline void callisto_printf_emit(uint8_t port, uint32_t format_id, const uint32_t* args, size_t count)
+{
+    if (count > 255) count = 255;
+    if (callisto_port_ready(port)) {
+        callisto_write8(port, (uint8_t)count);
+        callisto_write32(port, format_id);
+        for (size_t i = 0; i < count; i++) {
+            callisto_write32(port, args[i]);
+        }
+    }
+}
+
+/**
+ * @brief Pass a float to CALLISTO_DPRINTF (sent as its f32 bits)
+ */
+static inline uint32_t callisto_f32(float value)
+{
+    union { float f; uint32_t u; } bits = { value };
+    return bits.u;
+}
+
 // Convenience macros for common use cases
 
+/**
+ * @brief Section holding deferred printf format strings
+ * 
+ * Keep it out of flash in the linker script, for example:
+ *   .callisto_fmt 0 (INFO) : { KEEP(*(.callisto_fmt)) }
+ */
+#define CALLISTO_FMT_SECTION __attribute__((section(".callisto_fmt"), used))
+
+/**
+ * @brief Section holding marker names for CALLISTO_NAMED_MARKER
+ */
+#define CALLISTO_MARKER_SECTION __attribute__((section(".callisto_markers"), used))
+
+/**
+ * @brief Named marker with the name recorded in the ELF
+ * 
+ * Example:
+ *   CALLISTO_NAMED_MARKER("sensor_read");
+ */
+#define CALLISTO_NAMED_MARKER(name) do { \
+    static const char _name[] CALLISTO_MARKER_SECTION = name; \
+    callisto_named_marker(_name); \
+} while(0)
+
+/**
+ * @brief Begin a named span with the name recorded in the ELF
+ * 
+ * Example:
+ *   CALLISTO_SPAN_BEGIN("sensor_read");
+ *   read_sensor();
+ *   CALLISTO_SPAN_END("sensor_read");
+ */
+#define CALLISTO_SPAN_BEGIN(name) do { \
+    static const char _name[] CALLISTO_MARKER_SECTION = name; \
+    callisto_span_begin(callisto_marker_hash(_name)); \
+} while(0)
+
+/**
+ * @brief End a span begun with CALLISTO_SPAN_BEGIN
+ */
+#define CALLISTO_SPAN_END(name) callisto_span_end(callisto_marker_hash(name))
+
+/**
+ * @brief Pass a 64-bit integer to CALLISTO_DPRINTF (use %lld / %llu)
+ */
+#define CALLISTO_U64(x) (uint32_t)((uint64_t)(x)), (uint32_t)((uint64_t)(x) >> 32)
+
+/**
+ * @brief Deferred printf - the format string stays in the ELF
+ * 
+ * Each argument is converted to one 32-bit word. Wrap floats with
+ * callisto_f32() and 64-bit integers with CALLISTO_U64(). %s arguments
+ * must point to strings declared with CALLISTO_FMT_SECTION.
+ * 
+ * Example:
+ *   CALLISTO_DPRINTF(4, "adc=%u vref=%f", adc, callisto_f32(vref));
+ */
+#define CALLISTO_DPRINTF(port, fmt, ...) do { \
+    static const char _fmt[] CALLISTO_FMT_SECTION = fmt; \
+    const uint32_t _args[] = { 0, ##__VA_ARGS__ }; \
+    callisto_printf_emit(port, (uint32_t)(uintptr_t)_fmt, _args + 1, \
+                         sizeof(_args) / sizeof(_args[0]) - 1); \
+} while(0)
+
 /**
  * @brief ISR enter macro - use at the beginning of ISR handlers
  * 
@@ -33,6 +33,9 @@
 //! 
 //! // Performance counters
 //! itm.counters().counter(1, 12345);
+//!
+//! // Deferred printf, formatted by the server (port 4 set to `Printf`)
+//! callisto_trace::trace_printf!(itm, 4, "temp=%d.%u C", 21, 5u8);
 //! ```
 //! 
 //! ## Port Assignments
@@ -83,8 +86,29 @@ pub mod events {
     pub const IDLE_ENTER: u8 = 0x04;
     /// Idle state exit event
     pub const IDLE_EXIT: u8 = 0x05;
+    /// Task created (task ID, priority)
+    pub const TASK_CREATE: u8 = 0x06;
+    /// Task deleted (task ID)
+    pub const TASK_DELETE: u8 = 0x07;
+    /// Four bytes of a task name (task ID, name bytes)
+    pub const TASK_NAME: u8 = 0x08;
+    /// Task priority changed (task ID, priority)
+    pub const TASK_PRIORITY: u8 = 0x09;
+    /// Task made ready to run (task ID)
+    pub const TASK_READY: u8 = 0x0A;
 }
 
+/// Span record types on the markers port
+pub mod span_events {
+    /// Span begin (span ID)
+    pub const SPAN_BEGIN: u8 = 0x01;
+    /// Span end (span ID)
+    pub const SPAN_END: u8 = 0x02;
+}
+
+/// Longest task name sent, in bytes
+pub const MAX_TASK_NAME: usize = 64;
+
 /// ITM stimulus port register
 #[inline(always)]
 fn stim_port(port: u8) -> *mut u32 {
@@ -174,6 +198,11 @@ impl Itm {
     pub fn user_port(&self, port: u8) -> UserPort {
         UserPort::new(port)
     }
+
+    /// Get a deferred printf interface on a user port
+    pub fn printf(&self, port: u8) -> PrintfPort {
+        PrintfPort::new(port)
+    }
 }
 
 impl Default for Itm {
@@ -268,6 +297,52 @@ impl RtosPort {
         }
     }
 
+    /// Send a task created event, followed by its name
+    pub fn task_create(&self, task_id: u32, priority: u32, name: &str) {
+        if port_ready(ports::RTOS) {
+            self.event(events::TASK_CREATE, task_id, priority);
+            self.task_rename(task_id, name);
+        }
+    }
+
+    /// Send a task deleted event
+    pub fn task_delete(&self, task_id: u32) {
+        self.event(events::TASK_DELETE, task_id, 0);
+    }
+
+    /// Send a task's name, four bytes per record
+    ///
+    /// Names longer than [`MAX_TASK_NAME`] bytes are truncated.
+    pub fn task_rename(&self, task_id: u32, name: &str) {
+        let name = &name.as_bytes()[..name.len().min(MAX_TASK_NAME)];
+        // The final chunk always holds a NUL, unless the name is truncated
+        let chunks = if name.len() == MAX_TASK_NAME {
+            name.len() / 4
+        } else {
+            name.len() / 4 + 1
+        };
+        for i in 0..chunks {
+            let mut chunk = [0u8; 4];
+            for (j, byte) in chunk.iter_mut().enumerate() {
+                *byte = name.get(i * 4 + j).copied().unwrap_or(0);
+            }
+            self.event(events::TASK_NAME, task_id, u32::from_le_bytes(chunk));
+        }
+    }
+
+    /// Send a task priority change
+    pub fn task_priority(&self, task_id: u32, priority: u32) {
+        self.event(events::TASK_PRIORITY, task_id, priority);
+    }
+
+    /// Send a task activation: the task became ready to run after being
+    /// created or blocked
+    ///
+    /// Not for tasks that were preempted, which stay ready.
+    pub fn task_ready(&self, task_id: u32) {
+        self.event(events::TASK_READY, task_id, 0);
+    }
+
     /// Send a generic event
     pub fn event(&self, event_type: u8, param_a: u32, param_b: u32) {
         if port_ready(ports::RTOS) {
@@ -294,10 +369,57 @@ impl MarkersPort {
     }
 
     /// Send a named marker (ID derived from name hash)
+    ///
+    /// The server shows the name if it is in its marker dictionary; prefer
+    /// [`trace_named_marker!`], which also records the name in the ELF.
     pub fn named_marker(&self, name: &str) {
         let id = simple_hash(name);
         self.marker(id);
     }
+
+    /// Begin a span; spans nest per task and ISR
+    pub fn span_begin(&self, id: u32) {
+        if port_ready(ports::MARKERS) {
+            write8(ports::MARKERS, span_events::SPAN_BEGIN);
+            write32(ports::MARKERS, id);
+        }
+    }
+
+    /// End the span begun with the same ID
+    pub fn span_end(&self, id: u32) {
+        if port_ready(ports::MARKERS) {
+            write8(ports::MARKERS, span_events::SPAN_END);
+            write32(ports::MARKERS, id);
+        }
+    }
+
+    /// Begin a span with an ID derived from its name
+    pub fn named_span_begin(&self, name: &str) {
+        self.span_begin(simple_hash(name));
+    }
+
+    /// End a span begun with [`MarkersPort::named_span_begin`]
+    pub fn named_span_end(&self, name: &str) {
+        self.span_end(simple_hash(name));
+    }
+
+    /// Begin a span that ends when the returned guard is dropped
+    pub fn span(&self, id: u32) -> SpanGuard {
+        self.span_begin(id);
+        SpanGuard { id }
+    }
+}
+
+/// Ends its span when dropped
+#[must_use = "the span ends as soon as the guard is dropped"]
+pub struct SpanGuard {
+    id: u32,
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        MarkersPort::new().span_end(self.id);
+    }
 }
 
 /// Counters port
@@ -358,11 +480,147 @@ impl UserPort {
     }
 }
 
+/// Deferred printf port
+///
+/// Sends a format string ID and the raw argument words; the server looks
+/// up the format string and does the formatting. IDs are the address of a
+/// string placed in the `.callisto_fmt` section (see [`trace_printf!`]) or
+/// come from a generated printf dictionary.
+pub struct PrintfPort {
+    port: u8,
+}
+
+impl PrintfPort {
+    fn new(port: u8) -> Self {
+        Self { port }
+    }
+
+    /// Send a format ID and its argument words
+    ///
+    /// At most 255 argument words are sent.
+    pub fn emit(&self, format_id: u32, args: &[u32]) {
+        if port_ready(self.port) {
+            let args = &args[..args.len().min(u8::MAX as usize)];
+            write8(self.port, args.len() as u8);
+            write32(self.port, format_id);
+            for &word in args {
+                write32(self.port, word);
+            }
+        }
+    }
+}
+
+/// Deferred printf support used by [`trace_printf!`]
+pub mod printf {
+    /// Most argument words one message can carry
+    pub const MAX_ARG_WORDS: usize = 16;
+
+    /// A value that can be passed as a deferred printf argument
+    ///
+    /// Integers up to 32 bits and `f32` take one word, 64-bit integers
+    /// take two (use `%lld`/`%llu`). Floats are sent as `f32` bits.
+    pub trait PrintfArg {
+        /// Append the argument's words
+        fn push_to(self, words: &mut Words);
+    }
+
+    macro_rules! word_arg {
+        ($($ty:ty),*) => {
+            $(impl PrintfArg for $ty {
+                fn push_to(self, words: &mut Words) {
+                    words.push_word(self as u32);
+                }
+            })*
+        };
+    }
+
+    word_arg!(u8, u16, u32, usize, i8, i16, i32, isize, char, bool);
+
+    impl PrintfArg for u64 {
+        fn push_to(self, words: &mut Words) {
+            words.push_word(self as u32);
+            words.push_word((self >> 32) as u32);
+        }
+    }
+
+    impl PrintfArg for i64 {
+        fn push_to(self, words: &mut Words) {
+            (self as u64).push_to(words);
+        }
+    }
+
+    impl PrintfArg for f32 {
+        fn push_to(self, words: &mut Words) {
+            words.push_word(self.to_bits());
+        }
+    }
+
+    /// Argument words for one message
+    pub struct Words {
+        words: [u32; MAX_ARG_WORDS],
+        len: usize,
+    }
+
+    impl Words {
+        /// Create an empty argument list
+        pub const fn new() -> Self {
+            Self {
+                words: [0; MAX_ARG_WORDS],
+                len: 0,
+            }
+        }
+
+        /// Append an argument; arguments past `MAX_ARG_WORDS` are dropped
+        pub fn push<T: PrintfArg>(&mut self, arg: T) {
+            arg.push_to(self);
+        }
+
+        /// Append a raw word
+        pub fn push_word(&mut self, word: u32) {
+            if self.len < MAX_ARG_WORDS {
+                self.words[self.len] = word;
+                self.len += 1;
+            }
+        }
+
+        /// Words pushed so far
+        pub fn as_slice(&self) -> &[u32] {
+            &self.words[..self.len]
+        }
+    }
+
+    impl Default for Words {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Copy a format string into a fixed-size array at compile time
+    pub const fn format_bytes<const N: usize>(format: &str) -> [u8; N] {
+        let bytes = format.as_bytes();
+        let mut out = [0u8; N];
+        let mut i = 0;
+        while i < N && i < bytes.len() {
+            out[i] = bytes[i];
+            i += 1;
+        }
+        out
+    }
+}
+
 /// Simple hash function for string IDs
 fn simple_hash(s: &str) -> u32 {
+    marker_id(s)
+}
+
+/// Marker ID sent for a named marker, usable in constants
+pub const fn marker_id(name: &str) -> u32 {
+    let bytes = name.as_bytes();
     let mut hash = 0u32;
-    for byte in s.bytes() {
-        hash = hash.wrapping_mul(31).wrapping_add(byte as u32);
+    let mut i = 0;
+    while i < bytes.len() {
+        hash = hash.wrapping_mul(31).wrapping_add(bytes[i] as u32);
+        i += 1;
     }
     hash
 }
@@ -399,6 +657,63 @@ macro_rules! trace_isr_exit {
     };
 }
 
+/// Trace a named marker, recording the name in the ELF
+///
+/// The ID is computed at compile time and the name is stored in the
+/// `.callisto_markers` section, so the server can name the marker from the
+/// firmware ELF. Keep the section out of flash like `.callisto_fmt`.
+#[macro_export]
+macro_rules! trace_named_marker {
+    ($itm:expr, $name:literal) => {{
+        const NAME: &str = concat!($name, "\0");
+        #[link_section = ".callisto_markers"]
+        #[used]
+        static STRING: [u8; NAME.len()] = $crate::printf::format_bytes(NAME);
+        $itm.markers().marker($crate::marker_id($name))
+    }};
+}
+
+/// Begin a named span, recording the name in the ELF like
+/// [`trace_named_marker!`]
+#[macro_export]
+macro_rules! trace_span_begin {
+    ($itm:expr, $name:literal) => {{
+        const NAME: &str = concat!($name, "\0");
+        #[link_section = ".callisto_markers"]
+        #[used]
+        static STRING: [u8; NAME.len()] = $crate::printf::format_bytes(NAME);
+        $itm.markers().span_begin($crate::marker_id($name))
+    }};
+}
+
+/// End a span begun with [`trace_span_begin!`]
+#[macro_export]
+macro_rules! trace_span_end {
+    ($itm:expr, $name:literal) => {
+        $itm.markers().span_end($crate::marker_id($name))
+    };
+}
+
+/// Deferred printf on a user port
+///
+/// The format string is stored in the `.callisto_fmt` section and only its
+/// address is sent, so the server needs the firmware ELF to format it.
+/// Keep the section out of flash with a linker script entry such as
+/// `.callisto_fmt 0 (INFO) : { KEEP(*(.callisto_fmt)) }`.
+#[macro_export]
+macro_rules! trace_printf {
+    ($itm:expr, $port:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {{
+        const FORMAT: &str = concat!($fmt, "\0");
+        #[link_section = ".callisto_fmt"]
+        #[used]
+        static STRING: [u8; FORMAT.len()] = $crate::printf::format_bytes(FORMAT);
+        #[allow(unused_mut)]
+        let mut words = $crate::printf::Words::new();
+        $(words.push($arg);)*
+        $itm.printf($port).emit(STRING.as_ptr() as u32, words.as_slice());
+    }};
+}
+
 /// Global ITM instance (optional convenience)
 #[cfg(feature = "cortex-m")]
 static mut GLOBAL_ITM: Option<Itm> = None;
@@ -471,6 +786,7 @@ mod tests {
     fn test_simple_hash() {
         assert_eq!(simple_hash("test"), simple_hash("test"));
         assert_ne!(simple_hash("test"), simple_hash("different"));
+        assert_eq!(marker_id("test"), 3556498);
     }
 
     #[test]
@@ -480,4 +796,17 @@ mod tests {
         assert_eq!(ports::MARKERS, 2);
         assert_eq!(ports::COUNTERS, 3);
     }
+
+    #[test]
+    fn test_printf_words() {
+        let mut words = printf::Words::new();
+        words.push(-2i8);
+        words.push(0x1_0000_0002u64);
+        words.push(1.5f32);
+        assert_eq!(
+            words.as_slice(),
+            &[0xFFFF_FFFE, 2, 1, 1.5f32.to_bits()]
+        );
+        assert_eq!(printf::format_bytes::<4>("ab\0"), *b"ab\0\0");
+    }
 }
\ No newline at end of file
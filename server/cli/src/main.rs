@@ -7,22 +7,32 @@ use axum::{
         ws::{Message, WebSocket, WebSocketUpgrade},
         State,
     },
-    http::StatusCode,
     response::Response,
     routing::get,
     Router,
 };
 use futures_util::{SinkExt, StreamExt};
-use callisto_core::{ItmSession, MockDataGenerator};
-use callisto_protocol::{ClientMessage, ServerMessage};
+use callisto_core::{
+    ItmSession, MarkerDictionary, ProbeConfig, QueueGauge, SourceConfig, MOCK_MARKER_NAMES,
+};
+use anyhow::Context;
+use callisto_protocol::{ClientMessage, Histogram, PortConfig, ServerMessage};
 use chrono::Utc;
 use clap::Parser;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{mpsc, Mutex};
 use tower_http::cors::CorsLayer;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+/// Bytes of a capture file decoded at once by `--analyze`
+const ANALYZE_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Longest histogram bar printed by `--analyze`
+const HISTOGRAM_WIDTH: u64 = 40;
+
 #[derive(Parser)]
 #[command(name = "callisto")]
 #[command(about = "Callisto ITM Viewer Server")]
@@ -31,10 +41,6 @@ struct Args {
     #[arg(long)]
     list_probes: bool,
 
-    /// Authorization token
-    #[arg(long)]
-    token: Option<String>,
-
     /// ITM baud rate
     #[arg(long, default_value = "2000000")]
     baud: u32,
@@ -43,20 +49,82 @@ struct Args {
     #[arg(long)]
     chip: Option<String>,
 
+    /// Trace from a probe (the default), optionally choosing it as
+    /// `VID:PID` or `VID:PID:serial` rather than using the first found
+    #[arg(long, value_name = "SELECTOR")]
+    probe: Option<Option<String>>,
+
+    /// TPIU input clock in Hz, usually the core clock
+    #[arg(long, default_value = "168000000")]
+    trace_clock: u32,
+
     /// Server port
     #[arg(long, default_value = "9229")]
     port: u16,
 
     /// Enable mock data generation
-    #[arg(long)]
+    #[arg(long, conflicts_with_all = ["replay", "tcp", "probe"])]
     mock: bool,
+
+    /// Read raw trace bytes from a capture file instead of a probe
+    #[arg(long, conflicts_with_all = ["tcp", "probe"])]
+    replay: Option<std::path::PathBuf>,
+
+    /// Read raw trace bytes from a TCP server (e.g. OpenOCD) instead of a probe
+    #[arg(long, conflicts_with = "probe")]
+    tcp: Option<String>,
+
+    /// Input is TPIU formatted; the ITM uses this ATB ID (usually 1)
+    #[arg(long)]
+    tpiu_itm_id: Option<u8>,
+
+    /// Emit a partial text line after this many milliseconds without data
+    #[arg(long, default_value = "200")]
+    text_idle_ms: u64,
+
+    /// JSON marker dictionary: a list of names, or an `{ "id": "name" }` map
+    #[arg(long)]
+    markers: Option<std::path::PathBuf>,
+
+    /// Firmware source tree to scan for named markers
+    #[arg(long)]
+    marker_sources: Option<std::path::PathBuf>,
+
+    /// CMSIS-SVD file for the chip, used to name interrupts
+    #[arg(long)]
+    svd: Option<std::path::PathBuf>,
+
+    /// JSON list of periodic tasks' expected timing, checked for deadline
+    /// misses: `[{ "task": "control", "period": 168000, "deadline": 84000 }]`
+    #[arg(long)]
+    task_deadlines: Option<std::path::PathBuf>,
+
+    /// Decode a capture file, print ISR timing histograms and exit
+    #[arg(long, value_name = "FILE")]
+    analyze: Option<std::path::PathBuf>,
+
+    /// Milliseconds between Stats messages while tracing
+    #[arg(long, default_value = "1000")]
+    stats_interval_ms: u64,
+
+    /// Also send raw ITM packets to clients, for debugging
+    #[arg(long)]
+    raw_itm: bool,
 }
 
+/// Senders for every connected client, keyed by connection ID
+type Clients = Arc<std::sync::Mutex<HashMap<Uuid, mpsc::UnboundedSender<ServerMessage>>>>;
+
 #[derive(Clone)]
 struct AppState {
     server_id: Uuid,
-    token: Option<String>,
-    mock_mode: bool,
+    /// Target clock from `--trace-clock`, reported in Meta
+    trace_clock: u32,
+    /// Trace session shared by all clients, since there is one target
+    session: Arc<Mutex<ItmSession>>,
+    /// Messages sent here reach every connected client
+    broadcast: mpsc::UnboundedSender<ServerMessage>,
+    clients: Clients,
 }
 
 #[tokio::main]
@@ -73,10 +141,67 @@ async fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
+    let clients = Clients::default();
+    let (broadcast, broadcast_rx) = mpsc::unbounded_channel();
+
+    let mut session = ItmSession::new(broadcast.clone());
+    let outgoing = session.monitor_outgoing_queue();
+    tokio::spawn(broadcast_messages(broadcast_rx, clients.clone(), outgoing));
+    session.set_stats_interval(Duration::from_millis(args.stats_interval_ms));
+    session.set_tpiu_formatter(args.tpiu_itm_id);
+    session.set_text_idle_timeout(Duration::from_millis(args.text_idle_ms));
+    session.set_raw_frames(args.raw_itm);
+    session.set_source(if args.mock {
+        SourceConfig::Mock
+    } else if let Some(path) = args.replay {
+        SourceConfig::File(path)
+    } else if let Some(address) = args.tcp {
+        SourceConfig::Tcp(address)
+    } else {
+        SourceConfig::Probe(ProbeConfig {
+            selector: args.probe.flatten(),
+            chip: args.chip,
+            baud: args.baud,
+            tpiu_clock_hz: args.trace_clock,
+        })
+    });
+
+    let mut markers = MarkerDictionary::new();
+    if let Some(path) = &args.markers {
+        markers.load_file(path)?;
+    }
+    if let Some(path) = &args.marker_sources {
+        markers.scan_sources(path)?;
+    }
+    if args.mock {
+        for name in MOCK_MARKER_NAMES {
+            markers.insert_name(name);
+        }
+    }
+    if !markers.is_empty() {
+        info!(
+            "Loaded {} marker name(s), {} hash collision(s)",
+            markers.len(),
+            markers.collisions().len()
+        );
+    }
+    session.set_marker_dictionary(markers);
+    if let Some(path) = &args.svd {
+        session.load_svd(path)?;
+    }
+    if let Some(path) = &args.task_deadlines {
+        session.load_task_deadlines(path)?;
+    }
+    if let Some(path) = &args.analyze {
+        return analyze_capture(session, path).await;
+    }
+
     let state = AppState {
         server_id: Uuid::new_v4(),
-        token: args.token,
-        mock_mode: args.mock,
+        trace_clock: args.trace_clock,
+        session: session.into_shared(),
+        broadcast,
+        clients,
     };
 
     info!("Starting Callisto server on port {}", args.port);
@@ -98,6 +223,19 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Forward session messages to every connected client
+async fn broadcast_messages(
+    mut rx: mpsc::UnboundedReceiver<ServerMessage>,
+    clients: Clients,
+    queue: QueueGauge,
+) {
+    while let Some(msg) = rx.recv().await {
+        queue.set(rx.len());
+        let mut clients = clients.lock().unwrap();
+        clients.retain(|_, client| client.send(msg.clone()).is_ok());
+    }
+}
+
 async fn list_probes() -> anyhow::Result<()> {
     info!("Listing available probes...");
     
@@ -119,6 +257,57 @@ async fn list_probes() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Decode a capture file on every port and print per-interrupt timing
+async fn analyze_capture(mut session: ItmSession, path: &std::path::Path) -> anyhow::Result<()> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    session.start_tracing(u32::MAX, None).await?;
+    for chunk in data.chunks(ANALYZE_CHUNK_SIZE) {
+        session.process_data(chunk)?;
+    }
+    session.flush_packets();
+
+    let ServerMessage::IsrTimingReport { isrs, .. } = session.isr_timing_report() else {
+        unreachable!()
+    };
+    if isrs.is_empty() {
+        println!("No interrupts in {}", path.display());
+    }
+    for isr in isrs {
+        println!(
+            "{} (exception {}): {} run(s), {} preempted, {} unpaired",
+            isr.name, isr.exception, isr.duration.count, isr.preemptions, isr.unpaired
+        );
+        print_histogram("duration", &isr.duration);
+        print_histogram("inter-arrival", &isr.inter_arrival);
+        print_histogram("jitter", &isr.jitter);
+    }
+    Ok(())
+}
+
+/// Print a histogram of cycle counts with a bar per bucket
+fn print_histogram(label: &str, histogram: &Histogram) {
+    if histogram.count == 0 {
+        return;
+    }
+    println!(
+        "  {:<14} min {}  mean {:.1}  max {} cycles",
+        label, histogram.min, histogram.mean, histogram.max
+    );
+    let largest = histogram.buckets.iter().map(|b| b.count).max().unwrap_or(1);
+    for bucket in &histogram.buckets {
+        let width = (bucket.count * HISTOGRAM_WIDTH).div_ceil(largest) as usize;
+        println!(
+            "    {:>10} .. {:<10} {:<width$} {}",
+            bucket.lower,
+            bucket.upper,
+            "#".repeat(width),
+            bucket.count,
+            width = HISTOGRAM_WIDTH as usize
+        );
+    }
+}
+
 async fn websocket_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
@@ -144,18 +333,9 @@ async fn handle_websocket(socket: WebSocket, state: AppState) {
         return;
     }
 
-    // Create ITM session
-    let session = Arc::new(Mutex::new(ItmSession::new(tx.clone())));
-
-    // Start mock data generator if enabled
-    let _mock_handle = if state.mock_mode {
-        let mut mock_gen = MockDataGenerator::new(tx.clone());
-        Some(tokio::spawn(async move {
-            mock_gen.start().await;
-        }))
-    } else {
-        None
-    };
+    // Receive session output alongside the other clients
+    let client_id = Uuid::new_v4();
+    state.clients.lock().unwrap().insert(client_id, tx.clone());
 
     // Spawn task to send messages to client
     let sender_task = {
@@ -185,7 +365,7 @@ async fn handle_websocket(socket: WebSocket, state: AppState) {
             Ok(Message::Text(text)) => {
                 match serde_json::from_str::<ClientMessage>(&text) {
                     Ok(client_msg) => {
-                        if let Err(e) = handle_client_message(client_msg, &session, &tx).await {
+                        if let Err(e) = handle_client_message(client_msg, &state, &tx).await {
                             error!("Error handling client message: {}", e);
                         }
                     }
@@ -206,41 +386,72 @@ async fn handle_websocket(socket: WebSocket, state: AppState) {
         }
     }
 
+    state.clients.lock().unwrap().remove(&client_id);
     sender_task.abort();
     info!("WebSocket connection closed");
 }
 
+/// Meta describing the session's current port configuration
+fn meta_message(session: &ItmSession, cpu_hz: u32) -> ServerMessage {
+    ServerMessage::Meta {
+        ports_map: session.port_config().clone(),
+        cpu_hz: Some(cpu_hz as u64),
+        dwt_available: true,
+        interrupts: session.interrupts().interrupts(),
+    }
+}
+
 async fn handle_client_message(
     msg: ClientMessage,
-    session: &Arc<Mutex<ItmSession>>,
+    state: &AppState,
     tx: &mpsc::UnboundedSender<ServerMessage>,
 ) -> anyhow::Result<()> {
+    let session = &state.session;
     match msg {
         ClientMessage::Connect { probe_selector, chip, token: _ } => {
             info!("Client requesting connection to probe: {:?}, chip: {:?}", probe_selector, chip);
+            let mut session_guard = session.lock().await;
+            session_guard.select_probe(probe_selector.clone(), chip.clone());
             
             let status = ServerMessage::Status {
                 connected: true,
-                target: Some("Mock Target".to_string()),
+                target: Some(session_guard.source().describe()),
                 chip: chip.clone(),
                 probe: probe_selector,
             };
             tx.send(status)?;
         }
         
-        ClientMessage::Start { allow_mask, baud_rate } => {
+        ClientMessage::Start { allow_mask, baud_rate, elf_path, printf_dictionary, svd_path } => {
             info!("Starting ITM tracing with mask: 0x{:08x}, baud: {:?}", allow_mask, baud_rate);
             
             let mut session_guard = session.lock().await;
-            session_guard.start_tracing(allow_mask, baud_rate).await?;
+            let started = async {
+                if let Some(path) = &elf_path {
+                    session_guard.load_firmware_elf(std::path::Path::new(path))?;
+                }
+                if let Some(path) = &printf_dictionary {
+                    session_guard.load_printf_dictionary(std::path::Path::new(path))?;
+                }
+                if let Some(path) = &svd_path {
+                    session_guard.load_svd(std::path::Path::new(path))?;
+                }
+                session_guard.start_tracing(allow_mask, baud_rate).await
+            }
+            .await;
+            if let Err(e) = started {
+                tx.send(ServerMessage::Error {
+                    timestamp: Utc::now(),
+                    message: format!("{:#}", e),
+                    code: None,
+                })?;
+                return Err(e);
+            }
             
             // Send meta information
-            let meta = ServerMessage::Meta {
-                ports_map: callisto_protocol::standard_ports::default_config(),
-                cpu_hz: Some(168_000_000), // Mock 168MHz
-                dwt_available: true,
-            };
-            tx.send(meta)?;
+            state
+                .broadcast
+                .send(meta_message(&session_guard, state.trace_clock))?;
         }
         
         ClientMessage::Stop => {
@@ -262,7 +473,53 @@ async fn handle_client_message(
             debug!("Setting filter - port_mask: {:?}, event_types: {:?}", port_mask, event_types);
             // TODO: Implement filtering
         }
+
+        ClientMessage::SetPortConfig { config } => {
+            update_port_config(vec![config], state, tx).await?;
+        }
+
+        ClientMessage::SetPortConfigs { ports } => {
+            update_port_config(ports, state, tx).await?;
+        }
+
+        ClientMessage::SetTaskDeadlines { tasks } => {
+            info!("Setting deadlines for {} task(s)", tasks.len());
+            session.lock().await.set_task_deadlines(tasks);
+        }
+
+        ClientMessage::GetSchedulingReport => {
+            tx.send(session.lock().await.scheduling_report())?;
+        }
+
+        ClientMessage::GetIsrTimingReport => {
+            tx.send(session.lock().await.isr_timing_report())?;
+        }
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Swap port decoders and tell every client about the new configuration
+async fn update_port_config(
+    configs: Vec<PortConfig>,
+    state: &AppState,
+    tx: &mpsc::UnboundedSender<ServerMessage>,
+) -> anyhow::Result<()> {
+    let ports: Vec<u8> = configs.iter().map(|config| config.port).collect();
+    info!("Reconfiguring ports {:?}", ports);
+
+    let mut session_guard = state.session.lock().await;
+    if let Err(e) = session_guard.update_port_config(configs) {
+        tx.send(ServerMessage::Error {
+            timestamp: Utc::now(),
+            message: format!("{:#}", e),
+            code: Some("INVALID_PARAMETERS".to_string()),
+        })?;
+        return Err(e);
+    }
+
+    state
+        .broadcast
+        .send(meta_message(&session_guard, state.trace_clock))?;
+    Ok(())
+}
@@ -1,102 +1,479 @@
 //! ITM port decoders for different data types
 
-use callisto_protocol::TraceEvent;
-use anyhow::Result;
+use crate::markers::MarkerDictionary;
+use callisto_protocol::{LogLevel, TraceEvent};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::debug;
 
 /// Trait for ITM port decoders
 pub trait ItmDecoder {
     fn decode(&mut self, port: u8, data: &[u8], timestamp: u64) -> Result<Vec<TraceEvent>>;
     fn reset(&mut self);
+
+    /// Trace data may have been lost (overflow or desync), so any partially
+    /// assembled record must be discarded
+    fn discontinuity(&mut self) {
+        self.reset();
+    }
+
+    /// Emit anything held back waiting for more data that has been idle
+    /// for too long
+    fn flush_idle(&mut self, _now: Instant) -> Vec<TraceEvent> {
+        Vec::new()
+    }
 }
 
+/// Reassembles records written as several stimulus port writes
+///
+/// Each instrumentation packet carries exactly one write, so the packet
+/// sizes tell where a record's fields start. A record that arrives as a
+/// single slice of the full record size is accepted as-is.
+pub struct RecordAssembler {
+    layout: &'static [usize],
+    buffer: Vec<u8>,
+    fields: usize,
+}
+
+impl RecordAssembler {
+    /// Create an assembler for records made of writes of the given sizes
+    pub fn new(layout: &'static [usize]) -> Self {
+        Self {
+            layout,
+            buffer: Vec::new(),
+            fields: 0,
+        }
+    }
+
+    /// Size of a complete record in bytes
+    pub fn record_size(&self) -> usize {
+        self.layout.iter().sum()
+    }
+
+    /// Whether a record has been started but not completed
+    pub fn is_partial(&self) -> bool {
+        self.fields > 0
+    }
+
+    /// Add one packet's payload, returning the record it completes
+    pub fn push(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        if self.fields == 0 && data.len() == self.record_size() {
+            return Some(data.to_vec());
+        }
+
+        if data.len() != self.layout[self.fields] {
+            // A write went missing; restart if this looks like the first
+            // field of a new record
+            if self.is_partial() {
+                debug!(
+                    "Dropping partial record after {} of {} field(s)",
+                    self.fields,
+                    self.layout.len()
+                );
+                self.reset();
+            }
+            if data.len() != self.layout[0] {
+                debug!("Ignoring {}-byte write outside a record", data.len());
+                return None;
+            }
+        }
+
+        self.buffer.extend_from_slice(data);
+        self.fields += 1;
+
+        if self.fields == self.layout.len() {
+            self.fields = 0;
+            Some(std::mem::take(&mut self.buffer))
+        } else {
+            None
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.fields = 0;
+    }
+}
+
+/// How long a partial line may sit before it is emitted without a newline
+pub const DEFAULT_TEXT_IDLE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Longest line kept before it is emitted without a newline
+const MAX_LINE_LENGTH: usize = 4096;
+
 /// Text decoder for string data
+///
+/// Bytes are assembled into lines before UTF-8 decoding, so characters
+/// split across packets survive. ANSI escape sequences are stripped, and
+/// lines starting with a level prefix such as `[ERROR]`, `W:` or `<3>`
+/// become `Log` events; other lines are `Text` events.
 pub struct TextDecoder {
-    buffer: String,
+    buffer: Vec<u8>,
+    idle_timeout: Duration,
+    last_data: Option<Instant>,
 }
 
 impl TextDecoder {
     pub fn new() -> Self {
+        Self::with_idle_timeout(DEFAULT_TEXT_IDLE_TIMEOUT)
+    }
+
+    /// Create a decoder that flushes partial lines after `idle_timeout`
+    pub fn with_idle_timeout(idle_timeout: Duration) -> Self {
         Self {
-            buffer: String::new(),
+            buffer: Vec::new(),
+            idle_timeout,
+            last_data: None,
+        }
+    }
+
+    fn line_event(line: &[u8]) -> Option<TraceEvent> {
+        let line = String::from_utf8_lossy(line);
+        let (text, color_level) = strip_ansi(line.trim_end_matches('\r'));
+        if text.trim().is_empty() {
+            return None;
+        }
+
+        match parse_level_prefix(&text) {
+            (Some(level), message) => Some(log_event(level, message)),
+            (None, _) => match color_level {
+                Some(level) => Some(log_event(level, &text)),
+                None => Some(TraceEvent::Text { message: text }),
+            },
         }
     }
 }
 
+impl Default for TextDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ItmDecoder for TextDecoder {
     fn decode(&mut self, _port: u8, data: &[u8], _timestamp: u64) -> Result<Vec<TraceEvent>> {
-        let text = String::from_utf8_lossy(data);
-        self.buffer.push_str(&text);
-        
+        self.buffer.extend_from_slice(data);
+        self.last_data = Some(Instant::now());
+
         let mut events = Vec::new();
-        
+
         // Split on newlines and emit complete messages
-        while let Some(pos) = self.buffer.find('\n') {
-            let message = self.buffer[..pos].to_string();
-            self.buffer.drain(..=pos);
-            
-            if !message.trim().is_empty() {
-                events.push(TraceEvent::Text { message });
-            }
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            events.extend(Self::line_event(&line[..pos]));
         }
-        
+
+        if self.buffer.len() > MAX_LINE_LENGTH {
+            // Break the line on a character boundary
+            let split = match std::str::from_utf8(&self.buffer) {
+                Ok(_) => self.buffer.len(),
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(_) => self.buffer.len(),
+            };
+            let line: Vec<u8> = self.buffer.drain(..split).collect();
+            events.extend(Self::line_event(&line));
+        }
+
         Ok(events)
     }
 
+    fn flush_idle(&mut self, now: Instant) -> Vec<TraceEvent> {
+        match self.last_data {
+            Some(last) if !self.buffer.is_empty() && now - last >= self.idle_timeout => {
+                let line = std::mem::take(&mut self.buffer);
+                Self::line_event(&line).into_iter().collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
     fn reset(&mut self) {
         self.buffer.clear();
+        self.last_data = None;
     }
 }
 
+fn log_event(level: LogLevel, message: &str) -> TraceEvent {
+    TraceEvent::Log {
+        level: Some(level),
+        message: message.to_string(),
+        module_path: None,
+        file: None,
+        line: None,
+        device_time: None,
+    }
+}
+
+/// Remove ANSI escape sequences, returning the severity implied by the
+/// first red or yellow foreground color
+fn strip_ansi(line: &str) -> (String, Option<LogLevel>) {
+    let mut text = String::with_capacity(line.len());
+    let mut level = None;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            text.push(c);
+            continue;
+        }
+        if chars.peek() != Some(&'[') {
+            // Two-character escape
+            chars.next();
+            continue;
+        }
+        chars.next();
+
+        let mut params = String::new();
+        let mut command = None;
+        for c in chars.by_ref() {
+            if ('\x40'..='\x7e').contains(&c) {
+                command = Some(c);
+                break;
+            }
+            params.push(c);
+        }
+
+        if command == Some('m') && level.is_none() {
+            level = params.split(';').find_map(|code| match code {
+                "31" | "91" => Some(LogLevel::Error),
+                "33" | "93" => Some(LogLevel::Warn),
+                _ => None,
+            });
+        }
+    }
+
+    (text, level)
+}
+
+fn level_from_name(name: &str) -> Option<LogLevel> {
+    Some(match name.to_ascii_uppercase().as_str() {
+        "E" | "ERR" | "ERROR" | "FATAL" => LogLevel::Error,
+        "W" | "WRN" | "WARN" | "WARNING" => LogLevel::Warn,
+        "I" | "INF" | "INFO" | "NOTICE" => LogLevel::Info,
+        "D" | "DBG" | "DEBUG" => LogLevel::Debug,
+        "T" | "V" | "TRC" | "TRACE" | "VERBOSE" => LogLevel::Trace,
+        _ => return None,
+    })
+}
+
+/// Parse a `[LEVEL]`, `LEVEL:` or syslog `<N>` prefix, returning the
+/// level and the rest of the line
+fn parse_level_prefix(line: &str) -> (Option<LogLevel>, &str) {
+    let trimmed = line.trim_start();
+
+    let parsed = if let Some(rest) = trimmed.strip_prefix('[') {
+        rest.split_once(']')
+            .and_then(|(name, rest)| Some((level_from_name(name.trim())?, rest)))
+    } else if let Some(rest) = trimmed.strip_prefix('<') {
+        rest.split_once('>').and_then(|(priority, rest)| {
+            let level = match priority.parse::<u8>().ok()? {
+                0..=3 => LogLevel::Error,
+                4 => LogLevel::Warn,
+                5 | 6 => LogLevel::Info,
+                7 => LogLevel::Debug,
+                _ => return None,
+            };
+            Some((level, rest))
+        })
+    } else {
+        trimmed.split_once(':').and_then(|(name, rest)| {
+            // Single letters only in capitals, so `a: b` stays text
+            if name.len() == 1 && !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+                return None;
+            }
+            Some((level_from_name(name)?, rest))
+        })
+    };
+
+    match parsed {
+        Some((level, rest)) => (Some(level), rest.trim_start()),
+        None => (None, line),
+    }
+}
+
+/// Span record types on the markers port
+pub mod span_events {
+    pub const SPAN_BEGIN: u8 = 0x01;
+    pub const SPAN_END: u8 = 0x02;
+}
+
 /// Marker decoder for timestamped events
-pub struct MarkerDecoder;
+///
+/// A lone 32-bit write is a marker. An 8-bit span record type followed by
+/// a 32-bit span ID begins or ends a span. IDs found in the marker
+/// dictionary are shown with their names.
+pub struct MarkerDecoder {
+    span_type: Option<u8>,
+    dictionary: Arc<MarkerDictionary>,
+}
 
 impl MarkerDecoder {
     pub fn new() -> Self {
-        Self
+        Self::with_dictionary(Arc::new(MarkerDictionary::new()))
+    }
+
+    /// Create a decoder that resolves IDs through a marker dictionary
+    pub fn with_dictionary(dictionary: Arc<MarkerDictionary>) -> Self {
+        Self {
+            span_type: None,
+            dictionary,
+        }
+    }
+
+    fn name(&self, id: u32) -> Option<String> {
+        self.dictionary.get(id).map(str::to_string)
+    }
+}
+
+impl Default for MarkerDecoder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl ItmDecoder for MarkerDecoder {
     fn decode(&mut self, _port: u8, data: &[u8], _timestamp: u64) -> Result<Vec<TraceEvent>> {
-        if data.len() >= 4 {
-            let id = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-            Ok(vec![TraceEvent::Marker { 
+        let word = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+        let (span_type, id) = match data.len() {
+            1 => {
+                if self.span_type.is_some() {
+                    debug!("Dropping span record without an ID");
+                }
+                self.span_type = Some(data[0]);
+                return Ok(vec![]);
+            }
+            4 => (self.span_type.take(), word(data)),
+            // Whole span record in one slice
+            5 => (Some(data[0]), word(&data[1..])),
+            len => {
+                debug!("Ignoring {}-byte write on markers port", len);
+                self.span_type = None;
+                return Ok(vec![]);
+            }
+        };
+
+        let event = match span_type {
+            None => TraceEvent::Marker { 
                 id, 
-                name: Some(format!("Marker {}", id))
-            }])
-        } else {
-            Ok(vec![])
-        }
+                name: Some(self.name(id).unwrap_or_else(|| format!("Marker {}", id)))
+            },
+            Some(span_events::SPAN_BEGIN) => TraceEvent::SpanBegin { span_id: id, name: self.name(id) },
+            Some(span_events::SPAN_END) => TraceEvent::SpanEnd { span_id: id, name: self.name(id) },
+            Some(other) => {
+                debug!("Unknown span record type 0x{:02x}", other);
+                return Ok(vec![]);
+            }
+        };
+        Ok(vec![event])
     }
 
     fn reset(&mut self) {
-        // No state to reset
+        self.span_type = None;
     }
 }
 
+/// RTOS event types on the task/ISR port
+pub mod rtos_events {
+    pub const TASK_SWITCH: u8 = 0x01;
+    pub const ISR_ENTER: u8 = 0x02;
+    pub const ISR_EXIT: u8 = 0x03;
+    pub const IDLE_ENTER: u8 = 0x04;
+    pub const IDLE_EXIT: u8 = 0x05;
+    pub const TASK_CREATE: u8 = 0x06;
+    pub const TASK_DELETE: u8 = 0x07;
+    pub const TASK_NAME: u8 = 0x08;
+    pub const TASK_PRIORITY: u8 = 0x09;
+    pub const TASK_READY: u8 = 0x0A;
+}
+
+/// Longest task name accepted from `TASK_NAME` records
+pub const MAX_TASK_NAME: usize = 64;
+
 /// Task/ISR decoder for RTOS events
-pub struct TaskIsrDecoder;
+///
+/// Records are an event type byte followed by two 32-bit parameters,
+/// written as three separate stimulus writes. Task names are sent as a
+/// series of `TASK_NAME` records carrying four bytes each, ending with
+/// the first record that contains a NUL byte.
+pub struct TaskIsrDecoder {
+    assembler: RecordAssembler,
+    names: HashMap<u32, Vec<u8>>,
+}
 
 impl TaskIsrDecoder {
     pub fn new() -> Self {
-        Self
+        Self {
+            assembler: RecordAssembler::new(&[1, 4, 4]),
+            names: HashMap::new(),
+        }
+    }
+
+    /// Add four bytes of a task name, returning the name once complete
+    fn name_chunk(&mut self, task_id: u32, chunk: [u8; 4]) -> Option<String> {
+        let name = self.names.entry(task_id).or_default();
+        let end = chunk.iter().position(|&b| b == 0);
+        name.extend_from_slice(&chunk[..end.unwrap_or(4)]);
+
+        if end.is_none() && name.len() < MAX_TASK_NAME {
+            return None;
+        }
+        let name = self.names.remove(&task_id).unwrap_or_default();
+        Some(String::from_utf8_lossy(&name).into_owned())
+    }
+}
+
+impl Default for TaskIsrDecoder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl ItmDecoder for TaskIsrDecoder {
     fn decode(&mut self, _port: u8, data: &[u8], _timestamp: u64) -> Result<Vec<TraceEvent>> {
-        if data.len() >= 9 {
+        use rtos_events::*;
+
+        if let Some(data) = self.assembler.push(data) {
             let event_type = data[0];
             let param_a = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
             let param_b = u32::from_le_bytes([data[5], data[6], data[7], data[8]]);
             
             let event = match event_type {
-                0x01 => TraceEvent::TaskSwitch { from_task: param_a, to_task: param_b },
-                0x02 => TraceEvent::IsrEnter { isr_id: param_a, name: Some(format!("ISR {}", param_a)) },
-                0x03 => TraceEvent::IsrExit { isr_id: param_a },
-                0x04 => TraceEvent::IdleEnter,
-                0x05 => TraceEvent::IdleExit,
-                _ => TraceEvent::Raw { data: data.to_vec() },
+                TASK_SWITCH => TraceEvent::TaskSwitch {
+                    from_task: param_a,
+                    to_task: param_b,
+                    from_name: None,
+                    to_name: None,
+                    from_priority: None,
+                    to_priority: None,
+                },
+                ISR_ENTER => TraceEvent::IsrEnter {
+                    isr_id: param_a,
+                    name: Some(format!("ISR {}", param_a)),
+                    peripheral: None,
+                },
+                ISR_EXIT => TraceEvent::IsrExit {
+                    isr_id: param_a,
+                    name: None,
+                    peripheral: None,
+                },
+                IDLE_ENTER => TraceEvent::IdleEnter,
+                IDLE_EXIT => TraceEvent::IdleExit,
+                TASK_CREATE => TraceEvent::TaskCreate { task_id: param_a, priority: param_b },
+                TASK_DELETE => {
+                    self.names.remove(&param_a);
+                    TraceEvent::TaskDelete { task_id: param_a }
+                }
+                TASK_NAME => match self.name_chunk(param_a, param_b.to_le_bytes()) {
+                    Some(name) => TraceEvent::TaskRename { task_id: param_a, name },
+                    None => return Ok(vec![]),
+                },
+                TASK_PRIORITY => TraceEvent::TaskPriority { task_id: param_a, priority: param_b },
+                TASK_READY => TraceEvent::TaskReady { task_id: param_a, name: None },
+                _ => TraceEvent::Raw { data },
             };
             
             Ok(vec![event])
@@ -106,35 +483,737 @@ impl ItmDecoder for TaskIsrDecoder {
     }
 
     fn reset(&mut self) {
-        // No state to reset
+        self.assembler.reset();
+        self.names.clear();
     }
 }
 
 /// Counter decoder for performance metrics
-pub struct CounterDecoder;
+///
+/// Records are a 32-bit counter ID followed by the low and high words of
+/// the 64-bit value.
+pub struct CounterDecoder {
+    assembler: RecordAssembler,
+}
 
 impl CounterDecoder {
     pub fn new() -> Self {
-        Self
+        Self {
+            assembler: RecordAssembler::new(&[4, 4, 4]),
+        }
+    }
+}
+
+impl Default for CounterDecoder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl ItmDecoder for CounterDecoder {
     fn decode(&mut self, _port: u8, data: &[u8], _timestamp: u64) -> Result<Vec<TraceEvent>> {
-        if data.len() >= 12 {
+        if let Some(data) = self.assembler.push(data) {
             let counter_id = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
             let value = u64::from_le_bytes([
                 data[4], data[5], data[6], data[7],
                 data[8], data[9], data[10], data[11]
             ]);
             
-            Ok(vec![TraceEvent::Counter { counter_id, value }])
+            Ok(vec![TraceEvent::Counter {
+                counter_id,
+                value,
+                name: None,
+            }])
         } else {
             Ok(vec![])
         }
     }
 
     fn reset(&mut self) {
-        // No state to reset
+        self.assembler.reset();
+    }
+}
+
+/// ELF section holding deferred printf format strings
+pub const PRINTF_SECTION: &str = ".callisto_fmt";
+
+/// Format strings for deferred printf, keyed by format ID
+///
+/// IDs are either the address of the string in the firmware's
+/// `.callisto_fmt` section, or assigned by a generated JSON dictionary.
+#[derive(Debug, Clone, Default)]
+pub struct PrintfTable {
+    formats: HashMap<u32, String>,
+}
+
+impl PrintfTable {
+    pub fn new() -> Self {
+        Self::default()
     }
-}
\ No newline at end of file
+
+    /// Load the format strings from a firmware ELF
+    ///
+    /// Returns `None` if the ELF has no `.callisto_fmt` section.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let data =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse_elf(&data)
+    }
+
+    /// Parse the format strings from ELF file contents
+    pub fn parse_elf(data: &[u8]) -> Result<Option<Self>> {
+        use object::{Object, ObjectSection};
+
+        let file = object::File::parse(data).context("Not a valid ELF file")?;
+        let Some(section) = file.section_by_name(PRINTF_SECTION) else {
+            return Ok(None);
+        };
+        let bytes = section
+            .data()
+            .with_context(|| format!("Failed to read {}", PRINTF_SECTION))?;
+
+        let mut table = Self::new();
+        let mut offset = 0;
+        for string in bytes.split(|&b| b == 0) {
+            // Empty runs are alignment padding between strings
+            if !string.is_empty() {
+                let id = section.address() as u32 + offset as u32;
+                table.insert(id, &String::from_utf8_lossy(string));
+            }
+            offset += string.len() + 1;
+        }
+        Ok(Some(table))
+    }
+
+    /// Load a generated JSON dictionary of `{ "id": "format" }` entries
+    ///
+    /// IDs may be decimal or `0x` prefixed hexadecimal.
+    pub fn load_dictionary(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse_dictionary(&json)
+            .with_context(|| format!("Invalid printf dictionary {}", path.display()))
+    }
+
+    /// Parse a JSON dictionary of `{ "id": "format" }` entries
+    pub fn parse_dictionary(json: &str) -> Result<Self> {
+        let entries: HashMap<String, String> = serde_json::from_str(json)?;
+
+        let mut table = Self::new();
+        for (key, format) in entries {
+            let id = match key.strip_prefix("0x").or_else(|| key.strip_prefix("0X")) {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => key.parse(),
+            }
+            .map_err(|_| anyhow::anyhow!("`{}` is not a valid format ID", key))?;
+            table.insert(id, &format);
+        }
+        Ok(table)
+    }
+
+    pub fn insert(&mut self, id: u32, format: &str) {
+        self.formats.insert(id, format.to_string());
+    }
+
+    /// Add every entry of another table, replacing duplicate IDs
+    pub fn extend(&mut self, other: PrintfTable) {
+        self.formats.extend(other.formats);
+    }
+
+    pub fn get(&self, id: u32) -> Option<&str> {
+        self.formats.get(&id).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.formats.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.formats.is_empty()
+    }
+
+    /// Apply printf formatting to argument words
+    ///
+    /// Each argument takes one 32-bit word, except `ll` and `j` integers
+    /// which take two (low word first). Floating point arguments are sent
+    /// as `f32` bits. `%s` arguments are addresses of strings in the table.
+    pub fn format(&self, format: &str, words: &[u32]) -> String {
+        let mut args = words.iter().copied();
+        let mut missing = false;
+        let mut next = || {
+            args.next().unwrap_or_else(|| {
+                missing = true;
+                0
+            })
+        };
+
+        let mut out = String::new();
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+
+            let mut spec = Spec::default();
+            while let Some(&flag) = chars.peek() {
+                match flag {
+                    '-' => spec.left = true,
+                    '+' => spec.plus = true,
+                    ' ' => spec.space = true,
+                    '0' => spec.zero = true,
+                    '#' => spec.alternate = true,
+                    _ => break,
+                }
+                chars.next();
+            }
+
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                let width = next() as i32;
+                spec.left |= width < 0;
+                spec.width = width.unsigned_abs() as usize;
+            } else {
+                spec.width = parse_number(&mut chars);
+            }
+
+            if chars.peek() == Some(&'.') {
+                chars.next();
+                if chars.peek() == Some(&'*') {
+                    chars.next();
+                    let precision = next() as i32;
+                    spec.precision = (precision >= 0).then_some(precision as usize);
+                } else {
+                    spec.precision = Some(parse_number(&mut chars));
+                }
+            }
+            // Arguments come from the target; keep a bad width within a line
+            spec.width = spec.width.min(MAX_LINE_LENGTH);
+            spec.precision = spec.precision.map(|precision| precision.min(MAX_LINE_LENGTH));
+
+            let mut length = String::new();
+            while let Some(&l) = chars.peek() {
+                if !"hlLqjzt".contains(l) {
+                    break;
+                }
+                length.push(l);
+                chars.next();
+            }
+            let wide = matches!(length.as_str(), "ll" | "q" | "j");
+
+            let Some(conversion) = chars.next() else {
+                out.push('%');
+                break;
+            };
+
+            let int_arg = |next: &mut dyn FnMut() -> u32| {
+                let low = next() as u64;
+                if wide {
+                    low | (next() as u64) << 32
+                } else {
+                    low
+                }
+            };
+
+            let body = match conversion {
+                '%' => {
+                    out.push('%');
+                    continue;
+                }
+                'd' | 'i' => {
+                    let raw = int_arg(&mut next);
+                    let value = match length.as_str() {
+                        "hh" => raw as i8 as i64,
+                        "h" => raw as i16 as i64,
+                        _ if wide => raw as i64,
+                        _ => raw as u32 as i32 as i64,
+                    };
+                    spec.integer(value.unsigned_abs(), value < 0, 10, "")
+                }
+                'u' | 'x' | 'X' | 'o' => {
+                    let raw = int_arg(&mut next);
+                    let value = match length.as_str() {
+                        "hh" => raw as u8 as u64,
+                        "h" => raw as u16 as u64,
+                        _ => raw,
+                    };
+                    let (base, prefix) = match conversion {
+                        'x' => (16, "0x"),
+                        'X' => (16, "0X"),
+                        'o' => (8, "0"),
+                        _ => (10, ""),
+                    };
+                    let prefix = if spec.alternate && value != 0 { prefix } else { "" };
+                    let digits = spec.integer(value, false, base, prefix);
+                    if conversion == 'X' {
+                        digits.to_uppercase()
+                    } else {
+                        digits
+                    }
+                }
+                'p' => format!("0x{:08x}", next()),
+                'c' => char::from(next() as u8).to_string(),
+                's' => {
+                    let address = next();
+                    let text = match self.get(address) {
+                        Some(text) => text.to_string(),
+                        None => format!("<string 0x{:08x}>", address),
+                    };
+                    match spec.precision {
+                        Some(precision) => text.chars().take(precision).collect(),
+                        None => text,
+                    }
+                }
+                'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                    let value = f32::from_bits(next()) as f64;
+                    spec.float(value, conversion)
+                }
+                'n' => {
+                    next();
+                    continue;
+                }
+                other => {
+                    // Not a conversion we know; show it as written
+                    out.push('%');
+                    out.push(other);
+                    continue;
+                }
+            };
+
+            out.push_str(&spec.pad(body));
+        }
+
+        if missing {
+            debug!("printf `{}` is missing argument words", format);
+            out.push_str(" <missing arguments>");
+        }
+        out
+    }
+}
+
+/// Flags, width and precision of one printf conversion
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    zero: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    fn integer(&self, magnitude: u64, negative: bool, base: u32, prefix: &str) -> String {
+        let mut digits = match base {
+            8 => format!("{:o}", magnitude),
+            16 => format!("{:x}", magnitude),
+            _ => magnitude.to_string(),
+        };
+        if let Some(precision) = self.precision {
+            if precision == 0 && magnitude == 0 {
+                digits.clear();
+            }
+            if digits.len() < precision {
+                digits.insert_str(0, &"0".repeat(precision - digits.len()));
+            }
+        }
+        let sign = if base == 10 { self.sign(negative) } else { "" };
+        self.zero_fill(&format!("{}{}", sign, prefix), digits, self.precision.is_none())
+    }
+
+    fn float(&self, value: f64, conversion: char) -> String {
+        let sign = self.sign(value.is_sign_negative() && !value.is_nan());
+        let value = value.abs();
+        let upper = conversion.is_ascii_uppercase();
+
+        if !value.is_finite() {
+            let text = if value.is_nan() { "nan" } else { "inf" };
+            let text = if upper { text.to_uppercase() } else { text.to_string() };
+            return format!("{}{}", sign, text);
+        }
+
+        let precision = self.precision.unwrap_or(6);
+        let digits = match conversion.to_ascii_lowercase() {
+            'f' => format!("{:.*}", precision, value),
+            'e' => exponent_form(value, precision),
+            _ => {
+                // %g: the shorter of %e and %f, without trailing zeros
+                let precision = precision.max(1);
+                let exponent = exponent_form(value, precision - 1)
+                    .rsplit_once('e')
+                    .and_then(|(_, e)| e.parse::<i32>().ok())
+                    .unwrap_or(0);
+                let text = if exponent < -4 || exponent >= precision as i32 {
+                    exponent_form(value, precision - 1)
+                } else {
+                    format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+                };
+                if self.alternate {
+                    text
+                } else {
+                    strip_fraction_zeros(&text)
+                }
+            }
+        };
+        let digits = if upper { digits.to_uppercase() } else { digits };
+        self.zero_fill(sign, digits, true)
+    }
+
+    /// Pad with zeros between the sign and the digits if requested
+    fn zero_fill(&self, prefix: &str, digits: String, allowed: bool) -> String {
+        let len = prefix.len() + digits.len();
+        if self.zero && !self.left && allowed && len < self.width {
+            format!("{}{}{}", prefix, "0".repeat(self.width - len), digits)
+        } else {
+            format!("{}{}", prefix, digits)
+        }
+    }
+
+    fn pad(&self, body: String) -> String {
+        let len = body.chars().count();
+        if len >= self.width {
+            body
+        } else if self.left {
+            format!("{}{}", body, " ".repeat(self.width - len))
+        } else {
+            format!("{}{}", " ".repeat(self.width - len), body)
+        }
+    }
+}
+
+fn parse_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> usize {
+    let mut value = 0usize;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        value = value.saturating_mul(10).saturating_add(digit as usize);
+        chars.next();
+    }
+    value
+}
+
+/// C style `%e`: at least two exponent digits, always signed
+fn exponent_form(value: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, value);
+    match text.split_once('e') {
+        Some((mantissa, exponent)) => {
+            let exponent: i32 = exponent.parse().unwrap_or(0);
+            format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+        }
+        None => text,
+    }
+}
+
+fn strip_fraction_zeros(text: &str) -> String {
+    let (mantissa, exponent) = match text.find('e') {
+        Some(pos) => text.split_at(pos),
+        None => (text, ""),
+    };
+    let mantissa = if mantissa.contains('.') {
+        mantissa.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        mantissa
+    };
+    format!("{}{}", mantissa, exponent)
+}
+
+/// Deferred printf decoder
+///
+/// Records are an 8-bit argument word count, the 32-bit format ID, then
+/// the argument words, each as a separate stimulus write. The format
+/// string is looked up on the host, so the firmware never formats text.
+pub struct PrintfDecoder {
+    table: Arc<PrintfTable>,
+    record: Option<PrintfRecord>,
+}
+
+struct PrintfRecord {
+    count: usize,
+    id: Option<u32>,
+    words: Vec<u32>,
+}
+
+impl PrintfDecoder {
+    pub fn new(table: Arc<PrintfTable>) -> Self {
+        Self {
+            table,
+            record: None,
+        }
+    }
+
+    fn message(&self, id: u32, words: &[u32]) -> TraceEvent {
+        let message = match self.table.get(id) {
+            Some(format) => {
+                let text = self.table.format(format, words);
+                text.strip_suffix('\n').map(str::to_string).unwrap_or(text)
+            }
+            None => format!("<unknown printf format 0x{:08x}> {:08x?}", id, words),
+        };
+        TraceEvent::Text { message }
+    }
+}
+
+impl ItmDecoder for PrintfDecoder {
+    fn decode(&mut self, _port: u8, data: &[u8], _timestamp: u64) -> Result<Vec<TraceEvent>> {
+        let word = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+        // A whole record in one slice
+        if self.record.is_none() && data.len() >= 5 && data.len() == 5 + 4 * data[0] as usize {
+            let words: Vec<u32> = data[5..].chunks_exact(4).map(word).collect();
+            return Ok(vec![self.message(word(&data[1..5]), &words)]);
+        }
+
+        match data.len() {
+            1 => {
+                if let Some(record) = &self.record {
+                    debug!(
+                        "Dropping printf record with {} of {} argument word(s)",
+                        record.words.len(),
+                        record.count
+                    );
+                }
+                self.record = Some(PrintfRecord {
+                    count: data[0] as usize,
+                    id: None,
+                    words: Vec::new(),
+                });
+            }
+            4 => match &mut self.record {
+                Some(record) => match record.id {
+                    None => record.id = Some(word(data)),
+                    Some(_) => record.words.push(word(data)),
+                },
+                None => debug!("Ignoring printf word outside a record"),
+            },
+            len => {
+                debug!("Ignoring {}-byte write on printf port", len);
+                self.record = None;
+            }
+        }
+
+        match &self.record {
+            Some(PrintfRecord {
+                count,
+                id: Some(id),
+                words,
+            }) if words.len() == *count => {
+                let event = self.message(*id, words);
+                self.record = None;
+                Ok(vec![event])
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.record = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_packets(decoder: &mut dyn ItmDecoder, packets: &[&[u8]]) -> Vec<TraceEvent> {
+        packets
+            .iter()
+            .flat_map(|packet| decoder.decode(1, packet, 0).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_task_switch_from_separate_writes() {
+        let mut decoder = TaskIsrDecoder::new();
+        let events = decode_packets(&mut decoder, &[&[0x01], &[1, 0, 0, 0], &[2, 0, 0, 0]]);
+        assert!(matches!(
+            events[..],
+            [TraceEvent::TaskSwitch { from_task: 1, to_task: 2, .. }]
+        ));
+
+        // A whole record in one slice still decodes
+        let events = decode_packets(&mut decoder, &[&[0x03, 7, 0, 0, 0, 0, 0, 0, 0]]);
+        assert!(matches!(events[..], [TraceEvent::IsrExit { isr_id: 7, .. }]));
+    }
+
+    #[test]
+    fn test_truncated_record_is_dropped() {
+        let mut decoder = TaskIsrDecoder::new();
+
+        // Second parameter lost, next record starts with its type byte
+        let events = decode_packets(
+            &mut decoder,
+            &[&[0x02], &[5, 0, 0, 0], &[0x03], &[6, 0, 0, 0], &[0, 0, 0, 0]],
+        );
+        assert!(matches!(events[..], [TraceEvent::IsrExit { isr_id: 6, .. }]));
+    }
+
+    #[test]
+    fn test_discontinuity_discards_partial_record() {
+        let mut decoder = CounterDecoder::new();
+
+        let events = decode_packets(&mut decoder, &[&[1, 0, 0, 0], &[9, 0, 0, 0]]);
+        assert!(events.is_empty());
+
+        decoder.discontinuity();
+        let events = decode_packets(
+            &mut decoder,
+            &[&[2, 0, 0, 0], &[0x10, 0, 0, 0], &[0, 0, 0, 0]],
+        );
+        assert!(matches!(
+            events[..],
+            [TraceEvent::Counter { counter_id: 2, value: 0x10, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_printf_formatting() {
+        let mut table = PrintfTable::new();
+        table.insert(0x100, "sensor");
+
+        let cases: &[(&str, &[u32], &str)] = &[
+            ("%d %u %x %X", &[-5i32 as u32, 7, 0xbeef, 0xbeef], "-5 7 beef BEEF"),
+            ("[%5d|%-5d|%05d|%+d]", &[42, 42, -42i32 as u32, 3], "[   42|42   |-0042|+3]"),
+            ("%#x %#o %.3d %c%%", &[255, 8, 7, b'A' as u32], "0xff 010 007 A%"),
+            ("%lld", &[0xFFFF_FFFE, 0xFFFF_FFFF], "-2"),
+            ("%hhu %hd", &[0x1FF, 0xFFFF], "255 -1"),
+            ("%*d|%.*f", &[4, 9, 2, 1.5f32.to_bits()], "   9|1.50"),
+            ("%f %e %g %g", &[2.5f32.to_bits(), 1234.5f32.to_bits(), 0.0001f32.to_bits(), 1e7f32.to_bits()],
+             "2.500000 1.234500e+03 0.0001 1e+07"),
+            ("%s=%.3s %s", &[0x100, 0x100, 0x200], "sensor=sen <string 0x00000200>"),
+            ("%d %d", &[1], "1 0 <missing arguments>"),
+        ];
+        for (format, words, expected) in cases {
+            assert_eq!(table.format(format, words), *expected, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_printf_width_and_precision_are_clamped() {
+        let table = PrintfTable::new();
+        let text = table.format("%*d", &[0x7FFF_FFFF, 1]);
+        assert_eq!(text.len(), MAX_LINE_LENGTH);
+        assert!(text.ends_with(" 1"));
+        let text = table.format("%*d", &[0x8000_0000, 1]);
+        assert_eq!(text.len(), MAX_LINE_LENGTH);
+        assert!(text.starts_with("1 "));
+        let text = table.format("%.4000000000f", &[1.5f32.to_bits()]);
+        assert_eq!(text.len(), 2 + MAX_LINE_LENGTH);
+    }
+
+    #[test]
+    fn test_printf_records_from_separate_writes() {
+        let table = PrintfTable::parse_dictionary(r#"{"0x10": "temp=%d.%u C\n", "2": "boot"}"#).unwrap();
+        let mut decoder = PrintfDecoder::new(Arc::new(table));
+
+        // Argument word lost, the next record restarts on its count byte
+        let events = decode_packets(
+            &mut decoder,
+            &[&[2], &[0x10, 0, 0, 0], &[21, 0, 0, 0], &[0], &[2, 0, 0, 0]],
+        );
+        assert!(matches!(&events[..], [TraceEvent::Text { message }] if message == "boot"));
+
+        let events = decode_packets(
+            &mut decoder,
+            &[&[2], &[0x10, 0, 0, 0], &[21, 0, 0, 0], &[5, 0, 0, 0]],
+        );
+        assert!(matches!(&events[..], [TraceEvent::Text { message }] if message == "temp=21.5 C"));
+
+        assert!(PrintfTable::parse_dictionary(r#"{"zz": "bad"}"#).is_err());
+    }
+
+    #[test]
+    fn test_text_utf8_split_across_packets() {
+        let mut decoder = TextDecoder::new();
+        let bytes = "temp 21°C\n".as_bytes();
+        let split = bytes.iter().position(|&b| b == 0xC2).unwrap() + 1;
+
+        let events = decode_packets(&mut decoder, &[&bytes[..split], &bytes[split..]]);
+        assert!(matches!(&events[..], [TraceEvent::Text { message }] if message == "temp 21°C"));
+    }
+
+    #[test]
+    fn test_text_level_prefixes_and_ansi() {
+        let mut decoder = TextDecoder::new();
+        let events = decode_packets(
+            &mut decoder,
+            &[b"[ERROR] disk full\r\nW: low battery\n<7>probe\n\x1b[33mhot\x1b[0m\nratio: 3\n"],
+        );
+
+        let levels: Vec<_> = events
+            .iter()
+            .map(|event| match event {
+                TraceEvent::Log { level, message, .. } => (*level, message.as_str()),
+                TraceEvent::Text { message } => (None, message.as_str()),
+                other => panic!("unexpected event {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            levels,
+            [
+                (Some(LogLevel::Error), "disk full"),
+                (Some(LogLevel::Warn), "low battery"),
+                (Some(LogLevel::Debug), "probe"),
+                (Some(LogLevel::Warn), "hot"),
+                (None, "ratio: 3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_text_partial_line_flushed_when_idle() {
+        let mut decoder = TextDecoder::with_idle_timeout(Duration::from_millis(50));
+        assert!(decode_packets(&mut decoder, &[b"prompt> "]).is_empty());
+
+        let now = Instant::now();
+        assert!(decoder.flush_idle(now).is_empty());
+        let events = decoder.flush_idle(now + Duration::from_millis(50));
+        assert!(matches!(&events[..], [TraceEvent::Text { message }] if message == "prompt> "));
+        assert!(decoder.flush_idle(now + Duration::from_secs(1)).is_empty());
+    }
+
+    #[test]
+    fn test_task_name_from_chunks() {
+        let mut decoder = TaskIsrDecoder::new();
+        let mut packets: Vec<Vec<u8>> = Vec::new();
+        for chunk in [*b"sens", *b"or\0\0"] {
+            packets.push(vec![rtos_events::TASK_NAME]);
+            packets.push(3u32.to_le_bytes().to_vec());
+            packets.push(chunk.to_vec());
+        }
+        let packets: Vec<&[u8]> = packets.iter().map(Vec::as_slice).collect();
+
+        let events = decode_packets(&mut decoder, &packets);
+        assert!(matches!(
+            &events[..],
+            [TraceEvent::TaskRename { task_id: 3, name }] if name == "sensor"
+        ));
+    }
+
+    #[test]
+    fn test_markers_and_spans() {
+        let mut decoder = MarkerDecoder::new();
+        let events = decode_packets(
+            &mut decoder,
+            &[&[7, 0, 0, 0], &[span_events::SPAN_BEGIN], &[9, 0, 0, 0], &[span_events::SPAN_END, 9, 0, 0, 0]],
+        );
+        assert!(matches!(
+            events[..],
+            [
+                TraceEvent::Marker { id: 7, .. },
+                TraceEvent::SpanBegin { span_id: 9, .. },
+                TraceEvent::SpanEnd { span_id: 9, .. },
+            ]
+        ));
+    }
+}
@@ -0,0 +1,982 @@
+//! defmt log frame decoding
+//!
+//! defmt firmware sends only the index of an interned format string and
+//! the raw argument bytes. The format strings live in the `.defmt` section
+//! of the firmware ELF, as symbols whose names are JSON descriptions and
+//! whose addresses are the indices.
+
+use crate::decoder::ItmDecoder;
+use anyhow::{bail, Context, Result};
+use callisto_protocol::{LogLevel, TraceEvent};
+use defmt_parser::{DisplayHint, Fragment, Parameter, ParserMode, TimePrecision, Type};
+use object::{Object, ObjectSection, ObjectSymbol};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+/// Format tag of `Format` impls written with `write!`
+const FORMAT_SEQUENCE: &str = "{=__internal_FormatSequence}";
+
+/// Deepest nesting of `Format` values accepted in one frame
+const MAX_NESTING: usize = 32;
+
+/// Most bytes buffered while waiting for the end of a frame
+const MAX_FRAME_SIZE: usize = 16 * 1024;
+
+/// Wire encoding of defmt frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefmtEncoding {
+    /// Frames back to back with no delimiter
+    Raw,
+    /// Reverse zero-compressed COBS, frames terminated by 0x00
+    Rzcobs,
+}
+
+/// Where a log statement is in the firmware sources
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: u32,
+    pub module_path: String,
+}
+
+/// One interned string from the `.defmt` section
+#[derive(Debug, Clone)]
+pub struct DefmtEntry {
+    /// Symbol tag, e.g. `defmt_info` or `defmt_derived`
+    pub tag: String,
+    pub format: String,
+    pub location: Option<Location>,
+}
+
+impl DefmtEntry {
+    /// Log level for log statement entries
+    pub fn level(&self) -> Option<LogLevel> {
+        Some(match self.tag.as_str() {
+            "defmt_trace" => LogLevel::Trace,
+            "defmt_debug" => LogLevel::Debug,
+            "defmt_info" => LogLevel::Info,
+            "defmt_warn" => LogLevel::Warn,
+            "defmt_error" => LogLevel::Error,
+            _ => return None,
+        })
+    }
+}
+
+/// Symbol name of an interned string
+#[derive(Deserialize)]
+struct SymbolName {
+    tag: String,
+    data: String,
+}
+
+/// Interned strings of a defmt firmware
+#[derive(Debug, Clone)]
+pub struct DefmtTable {
+    encoding: DefmtEncoding,
+    entries: HashMap<u16, DefmtEntry>,
+    timestamp: Option<String>,
+}
+
+impl DefmtTable {
+    pub fn new(encoding: DefmtEncoding) -> Self {
+        Self {
+            encoding,
+            entries: HashMap::new(),
+            timestamp: None,
+        }
+    }
+
+    /// Load the table from a firmware ELF file
+    ///
+    /// Returns `None` if the ELF was not built with defmt.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let data =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse_elf(&data)
+    }
+
+    /// Parse the table from ELF file contents
+    pub fn parse_elf(data: &[u8]) -> Result<Option<Self>> {
+        let file = object::File::parse(data).context("Not a valid ELF file")?;
+        let Some(section) = file.section_by_name(".defmt") else {
+            return Ok(None);
+        };
+
+        let mut encoding = None;
+        let mut table = Self::new(DefmtEncoding::Rzcobs);
+        let mut indices = HashMap::new();
+
+        for symbol in file.symbols() {
+            if symbol.section_index() != Some(section.index()) {
+                continue;
+            }
+            let Ok(name) = symbol.name() else { continue };
+
+            match name {
+                "_defmt_encoding_ = raw" => encoding = Some(DefmtEncoding::Raw),
+                "_defmt_encoding_ = rzcobs" => encoding = Some(DefmtEncoding::Rzcobs),
+                _ => {}
+            }
+            let Ok(parsed) = serde_json::from_str::<SymbolName>(name) else {
+                continue;
+            };
+            let Ok(index) = u16::try_from(symbol.address()) else {
+                bail!("defmt symbol address 0x{:x} out of range", symbol.address());
+            };
+
+            if parsed.tag == "defmt_timestamp" {
+                table.timestamp = Some(parsed.data.clone());
+            }
+            table.insert(index, &parsed.tag, &parsed.data);
+            indices.insert(name.to_string(), index);
+        }
+
+        match encoding {
+            Some(encoding) => table.encoding = encoding,
+            None => bail!("ELF has a .defmt section but no defmt encoding marker"),
+        }
+
+        match read_locations(&file, &indices) {
+            Ok(locations) => {
+                for (index, location) in locations {
+                    if let Some(entry) = table.entries.get_mut(&index) {
+                        entry.location = Some(location);
+                    }
+                }
+            }
+            Err(e) => warn!("No defmt source locations: {}", e),
+        }
+
+        info!(
+            "Loaded {} defmt strings ({:?} encoding)",
+            table.entries.len(),
+            table.encoding
+        );
+        Ok(Some(table))
+    }
+
+    pub fn encoding(&self) -> DefmtEncoding {
+        self.encoding
+    }
+
+    /// Add an interned string
+    pub fn insert(&mut self, index: u16, tag: &str, format: &str) {
+        self.entries.insert(
+            index,
+            DefmtEntry {
+                tag: tag.to_string(),
+                format: format.to_string(),
+                location: None,
+            },
+        );
+    }
+
+    /// Set the format of the timestamp sent with every log frame
+    pub fn set_timestamp(&mut self, format: Option<&str>) {
+        self.timestamp = format.map(str::to_string);
+    }
+
+    pub fn get(&self, index: u16) -> Option<&DefmtEntry> {
+        self.entries.get(&index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Decode one log frame from the start of `bytes`
+    ///
+    /// Returns the event and the number of bytes it used.
+    fn decode_frame(&self, bytes: &[u8]) -> Result<(TraceEvent, usize), FrameError> {
+        let mut reader = Reader::new(bytes);
+        let index = reader.u16()?;
+        let entry = self
+            .get(index)
+            .ok_or_else(|| FrameError::Invalid(format!("unknown string index {}", index)))?;
+
+        let device_time = match &self.timestamp {
+            Some(format) => Some(self.format(format, false, &mut reader, 0)?),
+            None => None,
+        };
+        let message = self.format(&entry.format, false, &mut reader, 0)?;
+        let location = entry.location.as_ref();
+
+        let event = TraceEvent::Log {
+            level: entry.level(),
+            message,
+            module_path: location.map(|l| l.module_path.clone()),
+            file: location.map(|l| l.file.clone()),
+            line: location.map(|l| l.line),
+            device_time,
+        };
+        Ok((event, reader.pos))
+    }
+
+    /// Decode the arguments of a format string and render it
+    fn format(
+        &self,
+        format: &str,
+        derived: bool,
+        reader: &mut Reader,
+        depth: usize,
+    ) -> Result<String, FrameError> {
+        if depth > MAX_NESTING {
+            return Err(FrameError::Invalid(
+                "Format values nested too deeply".into(),
+            ));
+        }
+
+        // Derived enums list all variants, and send the variant index first
+        let variants = if derived {
+            split_variants(format)
+        } else {
+            vec![format]
+        };
+        let format = if variants.len() > 1 {
+            let discriminant = match variants.len() {
+                0..=0xFF => reader.uint(1)?,
+                0x100..=0xFFFF => reader.uint(2)?,
+                _ => reader.uint(4)?,
+            } as usize;
+            variants.get(discriminant).copied().ok_or_else(|| {
+                FrameError::Invalid(format!("enum variant {} out of range", discriminant))
+            })?
+        } else {
+            format
+        };
+
+        let fragments = defmt_parser::parse(format, ParserMode::ForwardsCompatible)
+            .map_err(|e| FrameError::Invalid(format!("bad format string {:?}: {}", format, e)))?;
+        let params: Vec<&Parameter> = fragments
+            .iter()
+            .filter_map(|fragment| match fragment {
+                Fragment::Parameter(param) => Some(param),
+                Fragment::Literal(_) => None,
+            })
+            .collect();
+
+        // Arguments are sent once each, in argument order
+        let count = params
+            .iter()
+            .map(|param| param.index + 1)
+            .max()
+            .unwrap_or(0);
+        let mut args = Vec::with_capacity(count);
+        for index in 0..count {
+            let uses = params.iter().copied().filter(|param| param.index == index);
+            let Some(first) = uses.clone().next() else {
+                return Err(FrameError::Invalid(format!("argument {} unused", index)));
+            };
+            let arg = match first.ty {
+                Type::BitField(_) => {
+                    let (start, end) = defmt_parser::get_max_bitfield_range(uses).unwrap_or((0, 8));
+                    let lowest = start / 8;
+                    let highest = end.saturating_sub(1) / 8;
+                    let size = match highest - lowest + 1 {
+                        1 => 1,
+                        2 => 2,
+                        3..=4 => 4,
+                        5..=8 => 8,
+                        _ => 16,
+                    };
+                    Arg::Uint(reader.uint(size)? << (lowest * 8))
+                }
+                ref ty => self.read_arg(ty, reader, depth)?,
+            };
+            args.push(arg);
+        }
+
+        let mut output = String::new();
+        for fragment in &fragments {
+            match fragment {
+                Fragment::Literal(text) => output.push_str(text),
+                Fragment::Parameter(param) => {
+                    output.push_str(&render(&args[param.index], &param.ty, param.hint.as_ref()))
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    fn read_arg(&self, ty: &Type, reader: &mut Reader, depth: usize) -> Result<Arg, FrameError> {
+        Ok(match ty {
+            Type::U8 => Arg::Uint(reader.uint(1)?),
+            Type::U16 => Arg::Uint(reader.uint(2)?),
+            Type::U32 | Type::Usize => Arg::Uint(reader.uint(4)?),
+            Type::U64 => Arg::Uint(reader.uint(8)?),
+            Type::U128 => Arg::Uint(reader.uint(16)?),
+            Type::I8 => Arg::Int(reader.uint(1)? as u8 as i8 as i128),
+            Type::I16 => Arg::Int(reader.uint(2)? as u16 as i16 as i128),
+            Type::I32 | Type::Isize => Arg::Int(reader.uint(4)? as u32 as i32 as i128),
+            Type::I64 => Arg::Int(reader.uint(8)? as u64 as i64 as i128),
+            Type::I128 => Arg::Int(reader.uint(16)? as i128),
+            Type::F32 => Arg::Float(f32::from_bits(reader.uint(4)? as u32) as f64),
+            Type::F64 => Arg::Float(f64::from_bits(reader.uint(8)? as u64)),
+            Type::Bool => Arg::Bool(reader.uint(1)? != 0),
+            Type::Char => {
+                let value = reader.uint(4)? as u32;
+                Arg::Char(
+                    char::from_u32(value).ok_or_else(|| {
+                        FrameError::Invalid(format!("invalid char 0x{:x}", value))
+                    })?,
+                )
+            }
+            Type::Str => {
+                let len = reader.uint(4)? as usize;
+                Arg::Str(String::from_utf8_lossy(reader.take(len)?).into_owned())
+            }
+            Type::IStr => {
+                let index = reader.u16()?;
+                let entry = self.get(index).ok_or_else(|| {
+                    FrameError::Invalid(format!("unknown string index {}", index))
+                })?;
+                Arg::Str(entry.format.clone())
+            }
+            Type::Debug | Type::Display => Arg::Str(reader.until_terminator()?),
+            Type::U8Slice => {
+                let len = reader.uint(4)? as usize;
+                Arg::Bytes(reader.take(len)?.to_vec())
+            }
+            Type::U8Array(len) => Arg::Bytes(reader.take(*len)?.to_vec()),
+            Type::Format => {
+                let index = reader.u16()?;
+                Arg::Formatted(self.format_value(index, reader, depth + 1)?)
+            }
+            Type::FormatSlice => {
+                let len = reader.uint(4)? as usize;
+                // Every element but a zero-sized one takes at least a byte;
+                // a longer length is unsent data or a corrupt prefix
+                if len > reader.remaining() {
+                    return Err(FrameError::Incomplete);
+                }
+                let index = reader.u16()?;
+                Arg::List(self.format_values(index, len, reader, depth)?)
+            }
+            Type::FormatArray(len) => {
+                let index = reader.u16()?;
+                Arg::List(self.format_values(index, *len, reader, depth)?)
+            }
+            Type::FormatSequence => Arg::Formatted(self.format_sequence(reader, depth + 1)?),
+            Type::BitField(_) => unreachable!("bitfields are read by format()"),
+        })
+    }
+
+    /// Render the data of a `Format` value whose format string is `index`
+    fn format_value(
+        &self,
+        index: u16,
+        reader: &mut Reader,
+        depth: usize,
+    ) -> Result<String, FrameError> {
+        let entry = self
+            .get(index)
+            .ok_or_else(|| FrameError::Invalid(format!("unknown string index {}", index)))?;
+        if entry.format == FORMAT_SEQUENCE {
+            self.format_sequence(reader, depth)
+        } else {
+            self.format(&entry.format, entry.tag == "defmt_derived", reader, depth)
+        }
+    }
+
+    fn format_values(
+        &self,
+        index: u16,
+        len: usize,
+        reader: &mut Reader,
+        depth: usize,
+    ) -> Result<Vec<String>, FrameError> {
+        (0..len)
+            .map(|_| self.format_value(index, reader, depth + 1))
+            .collect()
+    }
+
+    /// Render `write!` output: format string indices and their arguments,
+    /// terminated by index 0
+    fn format_sequence(&self, reader: &mut Reader, depth: usize) -> Result<String, FrameError> {
+        let mut output = String::new();
+        loop {
+            let index = reader.u16()?;
+            if index == 0 {
+                return Ok(output);
+            }
+            output.push_str(&self.format_value(index, reader, depth + 1)?);
+        }
+    }
+}
+
+/// Split a derived enum format into its variants at top-level `|`
+fn split_variants(format: &str) -> Vec<&str> {
+    let mut variants = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let bytes = format.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' if bytes.get(i + 1) == Some(&b'{') => i += 1,
+            b'}' if depth == 0 && bytes.get(i + 1) == Some(&b'}') => i += 1,
+            b'{' => depth += 1,
+            b'}' => depth = depth.saturating_sub(1),
+            b'|' if depth == 0 => {
+                variants.push(&format[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    variants.push(&format[start..]);
+    variants
+}
+
+/// Decoded argument value
+enum Arg {
+    Uint(u128),
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Str(String),
+    Bytes(Vec<u8>),
+    Formatted(String),
+    List(Vec<String>),
+}
+
+fn render(arg: &Arg, ty: &Type, hint: Option<&DisplayHint>) -> String {
+    let debug = matches!(hint, Some(DisplayHint::Debug));
+    match arg {
+        Arg::Uint(value) => {
+            let value = match ty {
+                Type::BitField(range) => {
+                    let width = u32::from(range.end - range.start);
+                    let mask = if width >= 128 {
+                        u128::MAX
+                    } else {
+                        (1u128 << width) - 1
+                    };
+                    (value >> range.start) & mask
+                }
+                _ => *value,
+            };
+            render_uint(value, hint)
+        }
+        Arg::Int(value) => match hint {
+            Some(DisplayHint::NoHint { zero_pad }) => format!("{:0w$}", value, w = zero_pad),
+            // Hex, octal and binary show the two's complement bits, like Rust
+            Some(
+                DisplayHint::Hexadecimal { .. }
+                | DisplayHint::Octal { .. }
+                | DisplayHint::Binary { .. },
+            ) => render_uint(twos_complement(*value, ty), hint),
+            _ => value.to_string(),
+        },
+        Arg::Float(value) => value.to_string(),
+        Arg::Bool(value) => value.to_string(),
+        Arg::Char(value) if debug => format!("{:?}", value),
+        Arg::Char(value) => value.to_string(),
+        Arg::Str(value) if debug => format!("{:?}", value),
+        Arg::Str(value) | Arg::Formatted(value) => value.clone(),
+        Arg::Bytes(bytes) => match hint {
+            Some(DisplayHint::Ascii) => {
+                let escaped: String = bytes
+                    .iter()
+                    .flat_map(|&byte| std::ascii::escape_default(byte))
+                    .map(char::from)
+                    .collect();
+                format!("b\"{}\"", escaped)
+            }
+            _ => {
+                let items: Vec<String> = bytes
+                    .iter()
+                    .map(|&byte| render_uint(byte as u128, hint))
+                    .collect();
+                format!("[{}]", items.join(", "))
+            }
+        },
+        Arg::List(items) => format!("[{}]", items.join(", ")),
+    }
+}
+
+fn render_uint(value: u128, hint: Option<&DisplayHint>) -> String {
+    match hint {
+        Some(DisplayHint::NoHint { zero_pad }) => format!("{:0w$}", value, w = zero_pad),
+        Some(DisplayHint::Hexadecimal {
+            alternate,
+            uppercase,
+            zero_pad,
+        }) => match (alternate, uppercase) {
+            (false, false) => format!("{:0w$x}", value, w = zero_pad),
+            (false, true) => format!("{:0w$X}", value, w = zero_pad),
+            (true, false) => format!("{:#0w$x}", value, w = zero_pad),
+            (true, true) => format!("{:#0w$X}", value, w = zero_pad),
+        },
+        Some(DisplayHint::Octal {
+            alternate,
+            zero_pad,
+        }) => match alternate {
+            false => format!("{:0w$o}", value, w = zero_pad),
+            true => format!("{:#0w$o}", value, w = zero_pad),
+        },
+        Some(DisplayHint::Binary {
+            alternate,
+            zero_pad,
+        }) => match alternate {
+            false => format!("{:0w$b}", value, w = zero_pad),
+            true => format!("{:#0w$b}", value, w = zero_pad),
+        },
+        Some(DisplayHint::Seconds(precision) | DisplayHint::Time(precision)) => match precision {
+            TimePrecision::Micros => {
+                format!("{}.{:06}", value / 1_000_000, value % 1_000_000)
+            }
+            TimePrecision::Millis => format!("{}.{:03}", value / 1_000, value % 1_000),
+            TimePrecision::Seconds => value.to_string(),
+        },
+        _ => value.to_string(),
+    }
+}
+
+/// Bits of a signed value at its declared width
+fn twos_complement(value: i128, ty: &Type) -> u128 {
+    let bits = match ty {
+        Type::I8 => 8,
+        Type::I16 => 16,
+        Type::I32 | Type::Isize => 32,
+        Type::I64 => 64,
+        _ => 128,
+    };
+    if bits == 128 {
+        value as u128
+    } else {
+        (value as u128) & ((1u128 << bits) - 1)
+    }
+}
+
+/// Why a frame could not be decoded
+#[derive(Debug)]
+enum FrameError {
+    /// More bytes are needed
+    Incomplete,
+    /// The frame does not match the table
+    Invalid(String),
+}
+
+/// Little-endian cursor over frame bytes
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FrameError> {
+        let end = self.pos.checked_add(len).ok_or(FrameError::Incomplete)?;
+        let bytes = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or(FrameError::Incomplete)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn uint(&mut self, size: usize) -> Result<u128, FrameError> {
+        let mut raw = [0u8; 16];
+        raw[..size].copy_from_slice(self.take(size)?);
+        Ok(u128::from_le_bytes(raw))
+    }
+
+    fn u16(&mut self) -> Result<u16, FrameError> {
+        Ok(self.uint(2)? as u16)
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    /// `Debug`/`Display` output, terminated by 0xFF
+    fn until_terminator(&mut self) -> Result<String, FrameError> {
+        let rest = &self.bytes[self.pos..];
+        let len = rest
+            .iter()
+            .position(|&byte| byte == 0xFF)
+            .ok_or(FrameError::Incomplete)?;
+        self.pos += len + 1;
+        Ok(String::from_utf8_lossy(&rest[..len]).into_owned())
+    }
+}
+
+/// Undo rzCOBS encoding of one frame (without its 0x00 terminator)
+///
+/// The result may carry a few trailing zero bytes of padding, which frame
+/// decoding ignores.
+pub fn rzcobs_decode(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(encoded.len() * 8 / 7);
+    let mut input = encoded.iter().rev().copied();
+
+    while let Some(code) = input.next() {
+        match code {
+            0x00 => return None,
+            0x01..=0x7F => {
+                for bit in (0..7).rev() {
+                    if code & (1 << bit) == 0 {
+                        output.push(input.next()?);
+                    } else {
+                        output.push(0);
+                    }
+                }
+            }
+            0x80..=0xFE => {
+                output.push(0);
+                for _ in 0..(code & 0x7F) + 7 {
+                    output.push(input.next()?);
+                }
+            }
+            0xFF => {
+                for _ in 0..134 {
+                    output.push(input.next()?);
+                }
+            }
+        }
+    }
+
+    output.reverse();
+    Some(output)
+}
+
+/// Map defmt symbols to source locations using DWARF debug info
+fn read_locations(
+    file: &object::File,
+    indices: &HashMap<String, u16>,
+) -> Result<HashMap<u16, Location>> {
+    let endian = if file.is_little_endian() {
+        gimli::RunTimeEndian::Little
+    } else {
+        gimli::RunTimeEndian::Big
+    };
+    let load_section = |id: gimli::SectionId| -> Result<Cow<[u8]>, gimli::Error> {
+        Ok(file
+            .section_by_name(id.name())
+            .and_then(|section| section.uncompressed_data().ok())
+            .unwrap_or(Cow::Borrowed(&[])))
+    };
+    let sections = gimli::DwarfSections::load(load_section)?;
+    let dwarf = sections.borrow(|section| gimli::EndianSlice::new(section, endian));
+
+    let mut locations = HashMap::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let mut namespaces: Vec<(isize, String)> = Vec::new();
+        let mut depth = 0;
+        let mut entries = unit.entries();
+
+        while let Some((delta, entry)) = entries.next_dfs()? {
+            depth += delta;
+            while namespaces.last().is_some_and(|(level, _)| *level >= depth) {
+                namespaces.pop();
+            }
+
+            match entry.tag() {
+                gimli::DW_TAG_namespace => {
+                    if let Some(name) = entry.attr_value(gimli::DW_AT_name)? {
+                        let name = dwarf.attr_string(&unit, name)?;
+                        namespaces.push((depth, name.to_string_lossy().into_owned()));
+                    }
+                }
+                gimli::DW_TAG_variable => {
+                    let Some(linkage) = entry.attr_value(gimli::DW_AT_linkage_name)? else {
+                        continue;
+                    };
+                    let linkage = dwarf.attr_string(&unit, linkage)?;
+                    let Some(&index) = indices.get(&*linkage.to_string_lossy()) else {
+                        continue;
+                    };
+
+                    let file_index = match entry.attr_value(gimli::DW_AT_decl_file)? {
+                        Some(gimli::AttributeValue::FileIndex(index)) => index,
+                        Some(value) => value.udata_value().unwrap_or(0),
+                        None => continue,
+                    };
+                    let line = entry
+                        .attr_value(gimli::DW_AT_decl_line)?
+                        .and_then(|value| value.udata_value())
+                        .unwrap_or(0);
+                    let Some(path) = file_path(&dwarf, &unit, file_index) else {
+                        continue;
+                    };
+
+                    let module_path: Vec<&str> =
+                        namespaces.iter().map(|(_, name)| name.as_str()).collect();
+                    locations.insert(
+                        index,
+                        Location {
+                            file: path,
+                            line: line as u32,
+                            module_path: module_path.join("::"),
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+
+    debug!(
+        "Found source locations for {} defmt strings",
+        locations.len()
+    );
+    Ok(locations)
+}
+
+type DwarfReader<'a> = gimli::EndianSlice<'a, gimli::RunTimeEndian>;
+
+fn file_path(
+    dwarf: &gimli::Dwarf<DwarfReader>,
+    unit: &gimli::Unit<DwarfReader>,
+    index: u64,
+) -> Option<String> {
+    let header = unit.line_program.as_ref()?.header();
+    let file = header.file(index)?;
+    let name = dwarf.attr_string(unit, file.path_name()).ok()?;
+    let mut path = std::path::PathBuf::new();
+    if let Some(directory) = file.directory(header) {
+        let directory = dwarf.attr_string(unit, directory).ok()?;
+        path.push(&*directory.to_string_lossy());
+    }
+    path.push(&*name.to_string_lossy());
+    Some(path.display().to_string())
+}
+
+/// Decoder for defmt log frames on a stimulus port
+///
+/// defmt-itm writes with whatever access sizes fit, so the port payload
+/// is treated as a byte stream.
+pub struct DefmtDecoder {
+    table: Arc<DefmtTable>,
+    buffer: Vec<u8>,
+}
+
+impl DefmtDecoder {
+    pub fn new(table: Arc<DefmtTable>) -> Self {
+        Self {
+            table,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn decode_rzcobs(&mut self, events: &mut Vec<TraceEvent>) {
+        while let Some(end) = self.buffer.iter().position(|&byte| byte == 0) {
+            let frame: Vec<u8> = self.buffer.drain(..=end).collect();
+            let frame = &frame[..end];
+            if frame.is_empty() {
+                continue;
+            }
+
+            let decoded = rzcobs_decode(frame)
+                .ok_or_else(|| FrameError::Invalid("bad rzCOBS encoding".into()))
+                .and_then(|decoded| self.table.decode_frame(&decoded));
+            match decoded {
+                Ok((event, _)) => events.push(event),
+                Err(e) => warn!("Dropping defmt frame: {:?}", e),
+            }
+        }
+    }
+
+    fn decode_raw(&mut self, events: &mut Vec<TraceEvent>) {
+        while !self.buffer.is_empty() {
+            match self.table.decode_frame(&self.buffer) {
+                Ok((event, used)) => {
+                    self.buffer.drain(..used);
+                    events.push(event);
+                }
+                Err(FrameError::Incomplete) => break,
+                Err(FrameError::Invalid(reason)) => {
+                    // Raw frames have no delimiter to resynchronize on
+                    warn!(
+                        "Dropping {} byte(s) of defmt data: {}",
+                        self.buffer.len(),
+                        reason
+                    );
+                    self.buffer.clear();
+                }
+            }
+        }
+    }
+}
+
+impl ItmDecoder for DefmtDecoder {
+    fn decode(&mut self, _port: u8, data: &[u8], _timestamp: u64) -> Result<Vec<TraceEvent>> {
+        self.buffer.extend_from_slice(data);
+
+        let mut events = Vec::new();
+        match self.table.encoding() {
+            DefmtEncoding::Rzcobs => self.decode_rzcobs(&mut events),
+            DefmtEncoding::Raw => self.decode_raw(&mut events),
+        }
+        if self.buffer.len() > MAX_FRAME_SIZE {
+            // A corrupt length prefix or a lost 0x00 would otherwise keep
+            // the buffer growing
+            warn!(
+                "Dropping {} byte(s) of defmt data: no frame end found",
+                self.buffer.len()
+            );
+            self.buffer.clear();
+        }
+        Ok(events)
+    }
+
+    fn reset(&mut self) {
+        self.buffer.clear();
+    }
+
+    fn discontinuity(&mut self) {
+        match self.table.encoding() {
+            // The next 0x00 ends the damaged frame, and decoding of that
+            // frame fails on its own
+            DefmtEncoding::Rzcobs => {}
+            DefmtEncoding::Raw => self.buffer.clear(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(encoding: DefmtEncoding) -> Arc<DefmtTable> {
+        let mut table = DefmtTable::new(encoding);
+        table.insert(1, "defmt_info", "temp={=i16} ok={=bool} {}");
+        table.insert(2, "defmt_derived", "Idle|Busy({=u8:#x})");
+        table.insert(3, "defmt_timestamp", "{=u32:ms}");
+        table.insert(4, "defmt_warn", "{=str} {=[u8]:a} {=0..4} {=[?]}");
+        table.set_timestamp(Some("{=u32:ms}"));
+        Arc::new(table)
+    }
+
+    #[test]
+    fn test_decode_raw_frames() {
+        let mut decoder = DefmtDecoder::new(table(DefmtEncoding::Raw));
+        let mut frame = vec![1, 0];
+        frame.extend_from_slice(&1500u32.to_le_bytes());
+        frame.extend_from_slice(&(-5i16).to_le_bytes());
+        frame.extend_from_slice(&[1, 2, 0, 1, 0x2A]);
+
+        // Split mid-frame, like separate stimulus writes
+        assert!(decoder.decode(0, &frame[..5], 0).unwrap().is_empty());
+        let events = decoder.decode(0, &frame[5..], 0).unwrap();
+
+        let TraceEvent::Log {
+            level,
+            message,
+            device_time,
+            ..
+        } = &events[0]
+        else {
+            panic!("expected a log event, got {:?}", events);
+        };
+        assert_eq!(*level, Some(LogLevel::Info));
+        assert_eq!(message, "temp=-5 ok=true Busy(0x2a)");
+        assert_eq!(device_time.as_deref(), Some("1.500"));
+    }
+
+    #[test]
+    fn test_decode_strings_slices_and_bitfields() {
+        let table = table(DefmtEncoding::Raw);
+        let mut frame = vec![4, 0, 0, 0, 0, 0];
+        frame.extend_from_slice(&2u32.to_le_bytes());
+        frame.extend_from_slice(b"hi");
+        frame.extend_from_slice(&2u32.to_le_bytes());
+        frame.extend_from_slice(b"ok");
+        frame.push(0b1010_0110);
+        frame.extend_from_slice(&2u32.to_le_bytes());
+        frame.extend_from_slice(&[2, 0, 0, 1, 7]);
+
+        let (event, used) = table.decode_frame(&frame).unwrap();
+        assert_eq!(used, frame.len());
+        assert!(matches!(
+            event,
+            TraceEvent::Log { message, level: Some(LogLevel::Warn), .. }
+                if message == "hi b\"ok\" 6 [Idle, Busy(0x7)]"
+        ));
+    }
+
+    #[test]
+    fn test_rzcobs_frames() {
+        assert_eq!(rzcobs_decode(&[0x01, 0x7e]).unwrap()[0], 0x01);
+        assert_eq!(
+            rzcobs_decode(&[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x81]).unwrap()[..8],
+            [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]
+        );
+
+        let mut table = DefmtTable::new(DefmtEncoding::Rzcobs);
+        table.insert(5, "defmt_println", "{=u8}");
+        let mut decoder = DefmtDecoder::new(Arc::new(table));
+
+        // Frame separator, an undecodable frame, then [5, 0, 7]
+        let events = decoder
+            .decode(0, &[0x00, 0x7f, 0x00, 0x05, 0x07, 0x7a, 0x00], 0)
+            .unwrap();
+        assert!(matches!(
+            &events[..],
+            [TraceEvent::Log { level: None, message, .. }] if message == "7"
+        ));
+    }
+
+    #[test]
+    fn test_slice_length_is_capped_by_the_frame() {
+        let mut table = DefmtTable::new(DefmtEncoding::Rzcobs);
+        table.insert(1, "defmt_info", "{=[?]}");
+        table.insert(2, "defmt_derived", "Unit");
+        let mut frame = vec![1, 0];
+        frame.extend_from_slice(&u32::MAX.to_le_bytes());
+        frame.extend_from_slice(&[2, 0]);
+        assert!(matches!(
+            table.decode_frame(&frame),
+            Err(FrameError::Incomplete)
+        ));
+    }
+
+    #[test]
+    fn test_raw_buffer_is_capped_after_a_bad_length() {
+        let mut table = DefmtTable::new(DefmtEncoding::Raw);
+        table.insert(1, "defmt_info", "{=[u8]}");
+        let mut decoder = DefmtDecoder::new(Arc::new(table));
+
+        let mut data = vec![1, 0];
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decoder.decode(0, &data, 0).unwrap().is_empty());
+        for _ in 0..(MAX_FRAME_SIZE / 1024 + 1) {
+            assert!(decoder.decode(0, &[0xAA; 1024], 0).unwrap().is_empty());
+            assert!(decoder.buffer.len() <= MAX_FRAME_SIZE);
+        }
+        assert!(decoder.buffer.len() < 1024);
+    }
+
+    #[test]
+    fn test_rzcobs_buffer_is_capped_without_a_terminator() {
+        let mut table = DefmtTable::new(DefmtEncoding::Rzcobs);
+        table.insert(5, "defmt_println", "{=u8}");
+        let mut decoder = DefmtDecoder::new(Arc::new(table));
+
+        for _ in 0..(MAX_FRAME_SIZE / 1024 + 1) {
+            decoder.decode(0, &[0x7f; 1024], 0).unwrap();
+            assert!(decoder.buffer.len() <= MAX_FRAME_SIZE);
+        }
+        // The damaged frame ends at the next 0x00 and decoding resumes
+        let events = decoder
+            .decode(0, &[0x00, 0x05, 0x07, 0x7a, 0x00], 0)
+            .unwrap();
+        assert_eq!(events.len(), 1);
+    }
+}
@@ -0,0 +1,384 @@
+//! DWT hardware source packet decoding
+
+use callisto_protocol::{DataAccess, TraceEvent};
+
+/// Hardware source packet discriminator IDs
+pub mod discriminator {
+    /// Event counter overflow packet
+    pub const EVENT_COUNTER: u8 = 0;
+    /// Exception trace packet
+    pub const EXCEPTION_TRACE: u8 = 1;
+    /// Periodic PC sample packet
+    pub const PC_SAMPLE: u8 = 2;
+    /// Data trace PC value (even) or address offset (odd), 0b01NNx
+    pub const DATA_TRACE_ADDRESS: u8 = 8;
+    /// Data trace data value, 0b10NNW with W set for writes
+    pub const DATA_TRACE_VALUE: u8 = 16;
+}
+
+/// Well-known counter IDs for DWT event counters
+///
+/// Chosen from the top of the ID space so they do not collide with
+/// firmware `CountersPort::counter` IDs.
+pub mod counter_id {
+    /// CPI counter: additional cycles spent on multi-cycle instructions
+    pub const CPI: u32 = 0xFFFF_FF00;
+    /// Exception overhead cycles
+    pub const EXC: u32 = 0xFFFF_FF01;
+    /// Cycles spent sleeping
+    pub const SLEEP: u32 = 0xFFFF_FF02;
+    /// Load/store unit extra cycles
+    pub const LSU: u32 = 0xFFFF_FF03;
+    /// Folded instructions
+    pub const FOLD: u32 = 0xFFFF_FF04;
+    /// Cycle counter tap events (POSTCNT underflows)
+    pub const CYC: u32 = 0xFFFF_FF05;
+}
+
+/// Event counter packet bits, in payload bit order: ID, name, and how
+/// much one overflow adds to the running total
+const EVENT_COUNTERS: [(u32, &str, u64); 6] = [
+    (counter_id::CPI, "DWT CPI", 256),
+    (counter_id::EXC, "DWT EXC", 256),
+    (counter_id::SLEEP, "DWT SLEEP", 256),
+    (counter_id::LSU, "DWT LSU", 256),
+    (counter_id::FOLD, "DWT FOLD", 256),
+    (counter_id::CYC, "DWT CYC", 1),
+];
+
+/// Exception trace function field
+const EXCEPTION_ENTERED: u8 = 1;
+const EXCEPTION_EXITED: u8 = 2;
+const EXCEPTION_RETURNED: u8 = 3;
+
+/// Exception number of the first external interrupt
+pub const FIRST_IRQ_EXCEPTION: u32 = 16;
+
+/// Human-readable name of an ARMv7-M exception number
+pub fn exception_name(exception: u32) -> String {
+    match exception {
+        0 => "Thread".to_string(),
+        1 => "Reset".to_string(),
+        2 => "NMI".to_string(),
+        3 => "HardFault".to_string(),
+        4 => "MemManage".to_string(),
+        5 => "BusFault".to_string(),
+        6 => "UsageFault".to_string(),
+        11 => "SVCall".to_string(),
+        12 => "DebugMonitor".to_string(),
+        14 => "PendSV".to_string(),
+        15 => "SysTick".to_string(),
+        n if n >= FIRST_IRQ_EXCEPTION => format!("IRQ{}", n - FIRST_IRQ_EXCEPTION),
+        n => format!("Reserved{}", n),
+    }
+}
+
+/// PC or address packet waiting for the data value of the same access
+struct PendingAccess {
+    comparator: u8,
+    pc: Option<u32>,
+    address_offset: Option<u16>,
+}
+
+impl PendingAccess {
+    fn into_event(self, access: DataAccess, value: Option<u32>) -> TraceEvent {
+        TraceEvent::DataTrace {
+            comparator: self.comparator,
+            access,
+            value,
+            pc: self.pc,
+            address_offset: self.address_offset,
+        }
+    }
+}
+
+/// Decoder for DWT hardware source packets
+pub struct DwtDecoder {
+    pending: Option<PendingAccess>,
+    counter_totals: [u64; 6],
+}
+
+impl DwtDecoder {
+    pub fn new() -> Self {
+        Self {
+            pending: None,
+            counter_totals: [0; 6],
+        }
+    }
+
+    /// Decode one hardware source packet into trace events
+    pub fn decode(&mut self, discriminator: u8, data: &[u8]) -> Vec<TraceEvent> {
+        match discriminator {
+            discriminator::EVENT_COUNTER => {
+                let mut events = self.flush();
+                events.extend(self.decode_event_counter(data));
+                events
+            }
+            discriminator::EXCEPTION_TRACE => self.finish(decode_exception(data)),
+            discriminator::PC_SAMPLE => self.finish(decode_pc_sample(data)),
+            discriminator::DATA_TRACE_ADDRESS..=15 => self.decode_data_address(discriminator, data),
+            discriminator::DATA_TRACE_VALUE..=23 => self.decode_data_value(discriminator, data),
+            _ => self.finish(None),
+        }
+    }
+
+    /// Event counter packet: one bit per counter that wrapped
+    ///
+    /// The CPI, EXC, SLEEP, LSU and FOLD counters are 8 bits wide, so each
+    /// overflow stands for 256 counts. CYC bits are counted as-is.
+    fn decode_event_counter(&mut self, data: &[u8]) -> Vec<TraceEvent> {
+        let Some(&bits) = data.first() else {
+            return vec![];
+        };
+
+        let mut events = Vec::new();
+        for (i, &(counter_id, name, increment)) in EVENT_COUNTERS.iter().enumerate() {
+            if bits & (1 << i) != 0 {
+                self.counter_totals[i] += increment;
+                events.push(TraceEvent::Counter {
+                    counter_id,
+                    value: self.counter_totals[i],
+                    name: Some(name.to_string()),
+                });
+            }
+        }
+        events
+    }
+
+    /// PC value or data address offset packet
+    ///
+    /// The DWT sends these ahead of the data value packet for the same
+    /// access, so they are held until the value arrives.
+    fn decode_data_address(&mut self, discriminator: u8, data: &[u8]) -> Vec<TraceEvent> {
+        let comparator = (discriminator >> 1) & 0x3;
+        let mut events = Vec::new();
+
+        let same_access = matches!(
+            &self.pending,
+            Some(pending) if pending.comparator == comparator
+        );
+        if !same_access {
+            events.extend(self.flush());
+        }
+        let pending = self.pending.get_or_insert(PendingAccess {
+            comparator,
+            pc: None,
+            address_offset: None,
+        });
+
+        match (discriminator & 1, data) {
+            (0, [a, b, c, d]) => pending.pc = Some(u32::from_le_bytes([*a, *b, *c, *d])),
+            (1, [a, b]) => pending.address_offset = Some(u16::from_le_bytes([*a, *b])),
+            _ => {}
+        }
+
+        events
+    }
+
+    /// Data value packet, completing any pending access on the same comparator
+    fn decode_data_value(&mut self, discriminator: u8, data: &[u8]) -> Vec<TraceEvent> {
+        let comparator = (discriminator >> 1) & 0x3;
+        let access = if discriminator & 1 != 0 {
+            DataAccess::Write
+        } else {
+            DataAccess::Read
+        };
+
+        let mut bytes = [0u8; 4];
+        let len = data.len().min(4);
+        bytes[..len].copy_from_slice(&data[..len]);
+        let value = Some(u32::from_le_bytes(bytes));
+
+        let mut events = Vec::new();
+        let pending = match self.pending.take() {
+            Some(pending) if pending.comparator == comparator => pending,
+            other => {
+                events.extend(other.map(|p| p.into_event(DataAccess::Match, None)));
+                PendingAccess {
+                    comparator,
+                    pc: None,
+                    address_offset: None,
+                }
+            }
+        };
+        events.push(pending.into_event(access, value));
+        events
+    }
+
+    /// Emit any pending access before an unrelated event
+    fn finish(&mut self, event: Option<TraceEvent>) -> Vec<TraceEvent> {
+        let mut events = self.flush();
+        events.extend(event);
+        events
+    }
+
+    /// Emit an access that never received a data value
+    pub fn flush(&mut self) -> Vec<TraceEvent> {
+        self.pending
+            .take()
+            .map(|p| p.into_event(DataAccess::Match, None))
+            .into_iter()
+            .collect()
+    }
+
+    pub fn reset(&mut self) {
+        self.pending = None;
+        self.counter_totals = [0; 6];
+    }
+}
+
+impl Default for DwtDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Exception trace: ExceptionNumber[7:0], then FN[5:4] and ExceptionNumber[8]
+fn decode_exception(data: &[u8]) -> Option<TraceEvent> {
+    if data.len() < 2 {
+        return None;
+    }
+
+    let exception = data[0] as u32 | ((data[1] as u32 & 0x1) << 8);
+    match (data[1] >> 4) & 0x3 {
+        EXCEPTION_ENTERED => Some(TraceEvent::ExceptionEnter {
+            exception,
+            name: Some(exception_name(exception)),
+        }),
+        EXCEPTION_EXITED => Some(TraceEvent::ExceptionExit { exception }),
+        EXCEPTION_RETURNED => Some(TraceEvent::ExceptionReturn { exception }),
+        _ => None,
+    }
+}
+
+/// Periodic PC sample: a 4-byte PC, or a single zero byte while sleeping
+fn decode_pc_sample(data: &[u8]) -> Option<TraceEvent> {
+    match data {
+        [a, b, c, d] => Some(TraceEvent::PcSample {
+            pc: Some(u32::from_le_bytes([*a, *b, *c, *d])),
+        }),
+        [0] => Some(TraceEvent::PcSample { pc: None }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exception_names() {
+        assert_eq!(exception_name(3), "HardFault");
+        assert_eq!(exception_name(14), "PendSV");
+        assert_eq!(exception_name(15), "SysTick");
+        assert_eq!(exception_name(16 + 37), "IRQ37");
+    }
+
+    #[test]
+    fn test_exception_trace_decoding() {
+        let mut decoder = DwtDecoder::new();
+
+        let events = decoder.decode(discriminator::EXCEPTION_TRACE, &[15, 0x10]);
+        assert!(matches!(
+            events.as_slice(),
+            [TraceEvent::ExceptionEnter { exception: 15, name: Some(name) }] if name == "SysTick"
+        ));
+
+        // IRQ 240 needs the ninth exception number bit
+        let events = decoder.decode(discriminator::EXCEPTION_TRACE, &[0x00, 0x21]);
+        assert!(matches!(
+            events.as_slice(),
+            [TraceEvent::ExceptionExit { exception: 256 }]
+        ));
+
+        let events = decoder.decode(discriminator::EXCEPTION_TRACE, &[0, 0x30]);
+        assert!(matches!(
+            events.as_slice(),
+            [TraceEvent::ExceptionReturn { exception: 0 }]
+        ));
+    }
+
+    #[test]
+    fn test_pc_sample_decoding() {
+        let mut decoder = DwtDecoder::new();
+
+        let events = decoder.decode(discriminator::PC_SAMPLE, &[0x34, 0x12, 0x00, 0x08]);
+        assert!(matches!(
+            events.as_slice(),
+            [TraceEvent::PcSample {
+                pc: Some(0x0800_1234)
+            }]
+        ));
+
+        let events = decoder.decode(discriminator::PC_SAMPLE, &[0]);
+        assert!(matches!(
+            events.as_slice(),
+            [TraceEvent::PcSample { pc: None }]
+        ));
+    }
+
+    #[test]
+    fn test_data_trace_correlates_pc_and_value() {
+        let mut decoder = DwtDecoder::new();
+
+        // Comparator 1: PC value packet, then a 2-byte write value
+        assert!(decoder.decode(10, &[0x00, 0x10, 0x00, 0x08]).is_empty());
+        let events = decoder.decode(19, &[0x2A, 0x00]);
+        assert!(matches!(
+            events.as_slice(),
+            [TraceEvent::DataTrace {
+                comparator: 1,
+                access: DataAccess::Write,
+                value: Some(42),
+                pc: Some(0x0800_1000),
+                address_offset: None,
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_data_trace_unmatched_address_is_flushed() {
+        let mut decoder = DwtDecoder::new();
+
+        // Comparator 0 address offset, then a read value on comparator 2
+        assert!(decoder.decode(9, &[0x34, 0x12]).is_empty());
+        let events = decoder.decode(20, &[7]);
+        assert!(matches!(
+            events.as_slice(),
+            [
+                TraceEvent::DataTrace {
+                    comparator: 0,
+                    access: DataAccess::Match,
+                    address_offset: Some(0x1234),
+                    ..
+                },
+                TraceEvent::DataTrace {
+                    comparator: 2,
+                    access: DataAccess::Read,
+                    value: Some(7),
+                    ..
+                },
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_event_counters_accumulate() {
+        let mut decoder = DwtDecoder::new();
+
+        // CPI and CYC, then CPI again
+        assert_eq!(
+            decoder.decode(discriminator::EVENT_COUNTER, &[0x21]).len(),
+            2
+        );
+        let events = decoder.decode(discriminator::EVENT_COUNTER, &[0x01]);
+        assert!(matches!(
+            events.as_slice(),
+            [TraceEvent::Counter {
+                counter_id: counter_id::CPI,
+                value: 512,
+                name: Some(_),
+            }]
+        ));
+    }
+}
@@ -0,0 +1,89 @@
+//! Background ingest task
+//!
+//! Reads raw trace bytes from the active source and feeds them through the
+//! session's ITM parser and decoders, so events flow without a client
+//! driving `process_data`.
+
+use crate::source::TraceSource;
+use crate::ItmSession;
+use std::sync::Weak;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// How often the session's periodic work runs (idle text, stats)
+const TICK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Handle to a running ingest task
+pub(crate) struct IngestTask {
+    stop: watch::Sender<bool>,
+    handle: JoinHandle<()>,
+}
+
+impl IngestTask {
+    pub(crate) fn spawn(session: Weak<Mutex<ItmSession>>, source: Box<dyn TraceSource>) -> Self {
+        let (stop, stop_rx) = watch::channel(false);
+        let handle = tokio::spawn(run(session, source, stop_rx));
+        Self { stop, handle }
+    }
+
+    /// Stop the task and wait for it to finish
+    ///
+    /// Safe to call with the session locked: the task gives up waiting
+    /// for the lock as soon as it is told to stop.
+    pub(crate) async fn stop(self) {
+        let _ = self.stop.send(true);
+        if let Err(e) = self.handle.await {
+            warn!("Ingest task failed: {}", e);
+        }
+    }
+}
+
+async fn run(
+    session: Weak<Mutex<ItmSession>>,
+    mut source: Box<dyn TraceSource>,
+    mut stop: watch::Receiver<bool>,
+) {
+    let mut tick = tokio::time::interval(TICK_INTERVAL);
+    debug!("Ingest task started");
+
+    loop {
+        let read = tokio::select! {
+            biased;
+            _ = stop.changed() => break,
+            _ = tick.tick() => None,
+            read = source.read() => Some(read),
+        };
+
+        let Some(shared) = session.upgrade() else {
+            break;
+        };
+        let mut session = tokio::select! {
+            biased;
+            _ = stop.changed() => break,
+            session = shared.lock() => session,
+        };
+
+        match read {
+            None => session.tick(),
+            Some(Ok(None)) => {
+                info!("Trace source {} ended", source.describe());
+                session.flush_packets();
+                break;
+            }
+            Some(Ok(Some(chunk))) => {
+                session.set_source_backlog(source.backlog());
+                if let Err(e) = session.process_chunk(&chunk) {
+                    warn!("Failed to process trace data: {:#}", e);
+                }
+            }
+            Some(Err(e)) => {
+                session.report_source_error(&e);
+                break;
+            }
+        }
+    }
+
+    debug!("Ingest task stopped");
+}
@@ -0,0 +1,315 @@
+//! Interrupt timing
+//!
+//! Pairs interrupt enters and exits on a nesting stack, so a handler
+//! preempted by a higher priority interrupt is timed without the nested
+//! one. Enters and exits come from the firmware's ISR events, or from DWT
+//! exception trace when the target has it enabled; exception trace is
+//! exact, so firmware ISR events are ignored once it is seen.
+
+use crate::dwt::{exception_name, FIRST_IRQ_EXCEPTION};
+use crate::svd::InterruptMap;
+use callisto_protocol::{Histogram, HistogramBucket, IsrTiming, TraceEvent};
+use std::collections::BTreeMap;
+
+/// Buckets per power of two; bucket width is at most 1/4 of its lower bound
+const SUB_BUCKET_BITS: u32 = 2;
+
+/// Histogram of cycle counts with logarithmic buckets
+#[derive(Debug, Clone, Default)]
+pub struct CycleHistogram {
+    count: u64,
+    sum: u128,
+    min: u64,
+    max: u64,
+    /// Count per bucket, keyed by lower bound
+    buckets: BTreeMap<u64, u64>,
+}
+
+impl CycleHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, value: u64) {
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        self.max = self.max.max(value);
+        self.count += 1;
+        self.sum += value as u128;
+        *self.buckets.entry(bucket_bounds(value).0).or_default() += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn to_histogram(&self) -> Histogram {
+        Histogram {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: if self.count == 0 {
+                0.0
+            } else {
+                self.sum as f64 / self.count as f64
+            },
+            buckets: self
+                .buckets
+                .iter()
+                .map(|(&lower, &count)| HistogramBucket {
+                    lower,
+                    upper: bucket_bounds(lower).1,
+                    count,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Bucket holding `value`, as lower bound and exclusive upper bound
+fn bucket_bounds(value: u64) -> (u64, u64) {
+    let bits = u64::BITS - value.leading_zeros();
+    if bits <= SUB_BUCKET_BITS + 1 {
+        return (value, value + 1);
+    }
+    let shift = bits - 1 - SUB_BUCKET_BITS;
+    let lower = value >> shift << shift;
+    (lower, lower.saturating_add(1 << shift))
+}
+
+#[derive(Default)]
+struct IsrStats {
+    duration: CycleHistogram,
+    inter_arrival: CycleHistogram,
+    jitter: CycleHistogram,
+    last_enter: Option<u64>,
+    last_interval: Option<u64>,
+    preemptions: u64,
+    unpaired: u64,
+}
+
+struct ActiveIsr {
+    exception: u32,
+    entered: u64,
+    /// Cycles spent in interrupts nested inside this one
+    nested: u64,
+    preempted: bool,
+}
+
+/// Per-interrupt duration and arrival statistics for the current session
+pub struct IsrTimingTracker {
+    isrs: BTreeMap<u32, IsrStats>,
+    stack: Vec<ActiveIsr>,
+    /// DWT exception trace seen; firmware ISR events are ignored
+    exception_trace: bool,
+}
+
+impl IsrTimingTracker {
+    pub fn new() -> Self {
+        Self {
+            isrs: BTreeMap::new(),
+            stack: Vec::new(),
+            exception_trace: false,
+        }
+    }
+
+    pub fn observe(&mut self, timestamp: u64, event: &TraceEvent) {
+        match *event {
+            TraceEvent::IsrEnter { isr_id, .. } if !self.exception_trace => {
+                self.enter(isr_id.saturating_add(FIRST_IRQ_EXCEPTION), timestamp);
+            }
+            TraceEvent::IsrExit { isr_id, .. } if !self.exception_trace => {
+                self.exit(isr_id.saturating_add(FIRST_IRQ_EXCEPTION), timestamp);
+            }
+            TraceEvent::ExceptionEnter { exception, .. } => {
+                self.start_exception_trace();
+                self.enter(exception, timestamp);
+            }
+            TraceEvent::ExceptionExit { exception } => {
+                self.start_exception_trace();
+                self.exit(exception, timestamp);
+            }
+            _ => {}
+        }
+    }
+
+    fn start_exception_trace(&mut self) {
+        if !self.exception_trace {
+            self.exception_trace = true;
+            self.stack.clear();
+        }
+    }
+
+    fn enter(&mut self, exception: u32, timestamp: u64) {
+        let stats = self.isrs.entry(exception).or_default();
+        if let Some(last) = stats.last_enter.replace(timestamp) {
+            let interval = timestamp.saturating_sub(last);
+            stats.inter_arrival.record(interval);
+            if let Some(previous) = stats.last_interval.replace(interval) {
+                stats.jitter.record(interval.abs_diff(previous));
+            }
+        }
+        if let Some(outer) = self.stack.last_mut() {
+            outer.preempted = true;
+        }
+        self.stack.push(ActiveIsr {
+            exception,
+            entered: timestamp,
+            nested: 0,
+            preempted: false,
+        });
+    }
+
+    fn exit(&mut self, exception: u32, timestamp: u64) {
+        let Some(pos) = self
+            .stack
+            .iter()
+            .rposition(|isr| isr.exception == exception)
+        else {
+            self.isrs.entry(exception).or_default().unpaired += 1;
+            return;
+        };
+        // Anything entered after it must have exited unseen
+        for lost in self.stack.split_off(pos + 1) {
+            self.isrs.entry(lost.exception).or_default().unpaired += 1;
+        }
+        let isr = self.stack.pop().expect("ISR at pos");
+        let elapsed = timestamp.saturating_sub(isr.entered);
+        let stats = self.isrs.entry(exception).or_default();
+        stats.duration.record(elapsed.saturating_sub(isr.nested));
+        if isr.preempted {
+            stats.preemptions += 1;
+        }
+        if let Some(outer) = self.stack.last_mut() {
+            outer.nested += elapsed;
+        }
+    }
+
+    /// Timing of every interrupt seen, in exception number order
+    pub fn report(&self, interrupts: &InterruptMap) -> Vec<IsrTiming> {
+        self.isrs
+            .iter()
+            .map(|(&exception, stats)| {
+                let irq = exception.checked_sub(FIRST_IRQ_EXCEPTION);
+                let name = irq
+                    .and_then(|irq| interrupts.get(irq))
+                    .map(|info| info.name.clone())
+                    .unwrap_or_else(|| exception_name(exception));
+                IsrTiming {
+                    exception,
+                    irq,
+                    name,
+                    duration: stats.duration.to_histogram(),
+                    inter_arrival: stats.inter_arrival.to_histogram(),
+                    jitter: stats.jitter.to_histogram(),
+                    preemptions: stats.preemptions,
+                    unpaired: stats.unpaired,
+                }
+            })
+            .collect()
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl Default for IsrTimingTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn isr(enter: bool, isr_id: u32) -> TraceEvent {
+        if enter {
+            TraceEvent::IsrEnter {
+                isr_id,
+                name: None,
+                peripheral: None,
+            }
+        } else {
+            TraceEvent::IsrExit {
+                isr_id,
+                name: None,
+                peripheral: None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_bucket_bounds() {
+        assert_eq!(bucket_bounds(0), (0, 1));
+        assert_eq!(bucket_bounds(7), (7, 8));
+        assert_eq!(bucket_bounds(8), (8, 10));
+        assert_eq!(bucket_bounds(1000), (896, 1024));
+        assert_eq!(bucket_bounds(u64::MAX).1, u64::MAX);
+    }
+
+    #[test]
+    fn test_nested_isr_is_not_counted_in_the_preempted_one() {
+        let mut tracker = IsrTimingTracker::new();
+        for (timestamp, event) in [
+            (0, isr(true, 28)),
+            (100, isr(true, 6)),
+            (140, isr(false, 6)),
+            (300, isr(false, 28)),
+            (1000, isr(true, 28)),
+            (1200, isr(false, 28)),
+            (2100, isr(true, 28)),
+            (2150, isr(false, 28)),
+            (2200, isr(false, 6)),
+        ] {
+            tracker.observe(timestamp, &event);
+        }
+
+        let report = tracker.report(&InterruptMap::new());
+        let tim2 = report.iter().find(|isr| isr.irq == Some(28)).unwrap();
+        assert_eq!(tim2.exception, 44);
+        assert_eq!(tim2.name, "IRQ28");
+        assert_eq!(tim2.duration.count, 3);
+        assert_eq!((tim2.duration.min, tim2.duration.max), (50, 260));
+        assert_eq!(tim2.preemptions, 1);
+        assert_eq!(
+            (tim2.inter_arrival.min, tim2.inter_arrival.max),
+            (1000, 1100)
+        );
+        assert_eq!(tim2.jitter.count, 1);
+        assert_eq!(tim2.jitter.max, 100);
+
+        let exti0 = report.iter().find(|isr| isr.irq == Some(6)).unwrap();
+        assert_eq!(exti0.duration.max, 40);
+        assert_eq!(exti0.unpaired, 1);
+    }
+
+    #[test]
+    fn test_exception_trace_replaces_firmware_events() {
+        let mut tracker = IsrTimingTracker::new();
+        for (timestamp, event) in [
+            (0, isr(true, 28)),
+            (
+                5,
+                TraceEvent::ExceptionEnter {
+                    exception: 15,
+                    name: None,
+                },
+            ),
+            (10, isr(false, 28)),
+            (25, TraceEvent::ExceptionExit { exception: 15 }),
+        ] {
+            tracker.observe(timestamp, &event);
+        }
+
+        let report = tracker.report(&InterruptMap::new());
+        assert_eq!(report.len(), 2);
+        let systick = &report[0];
+        assert_eq!(systick.name, "SysTick");
+        assert_eq!(systick.irq, None);
+        assert_eq!(systick.duration.min, 20);
+        assert_eq!(report[1].duration.count, 0);
+    }
+}
@@ -1,49 +1,439 @@
 //! ITM frame processing and parsing
+//!
+//! Implements the packet layer of the ARMv7-M ITM/DWT trace protocol
+//! (ARMv7-M ARM, appendix D4). Raw SWO bytes go in, packets come out.
 
-use callisto_protocol::{ItmFrame, TraceEvent};
 use anyhow::Result;
+use callisto_protocol::{ItmFrame, TimestampQuality};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+/// Synchronization packet: at least 47 zero bits followed by a one
+const SYNC_MIN_ZERO_BYTES: usize = 5;
+const SYNC_TERMINATOR: u8 = 0x80;
+
+/// Overflow packet header
+const OVERFLOW_HEADER: u8 = 0x70;
+
+/// Global timestamp packet headers
+const GTS1_HEADER: u8 = 0x94;
+const GTS2_HEADER: u8 = 0xB4;
+
+/// Continuation bit used by multi-byte protocol packets
+const CONTINUATION: u8 = 0x80;
+
+/// Number of bits carried by a full GTS1 packet
+const GTS1_BITS: u8 = 26;
+
+/// Most GTS2 payload bytes: the 64-bit form has five bytes with the
+/// continuation bit set and a last one without
+const GTS2_MAX_PAYLOAD: usize = 6;
+
+/// Packets held waiting for a timestamp before being released as inexact
+const MAX_PENDING_PACKETS: usize = 1024;
+
+/// Timestamp control field of an LTS1 packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampControl {
+    /// Timestamp is synchronous to the packet it follows
+    Synchronous,
+    /// Timestamp was delayed relative to the source packet
+    TimestampDelayed,
+    /// Source packet was delayed relative to the event
+    EventDelayed,
+    /// Both the timestamp and the source packet were delayed
+    BothDelayed,
+}
+
+impl TimestampControl {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0x3 {
+            0 => TimestampControl::Synchronous,
+            1 => TimestampControl::TimestampDelayed,
+            2 => TimestampControl::EventDelayed,
+            _ => TimestampControl::BothDelayed,
+        }
+    }
+}
+
+impl From<TimestampControl> for TimestampQuality {
+    fn from(control: TimestampControl) -> Self {
+        match control {
+            TimestampControl::Synchronous => TimestampQuality::Exact,
+            TimestampControl::TimestampDelayed => TimestampQuality::TimestampDelayed,
+            TimestampControl::EventDelayed => TimestampQuality::EventDelayed,
+            TimestampControl::BothDelayed => TimestampQuality::BothDelayed,
+        }
+    }
+}
+
+/// A single decoded ITM/DWT packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ItmPacket {
+    /// Synchronization packet
+    Sync,
+    /// ITM FIFO overflow, one or more packets were lost
+    Overflow,
+    /// Software instrumentation packet from a stimulus port
+    Instrumentation { port: u8, data: Vec<u8> },
+    /// Hardware source packet from the DWT
+    Hardware { discriminator: u8, data: Vec<u8> },
+    /// Extension packet (`hardware` is the SH bit)
+    Extension { hardware: bool, value: u32 },
+    /// Local timestamp (LTS1 or LTS2), delta since the previous one
+    LocalTimestamp {
+        delta: u32,
+        control: TimestampControl,
+    },
+    /// Global timestamp low bits; `bits` is how many low bits were sent
+    GlobalTimestamp1 {
+        value: u32,
+        bits: u8,
+        clock_changed: bool,
+        wrap: bool,
+    },
+    /// Global timestamp high bits, already shifted into place
+    GlobalTimestamp2 { value: u64 },
+}
+
+/// A packet stamped with its absolute target time in cycles
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedPacket {
+    pub packet: ItmPacket,
+    pub timestamp: u64,
+    pub quality: TimestampQuality,
+}
+
+impl TimedPacket {
+    /// Build a protocol frame for an instrumentation packet
+    pub fn to_frame(&self) -> Option<ItmFrame> {
+        match &self.packet {
+            ItmPacket::Instrumentation { port, data } => Some(ItmFrame {
+                port: *port,
+                data: data.clone(),
+                timestamp: Some(self.timestamp),
+                timestamp_quality: self.quality,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Errors detected in the ITM byte stream
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ItmError {
+    /// An invalid header or impossible packet was seen and bytes were
+    /// discarded until the next sync packet
+    #[error(
+        "ITM stream desynchronized at byte offset {offset} (header 0x{header:02x}), \
+         discarded {discarded} byte(s) before resync"
+    )]
+    Desync {
+        offset: u64,
+        header: u8,
+        discarded: u64,
+    },
+}
+
+impl ItmError {
+    /// Stable error code reported to clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            ItmError::Desync { .. } => "ITM_DESYNC",
+        }
+    }
+}
+
+/// Outcome of trying to parse one packet at the front of the buffer
+enum Parsed {
+    /// A packet and the number of bytes it consumed
+    Packet(ItmPacket, usize),
+    /// Bytes that cannot start a valid packet
+    Invalid(usize),
+    /// Not enough bytes buffered yet
+    Incomplete,
+}
+
+/// Where the stream lost alignment and how much has been thrown away since
+struct Desync {
+    offset: u64,
+    header: u8,
+    discarded: u64,
+}
 
 /// ITM frame processor
 pub struct ItmProcessor {
     buffer: Vec<u8>,
+    stimulus_page: u8,
     timestamp_base: u64,
+    prescaler: u64,
+    global_timestamp: u64,
+    timestamps_seen: bool,
+    pending: Vec<ItmPacket>,
+    stream_offset: u64,
+    desync: Option<Desync>,
+    errors: Vec<ItmError>,
 }
 
 impl ItmProcessor {
     pub fn new() -> Self {
         Self {
             buffer: Vec::new(),
+            stimulus_page: 0,
             timestamp_base: 0,
+            prescaler: 1,
+            global_timestamp: 0,
+            timestamps_seen: false,
+            pending: Vec::new(),
+            stream_offset: 0,
+            desync: None,
+            errors: Vec::new(),
         }
     }
 
-    /// Process raw ITM data and extract frames
-    pub fn process_data(&mut self, data: &[u8]) -> Result<Vec<ItmFrame>> {
-        // TODO: Implement ITM frame parsing
-        // For now, create mock frames
-        let mut frames = Vec::new();
-        
-        if !data.is_empty() {
-            frames.push(ItmFrame {
-                port: 0,
-                data: data.to_vec(),
-                timestamp: Some(self.get_timestamp()),
-            });
-        }
-        
-        Ok(frames)
+    /// Set the local timestamp prescaler configured in ITM_TCR.TSPrescale
+    /// (1, 4, 16 or 64) so LTS deltas are scaled back to cycles
+    pub fn set_timestamp_prescaler(&mut self, prescaler: u32) {
+        self.prescaler = prescaler.max(1) as u64;
     }
 
-    /// Get current timestamp (mock implementation)
-    fn get_timestamp(&mut self) -> u64 {
-        self.timestamp_base += 1000; // Mock increment
+    /// Current reconstructed target time in cycles
+    pub fn timestamp(&self) -> u64 {
         self.timestamp_base
     }
 
+    /// Whether the parser is aligned to packet boundaries
+    pub fn is_synchronized(&self) -> bool {
+        self.desync.is_none()
+    }
+
+    /// Take the stream errors detected since the last call
+    pub fn take_errors(&mut self) -> Vec<ItmError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Process raw ITM data and extract timestamped packets
+    ///
+    /// Bytes belonging to a packet that is not yet complete are kept and
+    /// decoded on the next call. Once the stream carries local timestamps,
+    /// packets are held until the timestamp that follows them arrives.
+    pub fn process_data(&mut self, data: &[u8]) -> Result<Vec<TimedPacket>> {
+        let mut timed = Vec::new();
+
+        for packet in self.parse_packets(data) {
+            match packet {
+                ItmPacket::LocalTimestamp { delta, control } => {
+                    self.timestamps_seen = true;
+                    self.timestamp_base += delta as u64 * self.prescaler;
+                    self.release_pending(control.into(), &mut timed);
+                    timed.push(self.stamp(packet, TimestampQuality::Exact));
+                    continue;
+                }
+                ItmPacket::GlobalTimestamp1 { value, bits, .. } => {
+                    let mask = (1u64 << bits.min(GTS1_BITS)) - 1;
+                    self.global_timestamp = (self.global_timestamp & !mask) | value as u64;
+                    self.timestamp_base = self.global_timestamp;
+                }
+                ItmPacket::GlobalTimestamp2 { value } => {
+                    let mask = (1u64 << GTS1_BITS) - 1;
+                    self.global_timestamp = (self.global_timestamp & mask) | value;
+                    self.timestamp_base = self.global_timestamp;
+                }
+                _ => {}
+            }
+
+            if self.timestamps_seen {
+                self.pending.push(packet);
+                if self.pending.len() >= MAX_PENDING_PACKETS {
+                    self.release_pending(TimestampQuality::Inexact, &mut timed);
+                }
+            } else {
+                timed.push(self.stamp(packet, TimestampQuality::Inexact));
+            }
+        }
+
+        Ok(timed)
+    }
+
+    /// Release packets still waiting for a timestamp, stamped with the
+    /// last known time
+    pub fn flush(&mut self) -> Vec<TimedPacket> {
+        let mut timed = Vec::new();
+        self.release_pending(TimestampQuality::Inexact, &mut timed);
+        timed
+    }
+
+    fn release_pending(&mut self, quality: TimestampQuality, timed: &mut Vec<TimedPacket>) {
+        let pending = std::mem::take(&mut self.pending);
+        timed.extend(
+            pending
+                .into_iter()
+                .map(|packet| self.stamp(packet, quality)),
+        );
+    }
+
+    fn stamp(&self, packet: ItmPacket, quality: TimestampQuality) -> TimedPacket {
+        TimedPacket {
+            packet,
+            timestamp: self.timestamp_base,
+            quality,
+        }
+    }
+
+    /// Split raw bytes into packets, buffering any incomplete tail
+    ///
+    /// After an invalid header the stream is considered desynchronized and
+    /// everything up to the next sync packet is discarded.
+    fn parse_packets(&mut self, data: &[u8]) -> Vec<ItmPacket> {
+        self.buffer.extend_from_slice(data);
+
+        let mut packets = Vec::new();
+        let mut pos = 0;
+
+        while pos < self.buffer.len() {
+            let parsed = self.parse_packet(&self.buffer[pos..]);
+
+            if let Some(desync) = self.desync.as_mut() {
+                match parsed {
+                    Parsed::Packet(ItmPacket::Sync, len) => {
+                        let desync = self.desync.take().unwrap();
+                        self.errors.push(ItmError::Desync {
+                            offset: desync.offset,
+                            header: desync.header,
+                            discarded: desync.discarded,
+                        });
+                        packets.push(ItmPacket::Sync);
+                        pos += len;
+                    }
+                    Parsed::Invalid(len) => {
+                        desync.discarded += len as u64;
+                        pos += len;
+                    }
+                    Parsed::Packet(..) => {
+                        desync.discarded += 1;
+                        pos += 1;
+                    }
+                    Parsed::Incomplete => break,
+                }
+                continue;
+            }
+
+            match parsed {
+                Parsed::Packet(packet, len) => {
+                    pos += len;
+                    if let ItmPacket::Extension {
+                        hardware: false,
+                        value,
+                    } = packet
+                    {
+                        self.stimulus_page = (value & 0x7) as u8;
+                    }
+                    packets.push(packet);
+                }
+                Parsed::Invalid(len) => {
+                    let offset = self.stream_offset + pos as u64;
+                    let header = self.buffer[pos];
+                    warn!(
+                        "Invalid ITM header 0x{:02x} at offset {}, waiting for sync",
+                        header, offset
+                    );
+                    self.desync = Some(Desync {
+                        offset,
+                        header,
+                        discarded: len as u64,
+                    });
+                    pos += len;
+                }
+                Parsed::Incomplete => break,
+            }
+        }
+
+        self.buffer.drain(..pos);
+        self.stream_offset += pos as u64;
+        debug!("Parsed {} ITM packet(s)", packets.len());
+        packets
+    }
+
+    fn parse_packet(&self, bytes: &[u8]) -> Parsed {
+        let header = bytes[0];
+
+        match header {
+            0x00 => parse_sync(bytes),
+            OVERFLOW_HEADER => Parsed::Packet(ItmPacket::Overflow, 1),
+            GTS1_HEADER => parse_gts1(bytes),
+            GTS2_HEADER => parse_gts2(bytes),
+            // LTS2: 0b0TTT0000, single byte timestamp
+            h if h & 0x8F == 0x00 => Parsed::Packet(
+                ItmPacket::LocalTimestamp {
+                    delta: ((h >> 4) & 0x7) as u32,
+                    control: TimestampControl::Synchronous,
+                },
+                1,
+            ),
+            // LTS1: 0b11TC0000 followed by up to four continuation bytes
+            h if h & 0xCF == 0xC0 => parse_continued(bytes, 4, |value| ItmPacket::LocalTimestamp {
+                delta: value as u32,
+                control: TimestampControl::from_bits(h >> 4),
+            }),
+            // Extension: 0bCXXX1S00
+            h if h & 0x0B == 0x08 => {
+                let low = ((h >> 4) & 0x7) as u32;
+                let hardware = h & 0x04 != 0;
+                if h & CONTINUATION == 0 {
+                    return Parsed::Packet(
+                        ItmPacket::Extension {
+                            hardware,
+                            value: low,
+                        },
+                        1,
+                    );
+                }
+                parse_continued(bytes, 4, |value| ItmPacket::Extension {
+                    hardware,
+                    value: low | ((value as u32) << 3),
+                })
+            }
+            // Source packets: 0bAAAAAHSS with SS != 0
+            h if h & 0x03 != 0 => {
+                let size = match h & 0x03 {
+                    1 => 1,
+                    2 => 2,
+                    _ => 4,
+                };
+                if bytes.len() < size + 1 {
+                    return Parsed::Incomplete;
+                }
+                let data = bytes[1..=size].to_vec();
+                let address = h >> 3;
+                let packet = if h & 0x04 == 0 {
+                    ItmPacket::Instrumentation {
+                        port: self.stimulus_page * 32 + address,
+                        data,
+                    }
+                } else {
+                    ItmPacket::Hardware {
+                        discriminator: address,
+                        data,
+                    }
+                };
+                Parsed::Packet(packet, size + 1)
+            }
+            // Reserved header encodings
+            _ => Parsed::Invalid(1),
+        }
+    }
+
     /// Reset the processor state
     pub fn reset(&mut self) {
         self.buffer.clear();
+        self.stimulus_page = 0;
         self.timestamp_base = 0;
+        self.global_timestamp = 0;
+        self.timestamps_seen = false;
+        self.pending.clear();
+        self.stream_offset = 0;
+        self.desync = None;
+        self.errors.clear();
     }
 }
 
@@ -51,4 +441,322 @@ impl Default for ItmProcessor {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+/// Parse a run of zero bytes, which must be a sync packet
+fn parse_sync(bytes: &[u8]) -> Parsed {
+    let zeros = bytes.iter().take_while(|&&b| b == 0).count();
+    match bytes.get(zeros) {
+        None => Parsed::Incomplete,
+        Some(&SYNC_TERMINATOR) if zeros >= SYNC_MIN_ZERO_BYTES => {
+            Parsed::Packet(ItmPacket::Sync, zeros + 1)
+        }
+        Some(_) => Parsed::Invalid(zeros),
+    }
+}
+
+/// Parse a GTS1 packet: bits [25:0] of the global timestamp
+fn parse_gts1(bytes: &[u8]) -> Parsed {
+    let payload = &bytes[1..];
+    let mut value = 0u32;
+
+    for (i, &byte) in payload.iter().take(4).enumerate() {
+        if i == 3 {
+            if byte & CONTINUATION != 0 {
+                return Parsed::Invalid(1);
+            }
+            value |= ((byte & 0x1F) as u32) << 21;
+            return Parsed::Packet(
+                ItmPacket::GlobalTimestamp1 {
+                    value,
+                    bits: 26,
+                    clock_changed: byte & 0x20 != 0,
+                    wrap: byte & 0x40 != 0,
+                },
+                5,
+            );
+        }
+
+        value |= ((byte & 0x7F) as u32) << (7 * i);
+        if byte & CONTINUATION == 0 {
+            return Parsed::Packet(
+                ItmPacket::GlobalTimestamp1 {
+                    value,
+                    bits: 7 * (i as u8 + 1),
+                    clock_changed: false,
+                    wrap: false,
+                },
+                i + 2,
+            );
+        }
+    }
+
+    Parsed::Incomplete
+}
+
+/// Parse a GTS2 packet: bits [47:26] or [63:26] of the global timestamp
+fn parse_gts2(bytes: &[u8]) -> Parsed {
+    parse_continued(bytes, GTS2_MAX_PAYLOAD, |value| {
+        let mask = (1u64 << (64 - GTS1_BITS)) - 1;
+        ItmPacket::GlobalTimestamp2 {
+            value: (value & mask) << GTS1_BITS,
+        }
+    })
+}
+
+/// Parse a header followed by up to `max` little-endian 7-bit groups,
+/// the last of which must have the continuation bit clear
+fn parse_continued(bytes: &[u8], max: usize, build: impl FnOnce(u64) -> ItmPacket) -> Parsed {
+    let mut value = 0u64;
+
+    for (i, &byte) in bytes[1..].iter().take(max).enumerate() {
+        value |= ((byte & 0x7F) as u64) << (7 * i);
+        if byte & CONTINUATION == 0 {
+            return Parsed::Packet(build(value), i + 2);
+        }
+        if i + 1 == max {
+            return Parsed::Invalid(1);
+        }
+    }
+
+    Parsed::Incomplete
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse bytes and release everything, ignoring timestamps
+    fn parse(processor: &mut ItmProcessor, data: &[u8]) -> Vec<ItmPacket> {
+        let mut timed = processor.process_data(data).unwrap();
+        timed.extend(processor.flush());
+        timed.into_iter().map(|t| t.packet).collect()
+    }
+
+    #[test]
+    fn test_instrumentation_sizes_and_ports() {
+        let mut processor = ItmProcessor::new();
+        let packets = parse(
+            &mut processor,
+            &[0x01, b'A', 0x0A, 0x34, 0x12, 0x1B, 1, 2, 3, 4],
+        );
+
+        assert_eq!(
+            packets,
+            vec![
+                ItmPacket::Instrumentation {
+                    port: 0,
+                    data: vec![b'A']
+                },
+                ItmPacket::Instrumentation {
+                    port: 1,
+                    data: vec![0x34, 0x12]
+                },
+                ItmPacket::Instrumentation {
+                    port: 3,
+                    data: vec![1, 2, 3, 4]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_packet_split_across_reads() {
+        let mut processor = ItmProcessor::new();
+        assert!(parse(&mut processor, &[0x13, 0xAA, 0xBB]).is_empty());
+
+        let packets = parse(&mut processor, &[0xCC, 0xDD]);
+        assert_eq!(
+            packets,
+            vec![ItmPacket::Instrumentation {
+                port: 2,
+                data: vec![0xAA, 0xBB, 0xCC, 0xDD],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sync_overflow_and_hardware() {
+        let mut processor = ItmProcessor::new();
+        let packets = parse(
+            &mut processor,
+            &[0, 0, 0, 0, 0, 0x80, 0x70, 0x0E, 0x0B, 0x20],
+        );
+
+        assert_eq!(
+            packets,
+            vec![
+                ItmPacket::Sync,
+                ItmPacket::Overflow,
+                ItmPacket::Hardware {
+                    discriminator: 1,
+                    data: vec![0x0B, 0x20],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let mut processor = ItmProcessor::new();
+        let packets = parse(
+            &mut processor,
+            &[
+                0x30, 0xD0, 0x85, 0x01, 0x94, 0x81, 0x82, 0x83, 0x61, 0xB4, 0x02,
+            ],
+        );
+
+        assert_eq!(
+            packets,
+            vec![
+                ItmPacket::LocalTimestamp {
+                    delta: 3,
+                    control: TimestampControl::Synchronous,
+                },
+                ItmPacket::LocalTimestamp {
+                    delta: 5 | (1 << 7),
+                    control: TimestampControl::TimestampDelayed,
+                },
+                ItmPacket::GlobalTimestamp1 {
+                    value: 1 | (2 << 7) | (3 << 14) | (1 << 21),
+                    bits: 26,
+                    clock_changed: true,
+                    wrap: true,
+                },
+                ItmPacket::GlobalTimestamp2 { value: 2 << 26 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extension_selects_stimulus_page() {
+        let mut processor = ItmProcessor::new();
+        let packets = parse(&mut processor, &[0x18, 0x29, 0x42]);
+
+        assert_eq!(
+            packets,
+            vec![
+                ItmPacket::Extension {
+                    hardware: false,
+                    value: 1
+                },
+                ItmPacket::Instrumentation {
+                    port: 37,
+                    data: vec![0x42]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_local_timestamps_stamp_preceding_packets() {
+        let mut processor = ItmProcessor::new();
+        // LTS2(1), port 0 'A', LTS1 delayed delta 100, port 0 'B', LTS2(2)
+        let timed = processor
+            .process_data(&[0x10, 0x01, b'A', 0xD0, 0x64, 0x01, b'B', 0x20])
+            .unwrap();
+        let frames: Vec<ItmFrame> = timed.iter().filter_map(|t| t.to_frame()).collect();
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].timestamp, Some(101));
+        assert_eq!(
+            frames[0].timestamp_quality,
+            TimestampQuality::TimestampDelayed
+        );
+        assert_eq!(frames[1].timestamp, Some(103));
+        assert_eq!(frames[1].timestamp_quality, TimestampQuality::Exact);
+    }
+
+    #[test]
+    fn test_global_timestamp_anchors_counter() {
+        let mut processor = ItmProcessor::new();
+        processor.set_timestamp_prescaler(4);
+        // GTS1 = 0x1000, GTS2 = 1 << 26, then LTS2(3)
+        processor
+            .process_data(&[0x94, 0x80, 0xA0, 0x80, 0x00, 0xB4, 0x01, 0x30])
+            .unwrap();
+
+        assert_eq!(processor.timestamp(), (1 << 26) + 0x1000 + 12);
+    }
+
+    #[test]
+    fn test_packets_without_timestamp_are_inexact() {
+        let mut processor = ItmProcessor::new();
+        processor.process_data(&[0x10]).unwrap();
+
+        assert!(processor.process_data(&[0x01, b'A']).unwrap().is_empty());
+        let flushed = processor.flush();
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(flushed[0].timestamp, 1);
+        assert_eq!(flushed[0].quality, TimestampQuality::Inexact);
+    }
+
+    #[test]
+    fn test_resync_after_invalid_header() {
+        let mut processor = ItmProcessor::new();
+        // Port 0 'A', reserved header 0x04, garbage, sync, port 0 'B'
+        let mut stream = vec![0x01, b'A', 0x04, 0x13, 0x55];
+        stream.extend_from_slice(&[0, 0, 0, 0, 0, 0x80, 0x01, b'B']);
+        let packets = parse(&mut processor, &stream);
+
+        assert_eq!(
+            packets,
+            vec![
+                ItmPacket::Instrumentation {
+                    port: 0,
+                    data: vec![b'A']
+                },
+                ItmPacket::Sync,
+                ItmPacket::Instrumentation {
+                    port: 0,
+                    data: vec![b'B']
+                },
+            ]
+        );
+        assert_eq!(
+            processor.take_errors(),
+            vec![ItmError::Desync {
+                offset: 2,
+                header: 0x04,
+                discarded: 3,
+            }]
+        );
+        assert!(processor.is_synchronized());
+    }
+
+    #[test]
+    fn test_overlong_timestamp_desyncs() {
+        let mut processor = ItmProcessor::new();
+        let packets = parse(&mut processor, &[0xC0, 0x81, 0x82, 0x83, 0x84, 0x01, b'A']);
+
+        assert!(packets.is_empty());
+        assert!(!processor.is_synchronized());
+    }
+
+    #[test]
+    fn test_gts2_64_bit_form() {
+        let mut processor = ItmProcessor::new();
+        // Five continuation bytes, and high bits past bit 63 dropped
+        let packets = parse(&mut processor, &[0xB4, 0x81, 0x80, 0x80, 0x80, 0x80, 0x7F]);
+        assert_eq!(
+            packets,
+            vec![ItmPacket::GlobalTimestamp2 {
+                value: (1 << 26) | (0x7 << 61)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_overlong_gts2_desyncs() {
+        let mut processor = ItmProcessor::new();
+        // A sixth byte with the continuation bit set is malformed
+        let packets = parse(
+            &mut processor,
+            &[0xB4, 0x81, 0x82, 0x83, 0x84, 0x85, 0x86, 0x01, b'A'],
+        );
+
+        assert!(packets.is_empty());
+        assert!(!processor.is_synchronized());
+        assert_eq!(processor.timestamp(), 0);
+    }
+}
@@ -4,91 +4,944 @@
 //! It handles probe-rs integration, ITM decoding, and event batching.
 
 use callisto_protocol::*;
-use anyhow::Result;
-use std::collections::HashMap;
-use tokio::sync::mpsc;
-use tracing::{debug, error, info, warn};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Mutex};
+use tracing::{debug, info, warn};
 
 pub mod probe;
 pub mod itm;
 pub mod decoder;
 pub mod mock;
+pub mod dwt;
+pub mod profiler;
+pub mod tpiu;
+pub mod registry;
+pub mod record;
+pub mod defmt;
+pub mod markers;
+pub mod tasks;
+pub mod svd;
+pub mod spans;
+pub mod source;
+pub mod stats;
+pub mod scheduling;
+pub mod isr_timing;
+pub mod utilization;
+mod ingest;
 
 pub use probe::*;
 pub use itm::*;
 pub use decoder::*;
 pub use mock::*;
+pub use dwt::*;
+pub use profiler::*;
+pub use tpiu::*;
+pub use registry::*;
+pub use record::*;
+pub use defmt::*;
+pub use markers::*;
+pub use tasks::*;
+pub use svd::*;
+pub use spans::*;
+pub use source::*;
+pub use stats::*;
+pub use scheduling::*;
+pub use isr_timing::*;
+pub use utilization::*;
+
+use ingest::IngestTask;
+
+/// Minimum host time between PC sample profile snapshots
+const PROFILE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Bytes kept per non-ITM TPIU source before the oldest are dropped
+const MAX_SOURCE_BUFFER: usize = 1024 * 1024;
 
 /// Core ITM session manager
 pub struct ItmSession {
     probe_manager: ProbeManager,
-    decoders: HashMap<u8, Box<dyn ItmDecoder + Send>>,
+    source: SourceConfig,
+    tpiu: Option<TpiuDeframer>,
+    itm_atb_id: u8,
+    other_sources: HashMap<u8, Vec<u8>>,
+    processor: ItmProcessor,
+    dwt_decoder: DwtDecoder,
+    profiler: PcProfiler,
+    last_profile: Option<Instant>,
+    registry: DecoderRegistry,
+    decoders: DecoderMap,
+    allow_mask: u32,
+    tasks: TaskTable,
+    interrupts: InterruptMap,
+    spans: SpanTracker,
+    utilization: UtilizationTracker,
+    scheduling: SchedulingAnalyzer,
+    isr_timing: IsrTimingTracker,
+    /// Port spans were last seen on, for diagnostics emitted at stop
+    span_port: u8,
+    /// Marker names from config files and sources, before any ELF names
+    markers: MarkerDictionary,
+    /// Timestamp of the latest stimulus packet, for events emitted later
+    last_timestamp: u64,
+    /// Also send the raw ITM packets of each batch as `Itm` messages
+    raw_frames: bool,
+    /// The shared handle this session lives in, for the ingest task
+    handle: Weak<Mutex<ItmSession>>,
+    ingest: Option<IngestTask>,
     event_sender: mpsc::UnboundedSender<ServerMessage>,
     stats: SessionStats,
+    rates: RateWindow,
+    stats_interval: Duration,
+    last_stats: Option<Instant>,
+    /// Depth of the queue `event_sender` feeds, if its consumer reports it
+    outgoing_queue: Option<QueueGauge>,
+    /// Chunks the source has captured but not yet handed over
+    source_backlog: Option<usize>,
+    /// SWO baud rate when tracing from a probe
+    swo_baud: Option<u32>,
 }
 
 /// Session statistics
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct SessionStats {
     pub events_processed: u64,
     pub bytes_processed: u64,
     pub dropped_events: u64,
+    pub overflow_packets: u64,
+    pub desyncs: u64,
+    pub discarded_bytes: u64,
     pub start_time: Option<std::time::Instant>,
+    /// When the source captured the latest trace bytes
+    pub last_capture: Option<Instant>,
+    /// Traffic per port, including the hardware pseudo-port
+    pub ports: BTreeMap<u8, PortCounters>,
+}
+
+impl SessionStats {
+    /// Fraction of events lost to ITM overflows
+    ///
+    /// An overflow packet means at least one packet was dropped, so this
+    /// is a lower bound.
+    pub fn drop_rate(&self) -> f64 {
+        let total = self.events_processed + self.dropped_events;
+        if total == 0 {
+            0.0
+        } else {
+            self.dropped_events as f64 / total as f64
+        }
+    }
 }
 
 impl ItmSession {
     pub fn new(event_sender: mpsc::UnboundedSender<ServerMessage>) -> Self {
         Self {
             probe_manager: ProbeManager::new(),
-            decoders: HashMap::new(),
+            source: SourceConfig::default(),
+            tpiu: None,
+            itm_atb_id: DEFAULT_ITM_ATB_ID,
+            other_sources: HashMap::new(),
+            processor: ItmProcessor::new(),
+            dwt_decoder: DwtDecoder::new(),
+            profiler: PcProfiler::default(),
+            last_profile: None,
+            registry: DecoderRegistry::default(),
+            decoders: DecoderMap::new(),
+            allow_mask: 0,
+            tasks: TaskTable::new(),
+            interrupts: InterruptMap::new(),
+            spans: SpanTracker::new(),
+            utilization: UtilizationTracker::default(),
+            scheduling: SchedulingAnalyzer::new(),
+            isr_timing: IsrTimingTracker::new(),
+            span_port: standard_ports::HARDWARE,
+            markers: MarkerDictionary::new(),
+            last_timestamp: 0,
+            raw_frames: false,
+            handle: Weak::new(),
+            ingest: None,
             event_sender,
             stats: SessionStats::default(),
+            rates: RateWindow::default(),
+            stats_interval: DEFAULT_STATS_INTERVAL,
+            last_stats: None,
+            outgoing_queue: None,
+            source_backlog: None,
+            swo_baud: None,
         }
     }
 
+    /// Move the session behind a shared lock
+    ///
+    /// Only a shared session can run the background ingest task that
+    /// reads from the probe while tracing.
+    pub fn into_shared(mut self) -> Arc<Mutex<Self>> {
+        Arc::new_cyclic(|handle| {
+            self.handle = handle.clone();
+            Mutex::new(self)
+        })
+    }
+
+    /// Set how often Stats messages are sent while tracing
+    pub fn set_stats_interval(&mut self, interval: Duration) {
+        self.stats_interval = interval;
+    }
+
+    /// Gauge for the consumer of the event channel to report its backlog,
+    /// which is included in Stats
+    pub fn monitor_outgoing_queue(&mut self) -> QueueGauge {
+        self.outgoing_queue.get_or_insert_with(QueueGauge::new).clone()
+    }
+
+    /// Choose where trace data is read from when tracing starts
+    pub fn set_source(&mut self, source: SourceConfig) {
+        self.source = source;
+    }
+
+    pub fn source(&self) -> &SourceConfig {
+        &self.source
+    }
+
+    /// Pick the probe and chip to attach to, if tracing through a probe
+    pub fn select_probe(&mut self, selector: Option<String>, chip: Option<String>) {
+        if let SourceConfig::Probe(config) = &mut self.source {
+            if selector.is_some() {
+                config.selector = selector;
+            }
+            if chip.is_some() {
+                config.chip = chip;
+            }
+        }
+    }
+
+    /// Also send raw ITM packets to clients, for debugging
+    pub fn set_raw_frames(&mut self, enabled: bool) {
+        self.raw_frames = enabled;
+    }
+
+    /// Expect TPIU formatted input, with the ITM on the given ATB ID
+    ///
+    /// Pass `None` when the formatter is bypassed and the port carries
+    /// raw ITM packets.
+    pub fn set_tpiu_formatter(&mut self, itm_atb_id: Option<u8>) {
+        self.tpiu = itm_atb_id.map(|_| TpiuDeframer::new());
+        self.itm_atb_id = itm_atb_id.unwrap_or(DEFAULT_ITM_ATB_ID);
+        self.other_sources.clear();
+    }
+
+    /// Take the bytes buffered so far for a non-ITM TPIU source
+    pub fn take_source_data(&mut self, atb_id: u8) -> Vec<u8> {
+        self.other_sources.remove(&atb_id).unwrap_or_default()
+    }
+
+    /// Set how long text ports hold a partial line before emitting it
+    ///
+    /// Takes effect when decoders are next built.
+    pub fn set_text_idle_timeout(&mut self, timeout: Duration) {
+        self.registry.set_text_idle_timeout(timeout);
+    }
+
+    /// Set the marker names known without a firmware ELF
+    pub fn set_marker_dictionary(&mut self, markers: MarkerDictionary) {
+        self.registry.set_marker_dictionary(markers.clone());
+        self.markers = markers;
+    }
+
+    /// Load symbol tables from the firmware ELF for decoders that need them
+    pub fn load_firmware_elf(&mut self, path: &std::path::Path) -> Result<()> {
+        let data = std::fs::read(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        let table = DefmtTable::parse_elf(&data)?;
+        if table.is_none() {
+            info!("{} has no defmt string table", path.display());
+        }
+        self.registry.set_defmt_table(table);
+
+        let printf = PrintfTable::parse_elf(&data)?;
+        if printf.is_none() {
+            info!("{} has no {} section", path.display(), PRINTF_SECTION);
+        }
+        self.registry.set_printf_table(printf);
+
+        let mut markers = self.markers.clone();
+        match MarkerDictionary::parse_elf(&data)? {
+            Some(names) => markers.extend(&names),
+            None => info!("{} has no {} section", path.display(), MARKER_SECTION),
+        }
+        self.registry.set_marker_dictionary(markers);
+        Ok(())
+    }
+
+    /// Load a generated printf dictionary, adding to any format strings
+    /// from the firmware ELF
+    pub fn load_printf_dictionary(&mut self, path: &std::path::Path) -> Result<()> {
+        let dictionary = PrintfTable::load_dictionary(path)?;
+        let mut table = self.registry.printf_table().cloned().unwrap_or_default();
+        table.extend(dictionary);
+        self.registry.set_printf_table(Some(table));
+        Ok(())
+    }
+
+    /// Reconfigure ports, swapping decoders immediately if tracing
+    ///
+    /// The update is all or nothing: if any configuration is invalid no
+    /// port is changed.
+    pub fn update_port_config(&mut self, configs: Vec<PortConfig>) -> Result<()> {
+        self.registry
+            .update_ports(configs, self.allow_mask, &mut self.decoders)
+    }
+
+    /// Load the chip's SVD file to name interrupts
+    pub fn load_svd(&mut self, path: &std::path::Path) -> Result<()> {
+        self.interrupts = InterruptMap::load(path)?;
+        info!(
+            "Loaded {} interrupt name(s) for {}",
+            self.interrupts.interrupts().len(),
+            self.interrupts.device().unwrap_or("unknown device")
+        );
+        Ok(())
+    }
+
+    /// Interrupt names from the SVD file, if one is loaded
+    pub fn interrupts(&self) -> &InterruptMap {
+        &self.interrupts
+    }
+
+    /// Tasks seen in this session
+    pub fn tasks(&self) -> &TaskTable {
+        &self.tasks
+    }
+
+    /// Set the expected period and deadline of periodic tasks
+    pub fn set_task_deadlines(&mut self, deadlines: Vec<TaskDeadline>) {
+        self.scheduling.set_deadlines(deadlines);
+    }
+
+    /// Load task deadlines from a JSON list
+    pub fn load_task_deadlines(&mut self, path: &std::path::Path) -> Result<()> {
+        self.scheduling.load_deadlines(path)?;
+        info!(
+            "Loaded deadlines for {} task(s)",
+            self.scheduling.deadlines().len()
+        );
+        Ok(())
+    }
+
+    /// Scheduling analysis of every task seen since tracing started
+    pub fn scheduling_report(&self) -> ServerMessage {
+        ServerMessage::SchedulingReport {
+            timestamp: Utc::now(),
+            tasks: self.scheduling.report(&self.tasks),
+        }
+    }
+
+    /// Duration and arrival histograms of every interrupt seen since
+    /// tracing started
+    pub fn isr_timing_report(&self) -> ServerMessage {
+        ServerMessage::IsrTimingReport {
+            timestamp: Utc::now(),
+            isrs: self.isr_timing.report(&self.interrupts),
+        }
+    }
+
+    /// Port configuration as decoded, for reporting in `Meta`
+    pub fn port_config(&self) -> &HashMap<u8, PortConfig> {
+        self.registry.ports()
+    }
+
     pub async fn start_tracing(&mut self, allow_mask: u32, baud_rate: Option<u32>) -> Result<()> {
         info!("Starting ITM tracing with port mask: 0x{:08x}", allow_mask);
         
         // Initialize decoders for enabled ports
-        self.setup_decoders(allow_mask);
-        
-        // Start probe session (placeholder for now)
-        self.probe_manager.start_session(allow_mask, baud_rate).await?;
+        self.decoders = self
+            .registry
+            .build_decoders(allow_mask)
+            .context("Invalid port configuration")?;
+        self.allow_mask = allow_mask;
+        self.last_timestamp = 0;
+        self.tasks.reset();
+        self.spans.reset();
+        self.utilization.reset();
+        self.scheduling.reset();
+        self.isr_timing.reset();
+        self.processor.reset();
+        if let Some(tpiu) = &mut self.tpiu {
+            tpiu.reset();
+        }
+        self.other_sources.clear();
+        self.dwt_decoder.reset();
+        self.profiler.reset();
         
-        self.stats.start_time = Some(std::time::Instant::now());
+        if let Some(ingest) = self.ingest.take() {
+            ingest.stop().await;
+        }
+
+        // Counters describe this run only
+        let now = Instant::now();
+        self.stats = SessionStats {
+            start_time: Some(now),
+            ..SessionStats::default()
+        };
+        self.rates.reset(now, &self.stats);
+        self.last_stats = Some(now);
+        self.source_backlog = None;
+        self.swo_baud = None;
+        if self.handle.strong_count() == 0 {
+            debug!("Session is not shared, feed trace data with process_data");
+            return Ok(());
+        }
+        let source = self
+            .source
+            .open(
+                &mut self.probe_manager,
+                allow_mask,
+                baud_rate,
+                self.tpiu.is_some(),
+            )
+            .await?;
+        if let SourceConfig::Probe(config) = &self.source {
+            self.swo_baud = Some(baud_rate.unwrap_or(config.baud));
+        }
+        self.start_ingest(source);
         Ok(())
     }
 
+    /// Read trace data from `source` in the background until it ends or
+    /// tracing stops
+    ///
+    /// Does nothing unless the session was made shared with
+    /// [`ItmSession::into_shared`]; otherwise data must be fed with
+    /// [`ItmSession::process_data`].
+    pub fn start_ingest(&mut self, source: Box<dyn TraceSource>) {
+        if self.handle.strong_count() == 0 {
+            debug!("Session is not shared, not starting the ingest task");
+            return;
+        }
+        // Replacing a running task drops its stop sender, which stops it
+        self.ingest = Some(IngestTask::spawn(self.handle.clone(), source));
+    }
+
     pub async fn stop_tracing(&mut self) -> Result<()> {
         info!("Stopping ITM tracing");
+        if let Some(ingest) = self.ingest.take() {
+            ingest.stop().await;
+        }
+        self.flush_packets();
+        self.flush_idle();
+        for event in self.spans.close_all() {
+            self.emit(self.last_timestamp, self.span_port, event);
+        }
+        let _ = self.event_sender.send(self.scheduling_report());
+        let _ = self.event_sender.send(self.isr_timing_report());
         self.probe_manager.stop_session().await?;
         Ok(())
     }
 
-    fn setup_decoders(&mut self, allow_mask: u32) {
-        self.decoders.clear();
-        
-        for port in 0..32 {
-            if (allow_mask & (1 << port)) != 0 {
-                let decoder: Box<dyn ItmDecoder + Send> = match port {
-                    0 => Box::new(TextDecoder::new()),
-                    1 => Box::new(TaskIsrDecoder::new()),
-                    2 => Box::new(MarkerDecoder::new()),
-                    3 => Box::new(CounterDecoder::new()),
-                    _ => Box::new(TextDecoder::new()),
-                };
-                self.decoders.insert(port, decoder);
+    /// Tell clients that the trace source failed
+    fn report_source_error(&self, error: &anyhow::Error) {
+        warn!("Trace source failed: {:#}", error);
+        let _ = self.event_sender.send(ServerMessage::Error {
+            timestamp: Utc::now(),
+            message: format!("Trace source failed: {:#}", error),
+            code: Some("SOURCE_ERROR".to_string()),
+        });
+    }
+
+    /// Periodic work while tracing: flush idle text and send due Stats
+    pub fn tick(&mut self) {
+        self.flush_idle();
+        if self
+            .last_stats
+            .is_some_and(|sent| sent.elapsed() >= self.stats_interval)
+        {
+            self.send_stats();
+        }
+    }
+
+    /// Record how many chunks the source is holding back
+    pub(crate) fn set_source_backlog(&mut self, backlog: Option<usize>) {
+        self.source_backlog = backlog;
+    }
+
+    /// Feed a chunk from a trace source through the pipeline
+    pub fn process_chunk(&mut self, chunk: &TraceChunk) -> Result<()> {
+        self.stats.last_capture = Some(chunk.captured_at);
+        self.process_data(&chunk.data)
+    }
+
+    /// Feed raw trace bytes through the ITM parser and port decoders
+    pub fn process_data(&mut self, data: &[u8]) -> Result<()> {
+        self.stats.bytes_processed += data.len() as u64;
+
+        let Some(tpiu) = &mut self.tpiu else {
+            return self.process_itm(data);
+        };
+
+        let mut itm = Vec::new();
+        for (id, bytes) in tpiu.process_data(data) {
+            if id == self.itm_atb_id {
+                itm = bytes;
+                continue;
+            }
+            let buffer = self.other_sources.entry(id).or_default();
+            buffer.extend_from_slice(&bytes);
+            if buffer.len() > MAX_SOURCE_BUFFER {
+                let excess = buffer.len() - MAX_SOURCE_BUFFER;
+                buffer.drain(..excess);
             }
         }
+
+        self.process_itm(&itm)
+    }
+
+    fn process_itm(&mut self, data: &[u8]) -> Result<()> {
+        let packets = self.processor.process_data(data)?;
+        self.process_packets(packets);
+        Ok(())
+    }
+
+    /// Decode packets still waiting for a timestamp, when the trace ends
+    pub fn flush_packets(&mut self) {
+        let packets = self.processor.flush();
+        self.process_packets(packets);
+    }
+
+    fn process_packets(&mut self, packets: Vec<TimedPacket>) {
+        let mut overflowed = false;
+
+        if self.raw_frames {
+            let frames: Vec<ItmFrame> = packets.iter().filter_map(|t| t.to_frame()).collect();
+            if let Some(first) = packets.first().filter(|_| !frames.is_empty()) {
+                let _ = self.event_sender.send(ServerMessage::Itm {
+                    timestamp: first.timestamp,
+                    frames,
+                });
+            }
+        }
+
+        for timed in packets {
+            match timed.packet {
+                ItmPacket::Instrumentation { port, data } => {
+                    self.last_timestamp = timed.timestamp;
+                    self.stats.ports.entry(port).or_default().bytes += data.len() as u64;
+                    let Some(decoder) = self.decoders.get_mut(&port) else {
+                        continue;
+                    };
+                    let events = match decoder.decode(port, &data, timed.timestamp) {
+                        Ok(events) => events,
+                        Err(e) => {
+                            // One port's bad data must not stop the others
+                            warn!("Port {} decoder error: {:#}", port, e);
+                            decoder.discontinuity();
+                            let _ = self.event_sender.send(ServerMessage::Error {
+                                timestamp: Utc::now(),
+                                message: format!("Port {}: {:#}", port, e),
+                                code: Some("DECODE_ERROR".to_string()),
+                            });
+                            continue;
+                        }
+                    };
+                    for event in events {
+                        self.send_event(timed.timestamp, port, event);
+                    }
+                }
+                ItmPacket::Hardware {
+                    discriminator,
+                    data,
+                } => {
+                    self.stats
+                        .ports
+                        .entry(standard_ports::HARDWARE)
+                        .or_default()
+                        .bytes += data.len() as u64;
+                    for event in self.dwt_decoder.decode(discriminator, &data) {
+                        // PC samples arrive every few hundred cycles, so they
+                        // are aggregated instead of streamed
+                        if let TraceEvent::PcSample { pc } = event {
+                            self.profiler.record(pc);
+                            continue;
+                        }
+                        self.send_event(timed.timestamp, standard_ports::HARDWARE, event);
+                    }
+                }
+                ItmPacket::Overflow => {
+                    warn!("ITM overflow at timestamp {}", timed.timestamp);
+                    self.stats.overflow_packets += 1;
+                    self.stats.dropped_events += 1;
+                    overflowed = true;
+                    self.decoder_discontinuity();
+
+                    let _ = self.event_sender.send(ServerMessage::Event {
+                        timestamp: timed.timestamp,
+                        port: standard_ports::HARDWARE,
+                        event: TraceEvent::Overflow,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        for error in self.processor.take_errors() {
+            warn!("{}", error);
+            let ItmError::Desync { discarded, .. } = &error;
+            self.stats.desyncs += 1;
+            self.stats.discarded_bytes += discarded;
+            self.decoder_discontinuity();
+
+            let _ = self.event_sender.send(ServerMessage::Error {
+                timestamp: Utc::now(),
+                message: error.to_string(),
+                code: Some(error.code().to_string()),
+            });
+        }
+
+        self.flush_idle();
+
+        // Let clients see the drop rate move as soon as data is lost
+        if overflowed {
+            self.send_stats();
+        }
+
+        if self.profiler.total_samples() > 0
+            && self
+                .last_profile
+                .is_none_or(|sent| sent.elapsed() >= PROFILE_INTERVAL)
+        {
+            self.send_profile();
+        }
+    }
+
+    /// Emit partial text lines from ports that have gone quiet
+    ///
+    /// Called after each batch of data, and should also be called
+    /// periodically while no data arrives.
+    pub fn flush_idle(&mut self) {
+        let now = Instant::now();
+        let mut flushed = Vec::new();
+        for (&port, decoder) in self.decoders.iter_mut() {
+            flushed.extend(decoder.flush_idle(now).into_iter().map(|event| (port, event)));
+        }
+        for (port, event) in flushed {
+            self.send_event(self.last_timestamp, port, event);
+        }
+    }
+
+    /// Drop partially assembled records after trace data was lost
+    fn decoder_discontinuity(&mut self) {
+        for decoder in self.decoders.values_mut() {
+            decoder.discontinuity();
+        }
+    }
+
+    fn send_event(&mut self, timestamp: u64, port: u8, mut event: TraceEvent) {
+        self.tasks.apply(&mut event);
+        self.interrupts.apply(&mut event);
+        if matches!(event, TraceEvent::SpanBegin { .. }) {
+            self.span_port = port;
+        }
+        let spans = self.spans.observe(timestamp, &event);
+        self.utilization.observe(timestamp, &event);
+        self.scheduling.observe(timestamp, &event, &self.tasks);
+        self.isr_timing.observe(timestamp, &event);
+        self.emit(timestamp, port, event);
+        for span in spans {
+            self.emit(timestamp, port, span);
+        }
+    }
+
+    fn emit(&mut self, timestamp: u64, port: u8, event: TraceEvent) {
+        self.stats.events_processed += 1;
+        self.stats.ports.entry(port).or_default().events += 1;
+        let _ = self.event_sender.send(ServerMessage::Event {
+            timestamp,
+            port,
+            event,
+        });
+    }
+
+    /// Send a snapshot of the PC sample profile
+    pub fn send_profile(&mut self) {
+        self.last_profile = Some(Instant::now());
+        let _ = self
+            .event_sender
+            .send(self.profiler.snapshot(DEFAULT_PROFILE_TOP));
+    }
+
+    /// Send a Stats message with rates over the recent window, and a
+    /// Utilization message once the trace has scheduling events
+    pub fn send_stats(&mut self) {
+        let now = Instant::now();
+        self.last_stats = Some(now);
+        let rates = self.rates.update(now, &self.stats);
+        let utilization = self
+            .utilization
+            .update(now, &self.tasks, &self.interrupts);
+
+        // UART SWO sends 10 bits per byte
+        let swo_utilization = self
+            .swo_baud
+            .filter(|&baud| baud > 0)
+            .map(|baud| rates.bytes_per_sec * 10.0 / baud as f64);
+
+        let _ = self.event_sender.send(ServerMessage::Stats {
+            timestamp: Utc::now(),
+            events_per_sec: rates.events_per_sec,
+            bytes_per_sec: rates.bytes_per_sec,
+            drop_rate: rates.drop_rate,
+            cpu_load: utilization.cpu_load,
+            swo_utilization,
+            ports: rates.ports,
+            queues: QueueDepths {
+                source: self.source_backlog.map(|depth| depth as u64),
+                outgoing: self.outgoing_queue.as_ref().map(|queue| queue.get() as u64),
+            },
+        });
+        if self.utilization.is_active() {
+            let _ = self.event_sender.send(ServerMessage::Utilization {
+                timestamp: Utc::now(),
+                window_cycles: utilization.window_cycles,
+                cpu_load: utilization.cpu_load,
+                tasks: utilization.tasks,
+                isrs: utilization.isrs,
+            });
+        }
     }
 
     pub fn get_stats(&self) -> SessionStats {
-        // Return a copy of current stats
-        SessionStats {
-            events_processed: self.stats.events_processed,
-            bytes_processed: self.stats.bytes_processed,
-            dropped_events: self.stats.dropped_events,
-            start_time: self.stats.start_time,
+        self.stats.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_overflow_is_counted_and_reported() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut session = ItmSession::new(tx);
+        session.start_tracing(0x0F, None).await.unwrap();
+
+        // Marker on port 2, then an overflow packet
+        session.process_data(&[0x13, 1, 0, 0, 0, 0x70]).unwrap();
+
+        let stats = session.get_stats();
+        assert_eq!(stats.events_processed, 1);
+        assert_eq!(stats.overflow_packets, 1);
+        assert_eq!(stats.dropped_events, 1);
+        assert_eq!(stats.drop_rate(), 0.5);
+        assert_eq!(stats.ports[&2], PortCounters { events: 1, bytes: 4 });
+
+        assert!(matches!(
+            rx.recv().await,
+            Some(ServerMessage::Event { port: 2, event: TraceEvent::Marker { id: 1, .. }, .. })
+        ));
+        assert!(matches!(
+            rx.recv().await,
+            Some(ServerMessage::Event { event: TraceEvent::Overflow, .. })
+        ));
+        assert!(matches!(
+            rx.recv().await,
+            Some(ServerMessage::Stats { drop_rate, .. }) if drop_rate == 0.5
+        ));
+
+        // A restart begins counting from zero
+        session.stop_tracing().await.unwrap();
+        session.start_tracing(0x0F, None).await.unwrap();
+        let stats = session.get_stats();
+        assert!(stats.start_time.is_some());
+        assert_eq!(stats.events_processed, 0);
+        assert_eq!(stats.bytes_processed, 0);
+        assert_eq!(stats.dropped_events, 0);
+        assert_eq!(stats.overflow_packets, 0);
+        assert!(stats.ports.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_desync_is_reported_to_clients() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut session = ItmSession::new(tx);
+        session.start_tracing(0x0F, None).await.unwrap();
+
+        session
+            .process_data(&[0x04, 0xAA, 0, 0, 0, 0, 0, 0x80])
+            .unwrap();
+
+        let stats = session.get_stats();
+        assert_eq!(stats.desyncs, 1);
+        assert_eq!(stats.discarded_bytes, 2);
+        assert!(matches!(
+            rx.recv().await,
+            Some(ServerMessage::Error { code: Some(code), .. }) if code == "ITM_DESYNC"
+        ));
+    }
+
+    struct FailingDecoder;
+
+    impl ItmDecoder for FailingDecoder {
+        fn decode(&mut self, _port: u8, _data: &[u8], _timestamp: u64) -> Result<Vec<TraceEvent>> {
+            anyhow::bail!("bad data")
+        }
+
+        fn reset(&mut self) {}
+    }
+
+    #[tokio::test]
+    async fn test_packets_waiting_for_a_timestamp_are_flushed_on_stop() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut session = ItmSession::new(tx);
+        session.start_tracing(0x0F, None).await.unwrap();
+
+        // A local timestamp, then a marker on port 2 with none after it
+        session.process_data(&[0x10, 0x13, 1, 0, 0, 0]).unwrap();
+        assert!(rx.try_recv().is_err());
+
+        session.stop_tracing().await.unwrap();
+        assert!(matches!(
+            rx.recv().await,
+            Some(ServerMessage::Event { port: 2, event: TraceEvent::Marker { id: 1, .. }, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_decoder_error_does_not_stop_the_batch() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut session = ItmSession::new(tx);
+        session.start_tracing(0x0F, None).await.unwrap();
+        session.decoders.insert(1, Box::new(FailingDecoder));
+
+        // A write to the failing port 1, then a marker on port 2
+        session
+            .process_data(&[0x09, 0xAA, 0x13, 1, 0, 0, 0])
+            .unwrap();
+
+        assert!(matches!(
+            rx.recv().await,
+            Some(ServerMessage::Error { code: Some(code), message, .. })
+                if code == "DECODE_ERROR" && message == "Port 1: bad data"
+        ));
+        assert!(matches!(
+            rx.recv().await,
+            Some(ServerMessage::Event { port: 2, event: TraceEvent::Marker { id: 1, .. }, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tpiu_frames_are_demultiplexed() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut session = ItmSession::new(tx);
+        session.set_tpiu_formatter(Some(1));
+        session.start_tracing(0x0F, None).await.unwrap();
+
+        // Marker on port 2 from ATB ID 1, interleaved with ATB ID 2 bytes
+        let frame = [
+            0x03, 0x13, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00, 0xEE, 0xDD, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x02,
+        ];
+        let mut stream = vec![0xFF, 0xFF, 0xFF, 0x7F];
+        stream.extend_from_slice(&frame);
+        session.process_data(&stream).unwrap();
+
+        assert!(matches!(
+            rx.recv().await,
+            Some(ServerMessage::Event { port: 2, event: TraceEvent::Marker { id: 1, .. }, .. })
+        ));
+        assert_eq!(session.take_source_data(2), vec![0x00, 0xEE, 0xDD, 0, 0, 0, 0, 0]);
+        assert!(session.take_source_data(2).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_spans_are_paired_and_closed_on_stop() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut session = ItmSession::new(tx);
+        session.start_tracing(0x0F, None).await.unwrap();
+
+        // Span 9 begins and ends on port 2, then span 4 begins
+        session
+            .process_data(&[0x11, 0x01, 0x13, 9, 0, 0, 0, 0x11, 0x02, 0x13, 9, 0, 0, 0])
+            .unwrap();
+        session.process_data(&[0x11, 0x01, 0x13, 4, 0, 0, 0]).unwrap();
+        session.stop_tracing().await.unwrap();
+
+        let mut events = Vec::new();
+        while let Ok(ServerMessage::Event { port, event, .. }) = rx.try_recv() {
+            assert_eq!(port, 2);
+            events.push(event);
+        }
+        assert!(matches!(
+            events[..],
+            [
+                TraceEvent::SpanBegin { span_id: 9, .. },
+                TraceEvent::SpanEnd { span_id: 9, .. },
+                TraceEvent::Span { span_id: 9, depth: 0, .. },
+                TraceEvent::SpanBegin { span_id: 4, .. },
+                TraceEvent::SpanDiagnostic { span_id: 4, problem: SpanProblem::Unclosed, .. },
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_utilization_is_sent_with_stats() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut session = ItmSession::new(tx);
+        session.start_tracing(0x0F, None).await.unwrap();
+        let mut source = MockSource::new();
+        for _ in 0..8 {
+            let chunk = source.read().await.unwrap().unwrap();
+            session.process_data(&chunk.data).unwrap();
+        }
+        session.send_stats();
+
+        let mut stats_load = None;
+        let mut utilization = None;
+        while let Ok(message) = rx.try_recv() {
+            match message {
+                ServerMessage::Stats { cpu_load, .. } => stats_load = cpu_load,
+                ServerMessage::Utilization { cpu_load, tasks, isrs, .. } => {
+                    utilization = Some((cpu_load, tasks, isrs))
+                }
+                _ => {}
+            }
         }
+        let (cpu_load, tasks, isrs) = utilization.expect("Utilization message");
+        // The mock idles for one chunk in four
+        let load = cpu_load.unwrap();
+        assert!(load > 0.6 && load < 0.9, "{}", load);
+        assert_eq!(stats_load, cpu_load);
+        assert!(tasks.iter().any(|task| task.name.as_deref() == Some("sensor")));
+        assert_eq!(isrs[0].irq, Some(28));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_ingest_task_feeds_the_pipeline() {
+        let path = std::env::temp_dir().join(format!("callisto-ingest-{}.itm", std::process::id()));
+        // A local timestamp and a marker on port 2, from a capture file
+        // that then ends before the marker's timestamp
+        std::fs::write(&path, [0x10, 0x13, 1, 0, 0, 0]).unwrap();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut session = ItmSession::new(tx);
+        session.set_source(SourceConfig::File(path.clone()));
+        let session = session.into_shared();
+        session.lock().await.start_tracing(0x0F, None).await.unwrap();
+
+        let message = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap();
+        assert!(matches!(
+            message,
+            Some(ServerMessage::Event { port: 2, event: TraceEvent::Marker { id: 1, .. }, .. })
+        ));
+
+        let mut session = session.lock().await;
+        session.stop_tracing().await.unwrap();
+        assert_eq!(session.get_stats().bytes_processed, 6);
+        assert!(session.get_stats().last_capture.is_some());
+        assert!(session.ingest.is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+//! Marker name dictionary
+//!
+//! Named markers are sent as a hash of the name (`simple_hash` in
+//! `callisto-trace`), so the names are recovered from a dictionary built
+//! from a config file, the firmware sources, or the firmware ELF.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::{debug, warn};
+
+/// ELF section holding the names used with named markers
+pub const MARKER_SECTION: &str = ".callisto_markers";
+
+/// Source file extensions scanned for named markers
+const SOURCE_EXTENSIONS: &[&str] = &["rs", "c", "h", "cc", "cpp", "hpp"];
+
+/// Calls whose first string literal is a marker or span name
+const NAMED_CALLS: &[&str] = &["named_marker", "span_begin"];
+
+/// Directories skipped when scanning sources
+const SKIPPED_DIRS: &[&str] = &["target", "build", "node_modules", ".git"];
+
+/// Hash used by the firmware for named markers
+pub fn marker_hash(name: &str) -> u32 {
+    name.bytes().fold(0u32, |hash, byte| {
+        hash.wrapping_mul(31).wrapping_add(byte as u32)
+    })
+}
+
+/// Two different names that map to the same marker ID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarkerCollision {
+    pub id: u32,
+    pub existing: String,
+    pub name: String,
+}
+
+/// Marker config file: a list of names, or explicit `{ "id": "name" }`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MarkerFile {
+    Names(Vec<String>),
+    Ids(HashMap<String, String>),
+}
+
+/// Marker IDs and the names they stand for
+#[derive(Debug, Clone, Default)]
+pub struct MarkerDictionary {
+    names: HashMap<u32, String>,
+    collisions: Vec<MarkerCollision>,
+}
+
+impl MarkerDictionary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a name sent with `named_marker`
+    pub fn insert_name(&mut self, name: &str) {
+        self.insert(marker_hash(name), name);
+    }
+
+    /// Add a name for an explicit marker ID
+    ///
+    /// If the ID already has a different name the first one is kept and
+    /// the collision is recorded.
+    pub fn insert(&mut self, id: u32, name: &str) {
+        match self.names.get(&id) {
+            Some(existing) if existing == name => {}
+            Some(existing) => {
+                warn!(
+                    "Marker ID 0x{:08x} is used by both `{}` and `{}`",
+                    id, existing, name
+                );
+                let collision = MarkerCollision {
+                    id,
+                    existing: existing.clone(),
+                    name: name.to_string(),
+                };
+                if !self.collisions.contains(&collision) {
+                    self.collisions.push(collision);
+                }
+            }
+            None => {
+                self.names.insert(id, name.to_string());
+            }
+        }
+    }
+
+    /// Add every entry of another dictionary
+    pub fn extend(&mut self, other: &MarkerDictionary) {
+        for (&id, name) in &other.names {
+            self.insert(id, name);
+        }
+        for collision in &other.collisions {
+            if !self.collisions.contains(collision) {
+                self.collisions.push(collision.clone());
+            }
+        }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&str> {
+        self.names.get(&id).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Names that hash to an ID already taken by another name
+    pub fn collisions(&self) -> &[MarkerCollision] {
+        &self.collisions
+    }
+
+    /// Load a JSON config file
+    ///
+    /// The file is either a list of marker names, or an object mapping
+    /// IDs (decimal or `0x` hex) to names.
+    pub fn load_file(&mut self, path: &Path) -> Result<()> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        self.parse_file(&json)
+            .with_context(|| format!("Invalid marker file {}", path.display()))
+    }
+
+    fn parse_file(&mut self, json: &str) -> Result<()> {
+        match serde_json::from_str(json)? {
+            MarkerFile::Names(names) => {
+                for name in names {
+                    self.insert_name(&name);
+                }
+            }
+            MarkerFile::Ids(ids) => {
+                for (key, name) in ids {
+                    let id = match key.strip_prefix("0x").or_else(|| key.strip_prefix("0X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16),
+                        None => key.parse(),
+                    }
+                    .map_err(|_| anyhow::anyhow!("`{}` is not a valid marker ID", key))?;
+                    self.insert(id, &name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Scan a source tree for named marker and span calls with literal
+    /// names
+    pub fn scan_sources(&mut self, root: &Path) -> Result<()> {
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let entries = std::fs::read_dir(&dir)
+                .with_context(|| format!("Failed to read {}", dir.display()))?;
+            for entry in entries {
+                let path = entry?.path();
+                let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                if path.is_dir() {
+                    if !SKIPPED_DIRS.contains(&name) {
+                        dirs.push(path);
+                    }
+                    continue;
+                }
+                let is_source = path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| SOURCE_EXTENSIONS.contains(&e));
+                if !is_source {
+                    continue;
+                }
+                match std::fs::read_to_string(&path) {
+                    Ok(source) => self.scan_source(&source),
+                    Err(e) => debug!("Skipping {}: {}", path.display(), e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the string literal in each `named_marker(...)` and
+    /// `span_begin(...)` call
+    ///
+    /// Matches `named_marker`, `trace_named_marker!`,
+    /// `callisto_named_marker` and `CALLISTO_NAMED_MARKER`, and likewise
+    /// for spans.
+    fn scan_source(&mut self, source: &str) {
+        let lower = source.to_ascii_lowercase();
+        for call_name in NAMED_CALLS {
+            let mut start = 0;
+            while let Some(pos) = lower[start..].find(call_name) {
+                let call = start + pos + call_name.len();
+                start = call;
+
+                let args = source[call..].trim_start_matches('!').trim_start();
+                let Some(args) = args.strip_prefix('(') else {
+                    continue;
+                };
+                let args = &args[..args.find(')').unwrap_or(args.len())];
+                if let Some(name) = string_literal(args) {
+                    self.insert_name(&name);
+                }
+            }
+        }
+    }
+
+    /// Read the names from the `.callisto_markers` section of an ELF
+    ///
+    /// Returns `None` if the ELF has no such section.
+    pub fn parse_elf(data: &[u8]) -> Result<Option<Self>> {
+        use object::{Object, ObjectSection};
+
+        let file = object::File::parse(data).context("Not a valid ELF file")?;
+        let Some(section) = file.section_by_name(MARKER_SECTION) else {
+            return Ok(None);
+        };
+        let bytes = section
+            .data()
+            .with_context(|| format!("Failed to read {}", MARKER_SECTION))?;
+
+        let mut markers = Self::new();
+        for name in bytes.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+            markers.insert_name(&String::from_utf8_lossy(name));
+        }
+        Ok(Some(markers))
+    }
+}
+
+/// First string literal in a call's arguments, with simple escapes
+fn string_literal(args: &str) -> Option<String> {
+    let mut chars = args[args.find('"')? + 1..].chars();
+    let mut value = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                '0' => value.push('\0'),
+                other => value.push(other),
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_matches_firmware() {
+        // "test" with the firmware's simple_hash
+        assert_eq!(marker_hash("test"), 3556498);
+    }
+
+    #[test]
+    fn test_scan_sources() {
+        let mut markers = MarkerDictionary::new();
+        markers.scan_source(
+            r#"
+            itm.markers().named_marker("boot");
+            trace_named_marker!(itm, "sensor \"read\"");
+            CALLISTO_NAMED_MARKER("idle");
+            trace_span_begin!(itm, "frame");
+            itm.markers().span_begin(SPAN_ID);
+            fn named_marker(&self, name: &str) {}
+            "#,
+        );
+
+        assert_eq!(markers.len(), 4);
+        assert_eq!(markers.get(marker_hash("frame")), Some("frame"));
+        assert_eq!(markers.get(marker_hash("boot")), Some("boot"));
+        assert_eq!(
+            markers.get(marker_hash("sensor \"read\"")),
+            Some("sensor \"read\"")
+        );
+    }
+
+    #[test]
+    fn test_collisions_are_reported() {
+        let mut markers = MarkerDictionary::new();
+        markers.parse_file(r#"["Aa", "BB", "Aa"]"#).unwrap();
+
+        // "Aa" and "BB" hash to the same ID
+        assert_eq!(markers.len(), 1);
+        assert_eq!(
+            markers.collisions(),
+            [MarkerCollision {
+                id: marker_hash("Aa"),
+                existing: "Aa".to_string(),
+                name: "BB".to_string(),
+            }]
+        );
+
+        markers.parse_file(r#"{"0x10": "explicit"}"#).unwrap();
+        assert_eq!(markers.get(0x10), Some("explicit"));
+    }
+}
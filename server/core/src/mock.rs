@@ -1,168 +1,281 @@
-//! Mock data generator for testing and demonstration
-
-use callisto_protocol::{ServerMessage, TraceEvent};
-use chrono::Utc;
-use std::time::{Duration, Instant};
-use tokio::sync::mpsc;
-use tokio::time::sleep;
-use tracing::debug;
-
-/// Mock data generator for testing the UI without hardware
-pub struct MockDataGenerator {
-    sender: mpsc::UnboundedSender<ServerMessage>,
-    start_time: Instant,
-    task_counter: u32,
-    marker_counter: u32,
+//! Mock trace source for testing and demonstration
+//!
+//! Generates the ITM byte stream a target using `callisto-trace` would
+//! send, so mock data exercises the same parsing and decoding as a probe.
+
+use crate::decoder::{rtos_events, span_events};
+use crate::markers::marker_hash;
+use crate::source::{TraceChunk, TraceSource};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio::time::{interval, Interval, MissedTickBehavior};
+
+/// Host time between generated chunks
+const MOCK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Target cycles per chunk, as for a 168MHz core
+const CYCLES_PER_CHUNK: u32 = 1_680_000;
+
+/// Simulated tasks: ID, priority and name
+const MOCK_TASKS: &[(u32, u32, &str)] = &[(1, 0, "idle"), (2, 3, "sensor"), (3, 2, "comms")];
+
+/// Simulated timer interrupt (TIM2 on STM32F4)
+const MOCK_IRQ: u32 = 28;
+
+/// Names of the spans the mock target sends, for the marker dictionary
+pub const MOCK_MARKER_NAMES: &[&str] = &[MOCK_SPAN];
+
+const MOCK_SPAN: &str = "mock_work";
+
+const MOCK_MESSAGES: &[&str] = &[
+    "System initialized",
+    "Processing data...",
+    "Task completed successfully",
+    "[WARN] Low memory",
+    "[DEBUG] Function called",
+];
+
+/// Mock source producing ITM traffic on the standard ports
+pub struct MockSource {
+    interval: Interval,
+    chunk_count: u32,
+    current_task: u32,
 }
 
-impl MockDataGenerator {
-    pub fn new(sender: mpsc::UnboundedSender<ServerMessage>) -> Self {
+impl MockSource {
+    pub fn new() -> Self {
+        let mut interval = interval(MOCK_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
         Self {
-            sender,
-            start_time: Instant::now(),
-            task_counter: 0,
-            marker_counter: 0,
+            interval,
+            chunk_count: 0,
+            current_task: MOCK_TASKS[0].0,
         }
     }
 
-    /// Start generating mock data
-    pub async fn start(&mut self) {
-        debug!("Starting mock data generation");
-        
-        let mut interval = tokio::time::interval(Duration::from_millis(100));
-        
-        loop {
-            interval.tick().await;
-            
-            let timestamp = self.start_time.elapsed().as_micros() as u64;
-            
-            // Generate various types of events
-            if self.task_counter % 10 == 0 {
-                self.send_task_switch_event(timestamp).await;
-            }
-            
-            if self.task_counter % 15 == 0 {
-                self.send_marker_event(timestamp).await;
-            }
-            
-            if self.task_counter % 20 == 0 {
-                self.send_text_event(timestamp).await;
-            }
-            
-            if self.task_counter % 25 == 0 {
-                self.send_isr_event(timestamp).await;
-            }
-            
-            if self.task_counter % 50 == 0 {
-                self.send_counter_event(timestamp).await;
-            }
-            
-            if self.task_counter % 100 == 0 {
-                self.send_stats_update().await;
-            }
-            
-            self.task_counter += 1;
-            
-            // Add some randomness to timing
-            if self.task_counter % 7 == 0 {
-                sleep(Duration::from_millis(50)).await;
+    /// ITM bytes for the next chunk of simulated time
+    fn generate(&mut self) -> Vec<u8> {
+        let mut itm = ItmWriter::default();
+        let n = self.chunk_count;
+        self.chunk_count += 1;
+
+        if n == 0 {
+            for &(task_id, priority, name) in MOCK_TASKS {
+                itm.rtos(rtos_events::TASK_CREATE, task_id, priority);
+                itm.task_name(task_id, name);
             }
+            itm.timestamp(100);
         }
-    }
 
-    async fn send_task_switch_event(&mut self, timestamp: u64) {
-        let from_task = (self.task_counter % 4) + 1;
-        let to_task = ((self.task_counter + 1) % 4) + 1;
-        
-        let event = ServerMessage::Event {
-            timestamp,
-            port: 1,
-            event: TraceEvent::TaskSwitch { from_task, to_task },
+        // Round-robin over the non-idle tasks, idling every fourth chunk
+        let next = if n % 4 == 3 {
+            MOCK_TASKS[0].0
+        } else {
+            MOCK_TASKS[1 + n as usize % (MOCK_TASKS.len() - 1)].0
         };
-        
-        let _ = self.sender.send(event);
+        // Released a little before it runs, as by a timer tick
+        if next != self.current_task {
+            itm.rtos(rtos_events::TASK_READY, next, 0);
+            itm.timestamp(400 + (n % 5) * 150);
+        }
+        itm.rtos(rtos_events::TASK_SWITCH, self.current_task, next);
+        self.current_task = next;
+        itm.timestamp(2_000);
+
+        // A local timestamp dates the packets before it, so each delta is
+        // the time from the previous packets up to these
+        if n.is_multiple_of(10) {
+            itm.span(span_events::SPAN_BEGIN, MOCK_SPAN);
+            itm.timestamp(100);
+        }
+        if n.is_multiple_of(5) {
+            itm.rtos(rtos_events::ISR_ENTER, MOCK_IRQ, 0);
+            itm.timestamp(20_000 + (n % 7) * 1_000);
+            itm.rtos(rtos_events::ISR_EXIT, MOCK_IRQ, 0);
+            itm.timestamp(1_500 + (n % 3) * 100);
+        }
+        if n.is_multiple_of(10) {
+            itm.span(span_events::SPAN_END, MOCK_SPAN);
+            itm.timestamp(20_000);
+        }
+        if n.is_multiple_of(15) {
+            itm.write32(2, n / 15 + 1);
+            itm.timestamp(100);
+        }
+        if n.is_multiple_of(20) {
+            let message = MOCK_MESSAGES[(n / 20) as usize % MOCK_MESSAGES.len()];
+            itm.text(0, &format!("{}\n", message));
+            itm.timestamp(300);
+        }
+        if n.is_multiple_of(50) {
+            itm.write32(3, 1);
+            let value = n as u64 * 1000;
+            itm.write32(3, value as u32);
+            itm.write32(3, (value >> 32) as u32);
+            itm.timestamp(100);
+        }
+
+        itm.timestamp(CYCLES_PER_CHUNK.saturating_sub(itm.elapsed));
+        itm.bytes
     }
+}
 
-    async fn send_marker_event(&mut self, timestamp: u64) {
-        self.marker_counter += 1;
-        
-        let event = ServerMessage::Event {
-            timestamp,
-            port: 2,
-            event: TraceEvent::Marker {
-                id: self.marker_counter,
-                name: Some(format!("Checkpoint {}", self.marker_counter)),
-            },
-        };
-        
-        let _ = self.sender.send(event);
+impl Default for MockSource {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    async fn send_text_event(&mut self, timestamp: u64) {
-        let messages = [
-            "System initialized",
-            "Processing data...",
-            "Task completed successfully",
-            "Warning: Low memory",
-            "Debug: Function called",
-        ];
-        
-        let message = messages[self.task_counter as usize % messages.len()].to_string();
-        
-        let event = ServerMessage::Event {
-            timestamp,
-            port: 0,
-            event: TraceEvent::Text { message },
-        };
-        
-        let _ = self.sender.send(event);
+#[async_trait]
+impl TraceSource for MockSource {
+    fn describe(&self) -> String {
+        "mock target".to_string()
     }
 
-    async fn send_isr_event(&mut self, timestamp: u64) {
-        let isr_id = (self.task_counter % 3) + 10; // ISR IDs 10, 11, 12
-        
-        // Send ISR enter
-        let enter_event = ServerMessage::Event {
-            timestamp,
-            port: 1,
-            event: TraceEvent::IsrEnter {
-                isr_id,
-                name: Some(format!("Timer{}", isr_id - 9)),
-            },
-        };
-        let _ = self.sender.send(enter_event);
-        
-        // Send ISR exit after a short delay
-        let exit_event = ServerMessage::Event {
-            timestamp: timestamp + 500, // 500 microseconds later
-            port: 1,
-            event: TraceEvent::IsrExit { isr_id },
-        };
-        let _ = self.sender.send(exit_event);
+    async fn read(&mut self) -> Result<Option<TraceChunk>> {
+        self.interval.tick().await;
+        Ok(Some(TraceChunk::now(self.generate())))
     }
+}
 
-    async fn send_counter_event(&mut self, timestamp: u64) {
-        let counter_id = 1;
-        let value = (self.task_counter as u64 * 1000) + (timestamp % 1000);
-        
-        let event = ServerMessage::Event {
-            timestamp,
-            port: 3,
-            event: TraceEvent::Counter { counter_id, value },
+/// Encodes stimulus port writes and local timestamps as ITM packets
+#[derive(Default)]
+struct ItmWriter {
+    bytes: Vec<u8>,
+    elapsed: u32,
+}
+
+impl ItmWriter {
+    fn stimulus(&mut self, port: u8, data: &[u8]) {
+        let size = match data.len() {
+            1 => 1,
+            2 => 2,
+            _ => 3,
         };
-        
-        let _ = self.sender.send(event);
+        self.bytes.push(port << 3 | size);
+        self.bytes.extend_from_slice(data);
+    }
+
+    fn write8(&mut self, port: u8, value: u8) {
+        self.stimulus(port, &[value]);
+    }
+
+    fn write32(&mut self, port: u8, value: u32) {
+        self.stimulus(port, &value.to_le_bytes());
+    }
+
+    fn rtos(&mut self, event: u8, a: u32, b: u32) {
+        self.write8(1, event);
+        self.write32(1, a);
+        self.write32(1, b);
+    }
+
+    fn task_name(&mut self, task_id: u32, name: &str) {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.resize(bytes.len() / 4 * 4 + 4, 0);
+        for chunk in bytes.chunks(4) {
+            self.rtos(
+                rtos_events::TASK_NAME,
+                task_id,
+                u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]),
+            );
+        }
+    }
+
+    fn span(&mut self, record: u8, name: &str) {
+        self.write8(2, record);
+        self.write32(2, marker_hash(name));
+    }
+
+    fn text(&mut self, port: u8, text: &str) {
+        for chunk in text.as_bytes().chunks(4) {
+            if chunk.len() == 4 {
+                self.stimulus(port, chunk);
+            } else {
+                for &byte in chunk {
+                    self.write8(port, byte);
+                }
+            }
+        }
     }
 
-    async fn send_stats_update(&mut self) {
-        let stats = ServerMessage::Stats {
-            timestamp: Utc::now(),
-            events_per_sec: 50.0 + (self.task_counter as f64 % 20.0),
-            bytes_per_sec: 1024.0 + (self.task_counter as f64 * 10.0 % 500.0),
-            drop_rate: if self.task_counter % 200 == 0 { 0.1 } else { 0.0 },
-            cpu_load: Some(0.3 + (self.task_counter as f64 % 100.0) / 200.0),
+    /// Local timestamp packet (LTS1) for the packets written since the
+    /// last one
+    fn timestamp(&mut self, delta: u32) {
+        let delta = delta.clamp(1, (1 << 28) - 1);
+        self.elapsed += delta;
+        self.bytes.push(0xC0);
+        let mut value = delta;
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.bytes.push(byte);
+                break;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ItmSession;
+    use callisto_protocol::{ServerMessage, TraceEvent};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_mock_stream_decodes() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut session = ItmSession::new(tx);
+        session.start_tracing(0x0F, None).await.unwrap();
+
+        let mut source = MockSource::new();
+        for _ in 0..20 {
+            let chunk = source.read().await.unwrap().unwrap();
+            session.process_data(&chunk.data).unwrap();
+        }
+
+        let mut events = Vec::new();
+        while let Ok(message) = rx.try_recv() {
+            match message {
+                ServerMessage::Event { event, .. } => events.push(event),
+                ServerMessage::Error { message, .. } => panic!("{}", message),
+                _ => {}
+            }
+        }
+        assert!(events.iter().any(|e| matches!(
+            e,
+            TraceEvent::TaskSwitch { to_name: Some(name), .. } if name == "sensor"
+        )));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            TraceEvent::Span { name: None, duration, .. } if *duration > 40_000
+        )));
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, TraceEvent::Marker { id: 1, .. })));
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, TraceEvent::Text { message } if message == "System initialized")));
+
+        let ServerMessage::SchedulingReport { tasks, .. } = session.scheduling_report() else {
+            unreachable!()
+        };
+        let sensor = tasks
+            .iter()
+            .find(|task| task.name.as_deref() == Some("sensor"))
+            .unwrap();
+        assert!(sensor.jobs > 0);
+        assert!(sensor.activation_latency.as_ref().unwrap().min >= 400);
+
+        let ServerMessage::IsrTimingReport { isrs, .. } = session.isr_timing_report() else {
+            unreachable!()
         };
-        
-        let _ = self.sender.send(stats);
+        assert_eq!(isrs[0].irq, Some(MOCK_IRQ));
+        assert_eq!(isrs[0].duration.count, 4);
+        assert_eq!((isrs[0].duration.min, isrs[0].duration.max), (1_500, 1_700));
     }
-}
\ No newline at end of file
+}
@@ -1,9 +1,58 @@
 //! Probe management and probe-rs integration
 
-use anyhow::Result;
+use crate::source::{TraceChunk, TraceSource};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
 use callisto_protocol::ProbeInfo;
+use probe_rs::architecture::arm::component::TraceSink;
+use probe_rs::architecture::arm::SwoConfig;
+use probe_rs::probe::list::Lister;
+use probe_rs::probe::DebugProbeSelector;
+use probe_rs::{MemoryInterface, Permissions, Session};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
 use tracing::{debug, info, warn};
 
+/// SWO baud rate used when neither the config nor `Start` gives one
+pub const DEFAULT_SWO_BAUD: u32 = 2_000_000;
+
+/// TPIU input clock assumed when none is configured, in Hz
+pub const DEFAULT_TPIU_CLOCK_HZ: u32 = 168_000_000;
+
+/// ITM Trace Enable Register, one bit per stimulus port
+const ITM_TER: u64 = 0xE000_0E00;
+
+/// Pause between SWO polls that returned no data
+const SWO_POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// SWO chunks buffered between the probe thread and the ingest task
+const SWO_CHANNEL_DEPTH: usize = 256;
+
+/// How to reach the target through a debug probe
+#[derive(Debug, Clone)]
+pub struct ProbeConfig {
+    /// probe-rs probe selector (`VID:PID` or `VID:PID:serial`); the first
+    /// probe found if unset
+    pub selector: Option<String>,
+    /// probe-rs target name, e.g. `STM32F407VGTx`
+    pub chip: Option<String>,
+    /// Default SWO baud rate
+    pub baud: u32,
+    /// TPIU input clock, usually the core clock, in Hz
+    pub tpiu_clock_hz: u32,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            selector: None,
+            chip: None,
+            baud: DEFAULT_SWO_BAUD,
+            tpiu_clock_hz: DEFAULT_TPIU_CLOCK_HZ,
+        }
+    }
+}
+
 /// Manages probe connections and ITM data collection
 pub struct ProbeManager {
     active_session: Option<ProbeSession>,
@@ -11,7 +60,6 @@ pub struct ProbeManager {
 
 /// Active probe session
 pub struct ProbeSession {
-    // TODO: Add probe-rs session fields
     pub connected: bool,
     pub target: Option<String>,
     pub chip: Option<String>,
@@ -26,32 +74,45 @@ impl ProbeManager {
 
     /// List available probes
     pub async fn list_probes() -> Result<Vec<ProbeInfo>> {
-        // TODO: Implement with probe-rs
         info!("Listing available probes");
-        
-        // Mock probe for now
-        Ok(vec![ProbeInfo {
-            identifier: "mock:0001".to_string(),
-            vendor_id: 0x1234,
-            product_id: 0x5678,
-            serial_number: Some("MOCK001".to_string()),
-            hid_interface: None,
-        }])
+
+        let probes = tokio::task::spawn_blocking(|| Lister::new().list_all()).await?;
+        Ok(probes
+            .into_iter()
+            .map(|probe| ProbeInfo {
+                identifier: probe.identifier,
+                vendor_id: probe.vendor_id,
+                product_id: probe.product_id,
+                serial_number: probe.serial_number,
+                hid_interface: probe.hid_interface,
+            })
+            .collect())
     }
 
-    /// Start a new probe session
-    pub async fn start_session(&mut self, allow_mask: u32, baud_rate: Option<u32>) -> Result<()> {
-        info!("Starting probe session with mask: 0x{:08x}, baud: {:?}", allow_mask, baud_rate);
-        
-        // TODO: Implement probe-rs connection
-        // For now, create a mock session
+    /// Attach to the target and start SWO capture
+    ///
+    /// `allow_mask` enables stimulus ports in ITM_TER. With
+    /// `tpiu_formatting` the TPIU wraps ITM data in formatter frames.
+    pub async fn start_session(
+        &mut self,
+        config: &ProbeConfig,
+        allow_mask: u32,
+        baud_rate: Option<u32>,
+        tpiu_formatting: bool,
+    ) -> Result<ProbeSource> {
+        let baud = baud_rate.unwrap_or(config.baud);
+        info!(
+            "Starting probe session with mask: 0x{:08x}, baud: {}",
+            allow_mask, baud
+        );
+
+        let source = ProbeSource::start(config.clone(), allow_mask, baud, tpiu_formatting).await?;
         self.active_session = Some(ProbeSession {
             connected: true,
-            target: Some("Mock Target".to_string()),
-            chip: Some("STM32F4xx".to_string()),
+            target: Some(source.target.clone()),
+            chip: config.chip.clone(),
         });
-
-        Ok(())
+        Ok(source)
     }
 
     /// Stop the current probe session
@@ -63,7 +124,7 @@ impl ProbeManager {
 
     /// Check if a session is active
     pub fn is_connected(&self) -> bool {
-        self.active_session.as_ref().map_or(false, |s| s.connected)
+        self.active_session.as_ref().is_some_and(|s| s.connected)
     }
 
     /// Get current session info
@@ -76,4 +137,122 @@ impl Default for ProbeManager {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+/// SWO data captured by a debug probe
+///
+/// probe-rs is blocking, so the probe is polled on its own thread. Dropping
+/// the source stops the thread, which disables SWV and detaches.
+pub struct ProbeSource {
+    target: String,
+    chunks: mpsc::Receiver<Result<TraceChunk>>,
+}
+
+impl ProbeSource {
+    async fn start(
+        config: ProbeConfig,
+        allow_mask: u32,
+        baud: u32,
+        tpiu_formatting: bool,
+    ) -> Result<Self> {
+        let (opened_tx, opened) = oneshot::channel();
+        let (chunks_tx, chunks) = mpsc::channel(SWO_CHANNEL_DEPTH);
+
+        std::thread::Builder::new()
+            .name("callisto-swo".to_string())
+            .spawn(move || {
+                let session = match attach(&config, allow_mask, baud, tpiu_formatting) {
+                    Ok(session) => session,
+                    Err(e) => {
+                        let _ = opened_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = opened_tx.send(Ok(session.target().name.clone()));
+                poll_swo(session, chunks_tx);
+            })
+            .context("Failed to start the SWO thread")?;
+
+        let target = opened.await.context("SWO thread exited")??;
+        Ok(Self { target, chunks })
+    }
+}
+
+#[async_trait]
+impl TraceSource for ProbeSource {
+    fn describe(&self) -> String {
+        format!("probe attached to {}", self.target)
+    }
+
+    async fn read(&mut self) -> Result<Option<TraceChunk>> {
+        self.chunks.recv().await.transpose()
+    }
+
+    fn backlog(&self) -> Option<usize> {
+        Some(self.chunks.len())
+    }
+}
+
+fn attach(
+    config: &ProbeConfig,
+    allow_mask: u32,
+    baud: u32,
+    tpiu_formatting: bool,
+) -> Result<Session> {
+    let lister = Lister::new();
+    let probe = match &config.selector {
+        Some(selector) => {
+            let selector = DebugProbeSelector::try_from(selector.as_str())?;
+            lister.open(selector)?
+        }
+        None => lister
+            .list_all()
+            .first()
+            .context("No debug probe found")?
+            .open()?,
+    };
+
+    let chip = config
+        .chip
+        .as_deref()
+        .context("A target chip is needed to trace with a probe")?;
+    let mut session = probe
+        .attach(chip, Permissions::default())
+        .with_context(|| format!("Failed to attach to {}", chip))?;
+
+    let swo = SwoConfig::new(config.tpiu_clock_hz)
+        .set_baud(baud)
+        .set_continuous_formatting(tpiu_formatting);
+    session
+        .setup_tracing(0, TraceSink::Swo(swo))
+        .context("Failed to set up SWO tracing")?;
+    session.core(0)?.write_word_32(ITM_TER, allow_mask)?;
+    Ok(session)
+}
+
+fn poll_swo(mut session: Session, chunks: mpsc::Sender<Result<TraceChunk>>) {
+    loop {
+        match session.read_trace_data() {
+            Ok(data) if data.is_empty() => {
+                if chunks.is_closed() {
+                    break;
+                }
+                std::thread::sleep(SWO_POLL_INTERVAL);
+            }
+            Ok(data) => {
+                if chunks.blocking_send(Ok(TraceChunk::now(data))).is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                let _ = chunks.blocking_send(Err(anyhow!(e).context("Failed to read SWO data")));
+                break;
+            }
+        }
+    }
+
+    if let Err(e) = session.disable_swv(0) {
+        warn!("Failed to disable SWV: {}", e);
+    }
+    debug!("SWO thread stopped");
+}
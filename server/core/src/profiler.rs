@@ -0,0 +1,148 @@
+//! Statistical profiling from DWT periodic PC samples
+
+use callisto_protocol::{ProfileEntry, ServerMessage};
+use chrono::Utc;
+use std::collections::{HashMap, VecDeque};
+
+/// Number of samples kept in the sliding window by default
+pub const DEFAULT_PROFILE_WINDOW: usize = 16_384;
+
+/// Number of hottest addresses reported in a snapshot by default
+pub const DEFAULT_PROFILE_TOP: usize = 32;
+
+/// Sliding-window histogram of sampled PC addresses
+pub struct PcProfiler {
+    window: usize,
+    samples: VecDeque<Option<u32>>,
+    histogram: HashMap<u32, u64>,
+    sleep_samples: u64,
+}
+
+impl PcProfiler {
+    /// Create a profiler that keeps the last `window` samples
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            samples: VecDeque::new(),
+            histogram: HashMap::new(),
+            sleep_samples: 0,
+        }
+    }
+
+    /// Record one PC sample, `None` when the core was sleeping
+    pub fn record(&mut self, pc: Option<u32>) {
+        if self.samples.len() == self.window {
+            if let Some(evicted) = self.samples.pop_front() {
+                self.forget(evicted);
+            }
+        }
+
+        match pc {
+            Some(pc) => *self.histogram.entry(pc).or_insert(0) += 1,
+            None => self.sleep_samples += 1,
+        }
+        self.samples.push_back(pc);
+    }
+
+    fn forget(&mut self, pc: Option<u32>) {
+        match pc {
+            Some(pc) => {
+                if let Some(count) = self.histogram.get_mut(&pc) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.histogram.remove(&pc);
+                    }
+                }
+            }
+            None => self.sleep_samples -= 1,
+        }
+    }
+
+    /// Number of samples currently in the window
+    pub fn total_samples(&self) -> u64 {
+        self.samples.len() as u64
+    }
+
+    /// Fraction of samples in the window taken while sleeping
+    pub fn sleep_fraction(&self) -> f64 {
+        if self.samples.is_empty() {
+            0.0
+        } else {
+            self.sleep_samples as f64 / self.samples.len() as f64
+        }
+    }
+
+    /// The `top` most frequently sampled addresses, hottest first
+    pub fn hot_spots(&self, top: usize) -> Vec<ProfileEntry> {
+        let total = self.samples.len().max(1) as f64;
+        let mut entries: Vec<ProfileEntry> = self
+            .histogram
+            .iter()
+            .map(|(&pc, &samples)| ProfileEntry {
+                pc,
+                samples,
+                fraction: samples as f64 / total,
+            })
+            .collect();
+
+        entries.sort_by(|a, b| b.samples.cmp(&a.samples).then(a.pc.cmp(&b.pc)));
+        entries.truncate(top);
+        entries
+    }
+
+    /// Build a profile snapshot message for clients
+    pub fn snapshot(&self, top: usize) -> ServerMessage {
+        ServerMessage::Profile {
+            timestamp: Utc::now(),
+            total_samples: self.total_samples(),
+            sleep_fraction: self.sleep_fraction(),
+            hot_spots: self.hot_spots(top),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.histogram.clear();
+        self.sleep_samples = 0;
+    }
+}
+
+impl Default for PcProfiler {
+    fn default() -> Self {
+        Self::new(DEFAULT_PROFILE_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_and_sleep_fraction() {
+        let mut profiler = PcProfiler::new(8);
+        for pc in [Some(0x100), Some(0x100), Some(0x200), None] {
+            profiler.record(pc);
+        }
+
+        assert_eq!(profiler.total_samples(), 4);
+        assert_eq!(profiler.sleep_fraction(), 0.25);
+
+        let hot = profiler.hot_spots(1);
+        assert_eq!(hot.len(), 1);
+        assert_eq!(hot[0].pc, 0x100);
+        assert_eq!(hot[0].samples, 2);
+        assert_eq!(hot[0].fraction, 0.5);
+    }
+
+    #[test]
+    fn test_window_evicts_old_samples() {
+        let mut profiler = PcProfiler::new(2);
+        profiler.record(None);
+        profiler.record(Some(0x100));
+        profiler.record(Some(0x200));
+
+        assert_eq!(profiler.total_samples(), 2);
+        assert_eq!(profiler.sleep_fraction(), 0.0);
+        assert_eq!(profiler.hot_spots(10).len(), 2);
+    }
+}
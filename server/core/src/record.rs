@@ -0,0 +1,354 @@
+//! User-defined binary record formats
+//!
+//! A format is a list of `type name` fields separated by semicolons, for
+//! example `u8 id; u32 value; f32 temp; i16[3] accel`. Fields are packed
+//! with no padding and stored little-endian, matching a `#[repr(C, packed)]`
+//! struct written to a user port.
+
+use crate::decoder::ItmDecoder;
+use anyhow::{bail, Result};
+use callisto_protocol::{FieldValue, RecordField, TraceEvent};
+use std::collections::HashSet;
+
+/// Largest record a format may describe, in bytes
+pub const MAX_RECORD_SIZE: usize = 1024;
+
+/// Scalar types available in record formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "u8" => Self::U8,
+            "i8" => Self::I8,
+            "u16" => Self::U16,
+            "i16" => Self::I16,
+            "u32" => Self::U32,
+            "i32" => Self::I32,
+            "u64" => Self::U64,
+            "i64" => Self::I64,
+            "f32" => Self::F32,
+            "f64" => Self::F64,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::U8 => "u8",
+            Self::I8 => "i8",
+            Self::U16 => "u16",
+            Self::I16 => "i16",
+            Self::U32 => "u32",
+            Self::I32 => "i32",
+            Self::U64 => "u64",
+            Self::I64 => "i64",
+            Self::F32 => "f32",
+            Self::F64 => "f64",
+        }
+    }
+
+    /// Size in bytes
+    pub fn size(self) -> usize {
+        match self {
+            Self::U8 | Self::I8 => 1,
+            Self::U16 | Self::I16 => 2,
+            Self::U32 | Self::I32 | Self::F32 => 4,
+            Self::U64 | Self::I64 | Self::F64 => 8,
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> FieldValue {
+        let mut raw = [0u8; 8];
+        raw[..bytes.len()].copy_from_slice(bytes);
+        let raw = u64::from_le_bytes(raw);
+
+        match self {
+            Self::U8 | Self::U16 | Self::U32 | Self::U64 => FieldValue::Unsigned(raw),
+            Self::I8 => FieldValue::Signed(raw as u8 as i8 as i64),
+            Self::I16 => FieldValue::Signed(raw as u16 as i16 as i64),
+            Self::I32 => FieldValue::Signed(raw as u32 as i32 as i64),
+            Self::I64 => FieldValue::Signed(raw as i64),
+            Self::F32 => FieldValue::Float(f32::from_bits(raw as u32) as f64),
+            Self::F64 => FieldValue::Float(f64::from_bits(raw)),
+        }
+    }
+}
+
+/// One field of a record format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSpec {
+    pub name: String,
+    pub scalar: ScalarType,
+    /// Element count for array fields
+    pub count: Option<usize>,
+}
+
+impl FieldSpec {
+    /// Size in bytes
+    pub fn size(&self) -> usize {
+        self.scalar.size().saturating_mul(self.count.unwrap_or(1))
+    }
+
+    /// Type as written in the format
+    pub fn type_name(&self) -> String {
+        match self.count {
+            Some(count) => format!("{}[{}]", self.scalar.name(), count),
+            None => self.scalar.name().to_string(),
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> FieldValue {
+        match self.count {
+            Some(_) => FieldValue::Array(
+                bytes
+                    .chunks_exact(self.scalar.size())
+                    .map(|element| self.scalar.decode(element))
+                    .collect(),
+            ),
+            None => self.scalar.decode(bytes),
+        }
+    }
+}
+
+/// Parsed record format description
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordFormat {
+    fields: Vec<FieldSpec>,
+    size: usize,
+}
+
+impl RecordFormat {
+    /// Parse and validate a format description
+    pub fn parse(format: &str) -> Result<Self> {
+        let mut fields = Vec::new();
+        let mut names = HashSet::new();
+
+        for (index, field) in format.split(';').enumerate() {
+            let field = field.trim();
+            if field.is_empty() {
+                continue;
+            }
+
+            let mut parts = field.split_whitespace();
+            let (Some(ty), Some(name), None) = (parts.next(), parts.next(), parts.next()) else {
+                bail!("Field {}: expected `type name`, got `{}`", index + 1, field);
+            };
+
+            let (scalar, count) = parse_type(ty)
+                .map_err(|e| anyhow::anyhow!("Field {} (`{}`): {}", index + 1, name, e))?;
+
+            if !is_identifier(name) {
+                bail!("Field {}: `{}` is not a valid field name", index + 1, name);
+            }
+            if !names.insert(name) {
+                bail!("Field {}: duplicate field name `{}`", index + 1, name);
+            }
+
+            fields.push(FieldSpec {
+                name: name.to_string(),
+                scalar,
+                count,
+            });
+        }
+
+        if fields.is_empty() {
+            bail!("Record format has no fields");
+        }
+
+        let size = fields
+            .iter()
+            .try_fold(0usize, |size, field| {
+                field
+                    .scalar
+                    .size()
+                    .checked_mul(field.count.unwrap_or(1))
+                    .and_then(|field_size| size.checked_add(field_size))
+            })
+            .filter(|&size| size <= MAX_RECORD_SIZE);
+        let Some(size) = size else {
+            bail!("Record is larger than the maximum of {} bytes", MAX_RECORD_SIZE);
+        };
+
+        Ok(Self { fields, size })
+    }
+
+    pub fn fields(&self) -> &[FieldSpec] {
+        &self.fields
+    }
+
+    /// Size of one record in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Decode one record of exactly `size()` bytes
+    pub fn decode(&self, record: &[u8]) -> Vec<RecordField> {
+        let mut offset = 0;
+        self.fields
+            .iter()
+            .map(|field| {
+                let bytes = &record[offset..offset + field.size()];
+                offset += field.size();
+                RecordField {
+                    name: field.name.clone(),
+                    field_type: field.type_name(),
+                    value: field.decode(bytes),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Parse `u16` or `u16[4]`
+fn parse_type(ty: &str) -> Result<(ScalarType, Option<usize>)> {
+    let (scalar, count) = match ty.split_once('[') {
+        Some((scalar, rest)) => {
+            let Some(count) = rest.strip_suffix(']') else {
+                bail!("unterminated array length in `{}`", ty);
+            };
+            let count: usize = count
+                .parse()
+                .map_err(|_| anyhow::anyhow!("invalid array length in `{}`", ty))?;
+            if count == 0 {
+                bail!("array length must be at least 1");
+            }
+            if count > MAX_RECORD_SIZE {
+                bail!(
+                    "array length {} exceeds the maximum record size of {} bytes",
+                    count,
+                    MAX_RECORD_SIZE
+                );
+            }
+            (scalar, Some(count))
+        }
+        None => (ty, None),
+    };
+
+    match ScalarType::parse(scalar) {
+        Some(scalar) => Ok((scalar, count)),
+        None => bail!("unknown type `{}`", scalar),
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Decoder for ports with a user-defined record format
+///
+/// Records may be written with any mix of 8, 16 and 32-bit writes, so
+/// the payload is treated as a byte stream and split at the record size.
+pub struct UserDecoder {
+    format: RecordFormat,
+    buffer: Vec<u8>,
+}
+
+impl UserDecoder {
+    pub fn new(format: RecordFormat) -> Self {
+        Self {
+            format,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl ItmDecoder for UserDecoder {
+    fn decode(&mut self, _port: u8, data: &[u8], _timestamp: u64) -> Result<Vec<TraceEvent>> {
+        self.buffer.extend_from_slice(data);
+
+        let size = self.format.size();
+        let mut events = Vec::new();
+        while self.buffer.len() >= size {
+            let record: Vec<u8> = self.buffer.drain(..size).collect();
+            events.push(TraceEvent::Record {
+                fields: self.format.decode(&record),
+            });
+        }
+        Ok(events)
+    }
+
+    fn reset(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format() {
+        let format = RecordFormat::parse("u8 id; u32 value; f32 temp; i16[3] accel;").unwrap();
+        assert_eq!(format.size(), 1 + 4 + 4 + 6);
+        assert_eq!(format.fields().len(), 4);
+        assert_eq!(format.fields()[3].type_name(), "i16[3]");
+    }
+
+    #[test]
+    fn test_invalid_formats_are_rejected() {
+        for format in [
+            "",
+            "u8",
+            "u24 id",
+            "u8 id; u8 id",
+            "u8 1st",
+            "u8[0] empty",
+            "u8[x] bad",
+            "u8[2 open",
+            "u8[2000] big",
+            "u64[2305843009213693952] x",
+            "u64[1024] a; u64[1024] b",
+        ] {
+            assert!(RecordFormat::parse(format).is_err(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_overflowing_size_is_rejected() {
+        let err = RecordFormat::parse("u64[2305843009213693952] x").unwrap_err();
+        assert!(format!("{:#}", err).contains("array length"), "{:#}", err);
+        let err = RecordFormat::parse("u32[256] a; u8 b").unwrap_err();
+        assert!(format!("{:#}", err).contains("maximum"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_decode_records_across_writes() {
+        let format = RecordFormat::parse("u8 id; i16[2] accel; f32 temp").unwrap();
+        let mut decoder = UserDecoder::new(format);
+
+        let mut record = vec![7u8];
+        record.extend_from_slice(&(-2i16).to_le_bytes());
+        record.extend_from_slice(&300i16.to_le_bytes());
+        record.extend_from_slice(&1.5f32.to_le_bytes());
+
+        assert!(decoder.decode(4, &record[..4], 0).unwrap().is_empty());
+        let events = decoder.decode(4, &record[4..], 0).unwrap();
+
+        let TraceEvent::Record { fields } = &events[0] else {
+            panic!("expected a record, got {:?}", events);
+        };
+        assert_eq!(fields[0].value, FieldValue::Unsigned(7));
+        assert_eq!(
+            fields[1].value,
+            FieldValue::Array(vec![FieldValue::Signed(-2), FieldValue::Signed(300)])
+        );
+        assert_eq!(fields[2].value, FieldValue::Float(1.5));
+    }
+}
@@ -0,0 +1,270 @@
+//! Decoder registry built from the port configuration
+
+use crate::decoder::*;
+use crate::defmt::{DefmtDecoder, DefmtTable};
+use crate::markers::MarkerDictionary;
+use crate::record::{RecordFormat, UserDecoder};
+use anyhow::{bail, Context, Result};
+use callisto_protocol::{standard_ports, DecoderType, PortConfig};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Decoders for the enabled ports, keyed by port number
+pub type DecoderMap = HashMap<u8, Box<dyn ItmDecoder + Send>>;
+
+/// Port configuration, and the source of each port's decoder
+///
+/// This is the map reported to clients in `Meta`, so the UI always shows
+/// what the server actually decodes.
+pub struct DecoderRegistry {
+    ports: HashMap<u8, PortConfig>,
+    defmt: Option<Arc<DefmtTable>>,
+    printf: Option<Arc<PrintfTable>>,
+    text_idle_timeout: Duration,
+    markers: Arc<MarkerDictionary>,
+}
+
+impl DecoderRegistry {
+    pub fn new(ports: HashMap<u8, PortConfig>) -> Self {
+        Self {
+            ports,
+            defmt: None,
+            printf: None,
+            text_idle_timeout: DEFAULT_TEXT_IDLE_TIMEOUT,
+            markers: Arc::new(MarkerDictionary::new()),
+        }
+    }
+
+    /// Set the defmt string table used by `Defmt` ports
+    pub fn set_defmt_table(&mut self, table: Option<DefmtTable>) {
+        self.defmt = table.map(Arc::new);
+    }
+
+    /// Set the format strings used by `Printf` ports
+    pub fn set_printf_table(&mut self, table: Option<PrintfTable>) {
+        self.printf = table.map(Arc::new);
+    }
+
+    /// Set how long text ports hold a partial line before emitting it
+    pub fn set_text_idle_timeout(&mut self, timeout: Duration) {
+        self.text_idle_timeout = timeout;
+    }
+
+    /// Set the names used by `Marker` ports
+    pub fn set_marker_dictionary(&mut self, markers: MarkerDictionary) {
+        self.markers = Arc::new(markers);
+    }
+
+    /// Format strings used by `Printf` ports
+    pub fn printf_table(&self) -> Option<&PrintfTable> {
+        self.printf.as_deref()
+    }
+
+    /// Create the decoder for a decoder type
+    ///
+    /// Fails if a user-defined record format does not parse, or a defmt or
+    /// printf port is configured without its string table.
+    pub fn create_decoder(&self, decoder: &DecoderType) -> Result<Box<dyn ItmDecoder + Send>> {
+        Ok(match decoder {
+            DecoderType::Text => Box::new(TextDecoder::with_idle_timeout(self.text_idle_timeout)),
+            DecoderType::Marker => Box::new(MarkerDecoder::with_dictionary(self.markers.clone())),
+            DecoderType::TaskIsr => Box::new(TaskIsrDecoder::new()),
+            DecoderType::Counter => Box::new(CounterDecoder::new()),
+            DecoderType::Defmt => match &self.defmt {
+                Some(table) => Box::new(DefmtDecoder::new(table.clone())),
+                None => bail!("defmt decoding needs a firmware ELF with a .defmt section"),
+            },
+            DecoderType::Printf => match &self.printf {
+                Some(table) => Box::new(PrintfDecoder::new(table.clone())),
+                None => bail!("printf decoding needs a firmware ELF or printf dictionary"),
+            },
+            DecoderType::User { format } => {
+                Box::new(UserDecoder::new(RecordFormat::parse(format)?))
+            }
+        })
+    }
+
+    /// Current port configuration
+    pub fn ports(&self) -> &HashMap<u8, PortConfig> {
+        &self.ports
+    }
+
+    /// Replace the configuration of a single port
+    pub fn set_port(&mut self, config: PortConfig) {
+        self.ports.insert(config.port, config);
+    }
+
+    /// Build decoders for the enabled ports in `allow_mask`
+    ///
+    /// Allowed ports without a configuration are added as text ports.
+    pub fn build_decoders(&mut self, allow_mask: u32) -> Result<DecoderMap> {
+        for port in 0..32u8 {
+            if allow_mask & (1 << port) != 0 {
+                self.ports
+                    .entry(port)
+                    .or_insert_with(|| PortConfig::text_port(port, &format!("Port {}", port)));
+            }
+        }
+
+        let mut decoders = DecoderMap::new();
+        for (&port, config) in &self.ports {
+            if port < 32 && allow_mask & (1 << port) != 0 && config.enabled {
+                decoders.insert(port, self.create_decoder(&config.decoder)?);
+            }
+        }
+        Ok(decoders)
+    }
+
+    /// Apply new port configurations to a running session
+    ///
+    /// All decoders are built before anything changes, so one invalid
+    /// configuration in a bulk update leaves every port untouched.
+    /// Reconfigured ports start with fresh decoder state.
+    pub fn update_ports(
+        &mut self,
+        configs: Vec<PortConfig>,
+        allow_mask: u32,
+        decoders: &mut DecoderMap,
+    ) -> Result<()> {
+        let mut built = Vec::with_capacity(configs.len());
+        for config in &configs {
+            if config.port >= 32 {
+                bail!("Port {} is not an ITM stimulus port", config.port);
+            }
+            let decoder = self
+                .create_decoder(&config.decoder)
+                .with_context(|| format!("Port {}", config.port))?;
+            let active = config.enabled && allow_mask & (1 << config.port) != 0;
+            built.push(active.then_some(decoder));
+        }
+
+        for (config, decoder) in configs.into_iter().zip(built) {
+            match decoder {
+                Some(decoder) => {
+                    decoders.insert(config.port, decoder);
+                }
+                None => {
+                    decoders.remove(&config.port);
+                }
+            }
+            self.ports.insert(config.port, config);
+        }
+        Ok(())
+    }
+}
+
+impl Default for DecoderRegistry {
+    fn default() -> Self {
+        Self::new(standard_ports::default_config())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::defmt::DefmtEncoding;
+    use callisto_protocol::TraceEvent;
+
+    #[test]
+    fn test_decoders_follow_port_config() {
+        let mut registry = DecoderRegistry::default();
+        registry.set_port(PortConfig::marker_port(0, "Markers on 0"));
+        let mut disabled = PortConfig::counter_port(3, "Counters");
+        disabled.enabled = false;
+        registry.set_port(disabled);
+
+        let mut decoders = registry.build_decoders(0x0F).unwrap();
+        assert!(!decoders.contains_key(&3));
+
+        let events = decoders
+            .get_mut(&0)
+            .unwrap()
+            .decode(0, &[7, 0, 0, 0], 0)
+            .unwrap();
+        assert!(matches!(events[..], [TraceEvent::Marker { id: 7, .. }]));
+    }
+
+    #[test]
+    fn test_unconfigured_ports_are_reported() {
+        let mut registry = DecoderRegistry::default();
+        let decoders = registry.build_decoders(1 << 20).unwrap();
+
+        assert!(decoders.contains_key(&20));
+        assert!(matches!(registry.ports()[&20].decoder, DecoderType::Text));
+    }
+
+    #[test]
+    fn test_bulk_update_is_all_or_nothing() {
+        let mut registry = DecoderRegistry::default();
+        let mut decoders = registry.build_decoders(0x0F).unwrap();
+
+        let mut disabled = PortConfig::text_port(0, "Console");
+        disabled.enabled = false;
+        let bad = PortConfig {
+            port: 1,
+            name: "Bad".to_string(),
+            decoder: DecoderType::User {
+                format: "u8".to_string(),
+            },
+            enabled: true,
+        };
+        assert!(registry
+            .update_ports(vec![disabled.clone(), bad], 0x0F, &mut decoders)
+            .is_err());
+        assert!(decoders.contains_key(&0));
+        assert!(registry.ports()[&0].enabled);
+
+        registry
+            .update_ports(
+                vec![disabled, PortConfig::marker_port(1, "Markers")],
+                0x0F,
+                &mut decoders,
+            )
+            .unwrap();
+        assert!(!decoders.contains_key(&0));
+        let events = decoders
+            .get_mut(&1)
+            .unwrap()
+            .decode(1, &[9, 0, 0, 0], 0)
+            .unwrap();
+        assert!(matches!(events[..], [TraceEvent::Marker { id: 9, .. }]));
+    }
+
+    #[test]
+    fn test_defmt_port_needs_elf() {
+        let mut registry = DecoderRegistry::default();
+        let mut config = PortConfig::text_port(0, "defmt");
+        config.decoder = DecoderType::Defmt;
+        registry.set_port(config);
+        assert!(registry.build_decoders(0x01).is_err());
+
+        registry.set_defmt_table(Some(DefmtTable::new(DefmtEncoding::Rzcobs)));
+        assert!(registry.build_decoders(0x01).is_ok());
+    }
+
+    #[test]
+    fn test_user_format_is_validated() {
+        let user_port = |format: &str| PortConfig {
+            port: 4,
+            name: "Sensor".to_string(),
+            decoder: DecoderType::User {
+                format: format.to_string(),
+            },
+            enabled: true,
+        };
+
+        let mut registry = DecoderRegistry::default();
+        registry.set_port(user_port("u8 id; f32 temp"));
+        let mut decoders = registry.build_decoders(0x10).unwrap();
+        let events = decoders
+            .get_mut(&4)
+            .unwrap()
+            .decode(4, &[1, 0, 0, 0, 0], 0)
+            .unwrap();
+        assert!(matches!(&events[..], [TraceEvent::Record { fields }] if fields.len() == 2));
+
+        registry.set_port(user_port("u8 id; q32 temp"));
+        assert!(registry.build_decoders(0x10).is_err());
+    }
+}
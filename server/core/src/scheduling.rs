@@ -0,0 +1,536 @@
+//! Scheduling analysis
+//!
+//! Times each job of each task from activations (`TaskReady`), task
+//! switches and ISRs. A job starts when the task is made ready and ends
+//! when it blocks. A task that runs again without a new activation was
+//! preempted rather than blocked, so jobs are only closed once the next
+//! activation shows how the task was switched out. Targets that send no
+//! `TaskReady` records get one job per time slice, without latencies.
+//! Time in ISRs, from firmware events or DWT exception trace once it is
+//! seen, is not counted as task execution.
+
+use crate::dwt::FIRST_IRQ_EXCEPTION;
+use crate::tasks::TaskTable;
+use anyhow::{Context, Result};
+use callisto_protocol::{TaskDeadline, TaskSchedule, TimingSummary, TraceEvent};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+
+/// Recent samples kept per metric for percentiles
+pub const TIMING_SAMPLES: usize = 1024;
+
+/// Durations recorded for one metric
+#[derive(Debug, Clone, Default)]
+struct Timing {
+    count: u64,
+    sum: u128,
+    min: u64,
+    max: u64,
+    recent: VecDeque<u64>,
+}
+
+impl Timing {
+    fn record(&mut self, value: u64) {
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        self.max = self.max.max(value);
+        self.count += 1;
+        self.sum += value as u128;
+        if self.recent.len() == TIMING_SAMPLES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(value);
+    }
+
+    fn summary(&self) -> Option<TimingSummary> {
+        if self.count == 0 {
+            return None;
+        }
+        let mut sorted: Vec<u64> = self.recent.iter().copied().collect();
+        sorted.sort_unstable();
+        // Nearest rank
+        let percentile = |p: usize| sorted[(sorted.len() * p).div_ceil(100).max(1) - 1];
+        Some(TimingSummary {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: self.sum as f64 / self.count as f64,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        })
+    }
+}
+
+struct Job {
+    activation: u64,
+    /// Activated by `TaskReady`, rather than assumed at the switch in
+    traced_activation: bool,
+    started: bool,
+    execution: u64,
+    missed: bool,
+}
+
+#[derive(Default)]
+struct TaskState {
+    job: Option<Job>,
+    /// Switched out during a job; preempted or blocked, not yet known
+    switched_out: Option<u64>,
+    /// End of the last job, while waiting for the next activation
+    completed_at: Option<u64>,
+    last_activation: Option<u64>,
+    latency: Timing,
+    execution: Timing,
+    response: Timing,
+    period: Timing,
+    jobs: u64,
+    preemptions: u64,
+    blocked_cycles: u64,
+    deadline_misses: u64,
+}
+
+/// Per-task scheduling metrics for the current session
+pub struct SchedulingAnalyzer {
+    tasks: BTreeMap<u32, TaskState>,
+    running: Option<u32>,
+    /// Active exception numbers, innermost last
+    isr_stack: Vec<u32>,
+    /// DWT exception trace seen; firmware ISR events are ignored
+    exception_trace: bool,
+    last_timestamp: Option<u64>,
+    /// The target sends `TaskReady` records
+    traced_activations: bool,
+    deadlines: Vec<TaskDeadline>,
+}
+
+impl SchedulingAnalyzer {
+    pub fn new() -> Self {
+        Self {
+            tasks: BTreeMap::new(),
+            running: None,
+            isr_stack: Vec::new(),
+            exception_trace: false,
+            last_timestamp: None,
+            traced_activations: false,
+            deadlines: Vec::new(),
+        }
+    }
+
+    /// Set the expected timing of periodic tasks, matched by task name
+    pub fn set_deadlines(&mut self, deadlines: Vec<TaskDeadline>) {
+        self.deadlines = deadlines;
+    }
+
+    /// Load task deadlines from a JSON list of `TaskDeadline`
+    pub fn load_deadlines(&mut self, path: &Path) -> Result<()> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let deadlines = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid task deadlines in {}", path.display()))?;
+        self.set_deadlines(deadlines);
+        Ok(())
+    }
+
+    pub fn deadlines(&self) -> &[TaskDeadline] {
+        &self.deadlines
+    }
+
+    /// Follow a decoded event, with task names already applied
+    pub fn observe(&mut self, timestamp: u64, event: &TraceEvent, table: &TaskTable) {
+        self.advance(timestamp);
+        match event {
+            TraceEvent::TaskReady { task_id, .. } => {
+                self.traced_activations = true;
+                self.activate(*task_id, timestamp, true, table);
+            }
+            TraceEvent::TaskSwitch {
+                from_task, to_task, ..
+            } => {
+                self.switch_out(*from_task, timestamp, table);
+                self.switch_in(*to_task, timestamp, table);
+            }
+            TraceEvent::TaskDelete { task_id } => {
+                self.tasks.remove(task_id);
+                if self.running == Some(*task_id) {
+                    self.running = None;
+                }
+            }
+            TraceEvent::IsrEnter { isr_id, .. } if !self.exception_trace => {
+                self.isr_stack
+                    .push(isr_id.saturating_add(FIRST_IRQ_EXCEPTION));
+            }
+            TraceEvent::IsrExit { isr_id, .. } if !self.exception_trace => {
+                self.exit_isr(isr_id.saturating_add(FIRST_IRQ_EXCEPTION));
+            }
+            TraceEvent::ExceptionEnter { exception, .. } => {
+                self.start_exception_trace();
+                self.isr_stack.push(*exception);
+            }
+            TraceEvent::ExceptionExit { exception } => {
+                self.start_exception_trace();
+                self.exit_isr(*exception);
+            }
+            _ => {}
+        }
+        if !self.deadlines.is_empty() {
+            self.check_overdue(timestamp, table);
+        }
+    }
+
+    fn start_exception_trace(&mut self) {
+        if !self.exception_trace {
+            self.exception_trace = true;
+            self.isr_stack.clear();
+        }
+    }
+
+    fn exit_isr(&mut self, exception: u32) {
+        // Anything entered after it must have exited unseen
+        if let Some(pos) = self.isr_stack.iter().rposition(|&id| id == exception) {
+            self.isr_stack.truncate(pos);
+        }
+    }
+
+    /// Charge the time since the last event to the running task
+    fn advance(&mut self, timestamp: u64) {
+        let elapsed = self
+            .last_timestamp
+            .map_or(0, |last| timestamp.saturating_sub(last));
+        self.last_timestamp = Some(timestamp);
+        if !self.isr_stack.is_empty() {
+            return;
+        }
+        if let Some(job) = self
+            .running
+            .and_then(|task_id| self.tasks.get_mut(&task_id))
+            .and_then(|task| task.job.as_mut())
+        {
+            job.execution += elapsed;
+        }
+    }
+
+    fn activate(&mut self, task_id: u32, timestamp: u64, traced: bool, table: &TaskTable) {
+        let deadline = deadline_for(&self.deadlines, task_id, table);
+        let task = self.tasks.entry(task_id).or_default();
+        if task.job.is_some() {
+            match task.switched_out.take() {
+                // It blocked when it was switched out
+                Some(end) => complete(task, end, deadline),
+                // Already running, or activated again before it ran
+                None => return,
+            }
+        }
+        if let Some(end) = task.completed_at.take() {
+            task.blocked_cycles += timestamp.saturating_sub(end);
+        }
+        if let Some(last) = task.last_activation.replace(timestamp) {
+            task.period.record(timestamp.saturating_sub(last));
+        }
+        task.job = Some(Job {
+            activation: timestamp,
+            traced_activation: traced,
+            started: self.running == Some(task_id),
+            execution: 0,
+            missed: false,
+        });
+    }
+
+    fn switch_out(&mut self, task_id: u32, timestamp: u64, table: &TaskTable) {
+        let deadline = deadline_for(&self.deadlines, task_id, table);
+        if let Some(task) = self.tasks.get_mut(&task_id) {
+            if task.job.is_some() {
+                if self.traced_activations {
+                    task.switched_out = Some(timestamp);
+                } else {
+                    complete(task, timestamp, deadline);
+                }
+            }
+        }
+        if self.running == Some(task_id) {
+            self.running = None;
+        }
+    }
+
+    fn switch_in(&mut self, task_id: u32, timestamp: u64, table: &TaskTable) {
+        let task = self.tasks.entry(task_id).or_default();
+        if task.switched_out.take().is_some() {
+            // Still ready, since it was not activated again
+            task.preemptions += 1;
+        }
+        if task.job.is_none() {
+            self.activate(task_id, timestamp, false, table);
+        }
+        let task = self.tasks.entry(task_id).or_default();
+        if let Some(job) = &mut task.job {
+            if !job.started {
+                job.started = true;
+                if job.traced_activation {
+                    task.latency
+                        .record(timestamp.saturating_sub(job.activation));
+                }
+            }
+        }
+        self.running = Some(task_id);
+    }
+
+    /// Count jobs still running past their deadline as soon as they miss it
+    fn check_overdue(&mut self, timestamp: u64, table: &TaskTable) {
+        for (&task_id, task) in &mut self.tasks {
+            let Some(job) = task.job.as_mut().filter(|job| !job.missed) else {
+                continue;
+            };
+            if task.switched_out.is_some() {
+                // May have completed already
+                continue;
+            }
+            let Some(deadline) = deadline_for(&self.deadlines, task_id, table) else {
+                continue;
+            };
+            if timestamp.saturating_sub(job.activation) > deadline_cycles(deadline) {
+                job.missed = true;
+                task.deadline_misses += 1;
+            }
+        }
+    }
+
+    /// Metrics for every task seen, in task ID order
+    pub fn report(&self, table: &TaskTable) -> Vec<TaskSchedule> {
+        self.tasks
+            .iter()
+            .map(|(&task_id, task)| TaskSchedule {
+                task_id,
+                name: table.name(task_id).map(str::to_string),
+                priority: table.get(task_id).and_then(|info| info.priority),
+                jobs: task.jobs,
+                activation_latency: task.latency.summary(),
+                execution_time: task.execution.summary(),
+                response_time: task.response.summary(),
+                activation_period: task.period.summary(),
+                preemptions: task.preemptions,
+                blocked_cycles: task.blocked_cycles,
+                deadline: deadline_for(&self.deadlines, task_id, table).cloned(),
+                deadline_misses: task.deadline_misses,
+            })
+            .collect()
+    }
+
+    /// Forget all tasks, keeping the configured deadlines
+    pub fn reset(&mut self) {
+        let deadlines = std::mem::take(&mut self.deadlines);
+        *self = Self::new();
+        self.deadlines = deadlines;
+    }
+}
+
+impl Default for SchedulingAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn deadline_for<'a>(
+    deadlines: &'a [TaskDeadline],
+    task_id: u32,
+    table: &TaskTable,
+) -> Option<&'a TaskDeadline> {
+    let name = table.name(task_id)?;
+    deadlines.iter().find(|deadline| deadline.task == name)
+}
+
+fn deadline_cycles(deadline: &TaskDeadline) -> u64 {
+    deadline.deadline.unwrap_or(deadline.period)
+}
+
+/// Close the current job at `end`
+fn complete(task: &mut TaskState, end: u64, deadline: Option<&TaskDeadline>) {
+    let Some(job) = task.job.take() else {
+        return;
+    };
+    let response = end.saturating_sub(job.activation);
+    task.jobs += 1;
+    task.execution.record(job.execution);
+    task.response.record(response);
+    if !job.missed && deadline.is_some_and(|deadline| response > deadline_cycles(deadline)) {
+        task.deadline_misses += 1;
+    }
+    task.completed_at = Some(end);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn switch(from_task: u32, to_task: u32) -> TraceEvent {
+        TraceEvent::TaskSwitch {
+            from_task,
+            to_task,
+            from_name: None,
+            to_name: None,
+            from_priority: None,
+            to_priority: None,
+        }
+    }
+
+    fn ready(task_id: u32) -> TraceEvent {
+        TraceEvent::TaskReady {
+            task_id,
+            name: None,
+        }
+    }
+
+    fn named_table(tasks: &[(u32, &str)]) -> TaskTable {
+        let mut table = TaskTable::new();
+        for &(task_id, name) in tasks {
+            table.apply(&mut TraceEvent::TaskRename {
+                task_id,
+                name: name.to_string(),
+            });
+        }
+        table
+    }
+
+    #[test]
+    fn test_jobs_with_preemption_and_isr() {
+        let table = named_table(&[(1, "idle"), (2, "control"), (3, "logger")]);
+        let mut analyzer = SchedulingAnalyzer::new();
+        for (timestamp, event) in [
+            (0, switch(1, 3)),
+            (100, ready(3)),
+            // Control is released and preempts the logger
+            (200, ready(2)),
+            (250, switch(3, 2)),
+            (
+                300,
+                TraceEvent::IsrEnter {
+                    isr_id: 5,
+                    name: None,
+                    peripheral: None,
+                },
+            ),
+            (
+                350,
+                TraceEvent::IsrExit {
+                    isr_id: 5,
+                    name: None,
+                    peripheral: None,
+                },
+            ),
+            (450, switch(2, 3)),
+            (600, switch(3, 1)),
+            (1200, ready(2)),
+            (1210, switch(1, 2)),
+            (1310, switch(2, 1)),
+            (2200, ready(2)),
+        ] {
+            analyzer.observe(timestamp, &event, &table);
+        }
+
+        let report = analyzer.report(&table);
+        let control = report.iter().find(|task| task.task_id == 2).unwrap();
+        assert_eq!(control.name.as_deref(), Some("control"));
+        assert_eq!(control.jobs, 2);
+        let latency = control.activation_latency.as_ref().unwrap();
+        assert_eq!((latency.min, latency.max), (10, 50));
+        let execution = control.execution_time.as_ref().unwrap();
+        // 200 cycles running, less 50 in the ISR
+        assert_eq!((execution.min, execution.max), (100, 150));
+        let response = control.response_time.as_ref().unwrap();
+        assert_eq!((response.min, response.max), (110, 250));
+        assert_eq!(control.activation_period.as_ref().unwrap().mean, 1000.0);
+        assert_eq!(control.blocked_cycles, 750 + 890);
+        assert_eq!(control.preemptions, 0);
+
+        let logger = report.iter().find(|task| task.task_id == 3).unwrap();
+        assert_eq!(logger.preemptions, 1);
+        assert_eq!(logger.jobs, 0);
+    }
+
+    #[test]
+    fn test_exception_trace_is_not_task_execution() {
+        let table = named_table(&[(1, "idle"), (2, "control")]);
+        let mut analyzer = SchedulingAnalyzer::new();
+        let exception = |enter: bool, exception: u32| {
+            if enter {
+                TraceEvent::ExceptionEnter {
+                    exception,
+                    name: None,
+                }
+            } else {
+                TraceEvent::ExceptionExit { exception }
+            }
+        };
+        for (timestamp, event) in [
+            (0, ready(2)),
+            (10, switch(1, 2)),
+            (100, exception(true, 15)),
+            (120, exception(true, 44)),
+            (150, exception(false, 44)),
+            (200, exception(false, 15)),
+            // Firmware events are ignored once exception trace is seen
+            (
+                300,
+                TraceEvent::IsrEnter {
+                    isr_id: 5,
+                    name: None,
+                    peripheral: None,
+                },
+            ),
+            (400, switch(2, 1)),
+            (1000, ready(2)),
+        ] {
+            analyzer.observe(timestamp, &event, &table);
+        }
+
+        let report = analyzer.report(&table);
+        let control = report.iter().find(|task| task.task_id == 2).unwrap();
+        assert_eq!(control.jobs, 1);
+        let execution = control.execution_time.as_ref().unwrap();
+        assert_eq!(execution.max, 390 - 100);
+    }
+
+    #[test]
+    fn test_deadline_misses() {
+        let table = named_table(&[(1, "idle"), (2, "control")]);
+        let mut analyzer = SchedulingAnalyzer::new();
+        analyzer.set_deadlines(vec![TaskDeadline {
+            task: "control".to_string(),
+            period: 1000,
+            deadline: Some(300),
+        }]);
+        for (timestamp, event) in [
+            (0, ready(2)),
+            (10, switch(1, 2)),
+            (200, switch(2, 1)),
+            (1000, ready(2)),
+            (1010, switch(1, 2)),
+            // Overdue while still running
+            (1400, TraceEvent::Marker { id: 1, name: None }),
+            (1500, switch(2, 1)),
+            (2000, ready(2)),
+        ] {
+            analyzer.observe(timestamp, &event, &table);
+        }
+
+        let report = analyzer.report(&table);
+        let control = report.iter().find(|task| task.task_id == 2).unwrap();
+        assert_eq!(control.jobs, 2);
+        assert_eq!(control.deadline_misses, 1);
+        assert_eq!(control.deadline.as_ref().unwrap().period, 1000);
+
+        analyzer.reset();
+        assert_eq!(analyzer.deadlines().len(), 1);
+        assert!(analyzer.report(&table).is_empty());
+    }
+
+    #[test]
+    fn test_timing_percentiles() {
+        let mut timing = Timing::default();
+        for value in 1..=100 {
+            timing.record(value);
+        }
+        let summary = timing.summary().unwrap();
+        assert_eq!((summary.p50, summary.p90, summary.p99), (50, 90, 99));
+        assert_eq!(summary.mean, 50.5);
+    }
+}
@@ -0,0 +1,193 @@
+//! Trace sources
+//!
+//! Everything that produces raw trace bytes (a debug probe, a capture file,
+//! a TCP socket or the mock generator) implements [`TraceSource`], so each
+//! one goes through the same parse/decode pipeline in `ItmSession`.
+
+use crate::mock::MockSource;
+use crate::probe::{ProbeConfig, ProbeManager};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::info;
+
+/// Largest chunk read from a stream source at once
+const READ_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Raw trace bytes and when the host captured them
+#[derive(Debug, Clone)]
+pub struct TraceChunk {
+    pub data: Vec<u8>,
+    pub captured_at: Instant,
+}
+
+impl TraceChunk {
+    /// Bytes captured just now
+    pub fn now(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            captured_at: Instant::now(),
+        }
+    }
+}
+
+/// Producer of raw trace bytes (ITM, or TPIU frames)
+#[async_trait]
+pub trait TraceSource: Send {
+    /// Human readable description, for logs
+    fn describe(&self) -> String;
+
+    /// Next chunk of trace bytes, or `None` once the source has ended
+    ///
+    /// Must be cancel safe: the ingest task drops the future to flush idle
+    /// decoders or to stop, and no data may be lost when it does.
+    async fn read(&mut self) -> Result<Option<TraceChunk>>;
+
+    /// Chunks captured but not yet read, for sources that buffer them
+    fn backlog(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Where trace data comes from when tracing starts
+#[derive(Debug, Clone)]
+pub enum SourceConfig {
+    /// SWO from a debug probe through probe-rs
+    Probe(ProbeConfig),
+    /// Generated ITM traffic, for trying the viewer without hardware
+    Mock,
+    /// Raw bytes captured to a file
+    File(PathBuf),
+    /// Raw bytes from a TCP server, e.g. OpenOCD's SWO/trace port
+    Tcp(String),
+}
+
+impl Default for SourceConfig {
+    fn default() -> Self {
+        Self::Probe(ProbeConfig::default())
+    }
+}
+
+impl SourceConfig {
+    /// Open the source; probes are attached through `probes` with the
+    /// given port mask, baud rate and TPIU formatting
+    pub async fn open(
+        &self,
+        probes: &mut ProbeManager,
+        allow_mask: u32,
+        baud_rate: Option<u32>,
+        tpiu_formatting: bool,
+    ) -> Result<Box<dyn TraceSource>> {
+        let source: Box<dyn TraceSource> = match self {
+            SourceConfig::Probe(config) => Box::new(
+                probes
+                    .start_session(config, allow_mask, baud_rate, tpiu_formatting)
+                    .await?,
+            ),
+            SourceConfig::Mock => Box::new(MockSource::new()),
+            SourceConfig::File(path) => Box::new(StreamSource::open_file(path).await?),
+            SourceConfig::Tcp(address) => Box::new(StreamSource::connect(address).await?),
+        };
+        info!("Reading trace data from {}", source.describe());
+        Ok(source)
+    }
+
+    /// Name of the target or source, for status messages
+    pub fn describe(&self) -> String {
+        match self {
+            SourceConfig::Probe(config) => {
+                config.chip.clone().unwrap_or_else(|| "probe".to_string())
+            }
+            SourceConfig::Mock => "mock target".to_string(),
+            SourceConfig::File(path) => format!("file {}", path.display()),
+            SourceConfig::Tcp(address) => format!("tcp {}", address),
+        }
+    }
+}
+
+/// Trace bytes read from a byte stream such as a file or a socket
+///
+/// Chunks are stamped with the time they were read.
+pub struct StreamSource {
+    name: String,
+    reader: Box<dyn AsyncRead + Send + Unpin>,
+    buffer: Vec<u8>,
+}
+
+impl StreamSource {
+    pub fn new(name: impl Into<String>, reader: Box<dyn AsyncRead + Send + Unpin>) -> Self {
+        Self {
+            name: name.into(),
+            reader,
+            buffer: vec![0; READ_BUFFER_SIZE],
+        }
+    }
+
+    /// Replay a capture file as fast as it can be decoded
+    pub async fn open_file(path: &std::path::Path) -> Result<Self> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok(Self::new(
+            format!("file {}", path.display()),
+            Box::new(file),
+        ))
+    }
+
+    /// Connect to a TCP server streaming raw trace bytes
+    pub async fn connect(address: &str) -> Result<Self> {
+        let stream = tokio::net::TcpStream::connect(address)
+            .await
+            .with_context(|| format!("Failed to connect to {}", address))?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(format!("tcp {}", address), Box::new(stream)))
+    }
+}
+
+#[async_trait]
+impl TraceSource for StreamSource {
+    fn describe(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn read(&mut self) -> Result<Option<TraceChunk>> {
+        let len = self
+            .reader
+            .read(&mut self.buffer)
+            .await
+            .with_context(|| format!("Failed to read from {}", self.name))?;
+        if len == 0 {
+            return Ok(None);
+        }
+        Ok(Some(TraceChunk::now(self.buffer[..len].to_vec())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_tcp_source() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket.write_all(&[0x13, 1, 0, 0, 0]).await.unwrap();
+        });
+
+        let mut source = SourceConfig::Tcp(address)
+            .open(&mut ProbeManager::new(), 0x0F, None, false)
+            .await
+            .unwrap();
+        let mut data = Vec::new();
+        while let Some(chunk) = source.read().await.unwrap() {
+            data.extend(chunk.data);
+        }
+        server.await.unwrap();
+        assert_eq!(data, [0x13, 1, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,368 @@
+//! Span pairing
+//!
+//! Pairs `SpanBegin` and `SpanEnd` events into completed spans. Spans
+//! nest per execution context: each task and each active ISR has its own
+//! stack, so a span interrupted by an ISR or a task switch still pairs
+//! with its own end. ISRs come from the firmware's events until DWT
+//! exception trace is seen, as in the ISR timing.
+
+use crate::dwt::FIRST_IRQ_EXCEPTION;
+use callisto_protocol::{SpanContext, SpanProblem, TraceEvent};
+use std::collections::HashMap;
+
+/// Deepest nesting kept per context; older spans are reported as unclosed
+pub const MAX_SPAN_DEPTH: usize = 64;
+
+struct OpenSpan {
+    span_id: u32,
+    name: Option<String>,
+    start: u64,
+}
+
+/// Tracks the current context and the open spans of every context
+pub struct SpanTracker {
+    task: SpanContext,
+    /// Active ISR contexts, innermost last
+    isr_stack: Vec<SpanContext>,
+    /// DWT exception trace seen; firmware ISR events are ignored
+    exception_trace: bool,
+    open: HashMap<SpanContext, Vec<OpenSpan>>,
+}
+
+impl SpanTracker {
+    pub fn new() -> Self {
+        Self {
+            task: SpanContext::Thread,
+            isr_stack: Vec::new(),
+            exception_trace: false,
+            open: HashMap::new(),
+        }
+    }
+
+    /// Context that spans begin and end in right now
+    pub fn context(&self) -> SpanContext {
+        self.isr_stack.last().copied().unwrap_or(self.task)
+    }
+
+    /// Number of spans still open across all contexts
+    pub fn open_spans(&self) -> usize {
+        self.open.values().map(Vec::len).sum()
+    }
+
+    /// Follow context changes and pair spans, returning completed spans
+    /// and diagnostics
+    pub fn observe(&mut self, timestamp: u64, event: &TraceEvent) -> Vec<TraceEvent> {
+        let mut out = Vec::new();
+        match event {
+            TraceEvent::TaskSwitch { to_task, .. } => {
+                self.task = SpanContext::Task { task_id: *to_task };
+            }
+            TraceEvent::TaskDelete { task_id } => {
+                self.close_context(SpanContext::Task { task_id: *task_id }, &mut out);
+            }
+            TraceEvent::IsrEnter { isr_id, .. } if !self.exception_trace => {
+                self.isr_stack.push(SpanContext::Isr { isr_id: *isr_id });
+            }
+            TraceEvent::IsrExit { isr_id, .. } if !self.exception_trace => {
+                self.exit_isr(SpanContext::Isr { isr_id: *isr_id }, &mut out);
+            }
+            TraceEvent::ExceptionEnter { exception, .. } => {
+                self.start_exception_trace();
+                self.isr_stack.push(exception_context(*exception));
+            }
+            TraceEvent::ExceptionExit { exception } => {
+                self.start_exception_trace();
+                self.exit_isr(exception_context(*exception), &mut out);
+            }
+            TraceEvent::SpanBegin { span_id, name } => {
+                let context = self.context();
+                let stack = self.open.entry(context).or_default();
+                if stack.len() >= MAX_SPAN_DEPTH {
+                    let oldest = stack.remove(0);
+                    out.push(diagnostic(oldest, context, SpanProblem::Unclosed));
+                }
+                stack.push(OpenSpan {
+                    span_id: *span_id,
+                    name: name.clone(),
+                    start: timestamp,
+                });
+            }
+            TraceEvent::SpanEnd { span_id, name } => {
+                let context = self.context();
+                let stack = self.open.entry(context).or_default();
+                match stack.iter().rposition(|open| open.span_id == *span_id) {
+                    Some(pos) => {
+                        // Spans opened inside this one never ended
+                        let inner = stack.split_off(pos + 1);
+                        let open = stack.pop().expect("span at pos");
+                        let depth = stack.len() as u32;
+                        for span in inner.into_iter().rev() {
+                            out.push(diagnostic(span, context, SpanProblem::Unclosed));
+                        }
+                        out.push(TraceEvent::Span {
+                            span_id: open.span_id,
+                            name: open.name.or_else(|| name.clone()),
+                            context,
+                            depth,
+                            start: open.start,
+                            duration: timestamp.saturating_sub(open.start),
+                        });
+                    }
+                    None => {
+                        let problem = match stack.last() {
+                            Some(open) => SpanProblem::Mismatched {
+                                open_span: open.span_id,
+                            },
+                            None => SpanProblem::UnmatchedEnd,
+                        };
+                        out.push(TraceEvent::SpanDiagnostic {
+                            span_id: *span_id,
+                            name: name.clone(),
+                            context,
+                            problem,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+        out
+    }
+
+    /// Report every open span as unclosed, e.g. when tracing stops
+    pub fn close_all(&mut self) -> Vec<TraceEvent> {
+        let mut out = Vec::new();
+        let mut contexts: Vec<SpanContext> = self.open.keys().copied().collect();
+        contexts.sort_by_key(|context| format!("{:?}", context));
+        for context in contexts {
+            self.close_context(context, &mut out);
+        }
+        self.isr_stack.clear();
+        out
+    }
+
+    fn start_exception_trace(&mut self) {
+        if !self.exception_trace {
+            self.exception_trace = true;
+            self.isr_stack.clear();
+        }
+    }
+
+    fn exit_isr(&mut self, context: SpanContext, out: &mut Vec<TraceEvent>) {
+        if let Some(pos) = self.isr_stack.iter().rposition(|&isr| isr == context) {
+            // Anything entered after it must have exited unseen
+            for isr in self.isr_stack.split_off(pos) {
+                self.close_context(isr, out);
+            }
+        }
+    }
+
+    fn close_context(&mut self, context: SpanContext, out: &mut Vec<TraceEvent>) {
+        if let Some(stack) = self.open.remove(&context) {
+            for span in stack.into_iter().rev() {
+                out.push(diagnostic(span, context, SpanProblem::Unclosed));
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl Default for SpanTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// IRQs share their context with the firmware's ISR events
+fn exception_context(exception: u32) -> SpanContext {
+    match exception.checked_sub(FIRST_IRQ_EXCEPTION) {
+        Some(isr_id) => SpanContext::Isr { isr_id },
+        None => SpanContext::Exception { exception },
+    }
+}
+
+fn diagnostic(span: OpenSpan, context: SpanContext, problem: SpanProblem) -> TraceEvent {
+    TraceEvent::SpanDiagnostic {
+        span_id: span.span_id,
+        name: span.name,
+        context,
+        problem,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn begin(span_id: u32) -> TraceEvent {
+        TraceEvent::SpanBegin {
+            span_id,
+            name: None,
+        }
+    }
+
+    fn end(span_id: u32) -> TraceEvent {
+        TraceEvent::SpanEnd {
+            span_id,
+            name: None,
+        }
+    }
+
+    fn isr(enter: bool, isr_id: u32) -> TraceEvent {
+        if enter {
+            TraceEvent::IsrEnter {
+                isr_id,
+                name: None,
+                peripheral: None,
+            }
+        } else {
+            TraceEvent::IsrExit {
+                isr_id,
+                name: None,
+                peripheral: None,
+            }
+        }
+    }
+
+    fn feed(tracker: &mut SpanTracker, events: &[(u64, TraceEvent)]) -> Vec<TraceEvent> {
+        events
+            .iter()
+            .flat_map(|(timestamp, event)| tracker.observe(*timestamp, event))
+            .collect()
+    }
+
+    #[test]
+    fn test_nested_spans_across_isr() {
+        let mut tracker = SpanTracker::new();
+        let out = feed(
+            &mut tracker,
+            &[
+                (0, begin(1)),
+                (10, begin(2)),
+                (20, isr(true, 5)),
+                (22, begin(2)),
+                (25, end(2)),
+                (30, isr(false, 5)),
+                (40, end(2)),
+                (100, end(1)),
+            ],
+        );
+
+        let spans: Vec<_> = out
+            .iter()
+            .map(|event| match event {
+                TraceEvent::Span {
+                    span_id,
+                    context,
+                    depth,
+                    duration,
+                    ..
+                } => (*span_id, *context, *depth, *duration),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                (2, SpanContext::Isr { isr_id: 5 }, 0, 3),
+                (2, SpanContext::Thread, 1, 30),
+                (1, SpanContext::Thread, 0, 100),
+            ]
+        );
+        assert_eq!(tracker.open_spans(), 0);
+    }
+
+    #[test]
+    fn test_unpaired_spans_are_diagnosed() {
+        let mut tracker = SpanTracker::new();
+        let out = feed(
+            &mut tracker,
+            &[
+                (0, end(9)),
+                (1, begin(1)),
+                (2, begin(2)),
+                (3, end(3)),
+                (4, end(1)),
+                (5, isr(true, 7)),
+                (6, begin(4)),
+                (7, isr(false, 7)),
+            ],
+        );
+
+        let problems: Vec<_> = out
+            .iter()
+            .filter_map(|event| match event {
+                TraceEvent::SpanDiagnostic {
+                    span_id, problem, ..
+                } => Some((*span_id, problem.clone())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                (9, SpanProblem::UnmatchedEnd),
+                (3, SpanProblem::Mismatched { open_span: 2 }),
+                (2, SpanProblem::Unclosed),
+                (4, SpanProblem::Unclosed),
+            ]
+        );
+        assert!(matches!(out[3], TraceEvent::Span { span_id: 1, .. }));
+
+        feed(&mut tracker, &[(8, begin(5))]);
+        assert_eq!(tracker.close_all().len(), 1);
+    }
+
+    #[test]
+    fn test_spans_in_exception_trace_contexts() {
+        let exception = |enter: bool, exception: u32| {
+            if enter {
+                TraceEvent::ExceptionEnter {
+                    exception,
+                    name: None,
+                }
+            } else {
+                TraceEvent::ExceptionExit { exception }
+            }
+        };
+        let mut tracker = SpanTracker::new();
+        let out = feed(
+            &mut tracker,
+            &[
+                (0, begin(1)),
+                (10, exception(true, 15)),
+                (12, begin(2)),
+                (20, exception(true, 44)),
+                (22, begin(3)),
+                (25, end(3)),
+                (30, exception(false, 44)),
+                // Ignored once exception trace is seen
+                (32, isr(true, 5)),
+                (35, end(2)),
+                (40, exception(false, 15)),
+                (50, end(1)),
+            ],
+        );
+
+        let spans: Vec<_> = out
+            .iter()
+            .map(|event| match event {
+                TraceEvent::Span {
+                    span_id, context, ..
+                } => (*span_id, *context),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                (3, SpanContext::Isr { isr_id: 28 }),
+                (2, SpanContext::Exception { exception: 15 }),
+                (1, SpanContext::Thread),
+            ]
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! Sliding-window session statistics
+//!
+//! Rates are computed from the session counters sampled whenever stats are
+//! sent, over the last few seconds, so they follow changes in traffic
+//! rather than averaging over the whole session.
+
+use crate::SessionStats;
+use callisto_protocol::PortStats;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Default time between Stats messages
+pub const DEFAULT_STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Span of time rates are computed over
+pub const STATS_WINDOW: Duration = Duration::from_secs(5);
+
+/// Traffic counted on one port
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PortCounters {
+    pub events: u64,
+    pub bytes: u64,
+}
+
+/// Depth of a queue drained outside the session, updated by its consumer
+#[derive(Debug, Clone, Default)]
+pub struct QueueGauge(Arc<AtomicUsize>);
+
+impl QueueGauge {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, depth: usize) {
+        self.0.store(depth, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Rates over the current window
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WindowRates {
+    pub events_per_sec: f64,
+    pub bytes_per_sec: f64,
+    /// Fraction of events lost to ITM overflows within the window
+    pub drop_rate: f64,
+    pub ports: Vec<PortStats>,
+}
+
+struct Sample {
+    at: Instant,
+    events: u64,
+    bytes: u64,
+    dropped: u64,
+    ports: BTreeMap<u8, PortCounters>,
+}
+
+impl Sample {
+    fn new(at: Instant, stats: &SessionStats) -> Self {
+        Self {
+            at,
+            events: stats.events_processed,
+            bytes: stats.bytes_processed,
+            dropped: stats.dropped_events,
+            ports: stats.ports.clone(),
+        }
+    }
+}
+
+/// Counter samples covering the last `window` of time
+pub struct RateWindow {
+    window: Duration,
+    samples: VecDeque<Sample>,
+}
+
+impl RateWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Start a new window from the current counters
+    pub fn reset(&mut self, now: Instant, stats: &SessionStats) {
+        self.samples.clear();
+        self.samples.push_back(Sample::new(now, stats));
+    }
+
+    /// Sample the counters and compute rates since the start of the window
+    pub fn update(&mut self, now: Instant, stats: &SessionStats) -> WindowRates {
+        self.samples.push_back(Sample::new(now, stats));
+        // Keep the newest sample at or before the window start as baseline
+        while self.samples.len() > 2
+            && now.saturating_duration_since(self.samples[1].at) >= self.window
+        {
+            self.samples.pop_front();
+        }
+
+        let (Some(first), Some(last)) = (self.samples.front(), self.samples.back()) else {
+            return WindowRates::default();
+        };
+        let elapsed = last.at.saturating_duration_since(first.at).as_secs_f64();
+        let per_sec = |count: u64| {
+            if elapsed > 0.0 {
+                count as f64 / elapsed
+            } else {
+                0.0
+            }
+        };
+
+        let events = last.events.saturating_sub(first.events);
+        let dropped = last.dropped.saturating_sub(first.dropped);
+        let ports = last
+            .ports
+            .iter()
+            .map(|(&port, counters)| {
+                let base = first.ports.get(&port).copied().unwrap_or_default();
+                PortStats {
+                    port,
+                    events: counters.events,
+                    bytes: counters.bytes,
+                    events_per_sec: per_sec(counters.events.saturating_sub(base.events)),
+                    bytes_per_sec: per_sec(counters.bytes.saturating_sub(base.bytes)),
+                }
+            })
+            .collect();
+
+        WindowRates {
+            events_per_sec: per_sec(events),
+            bytes_per_sec: per_sec(last.bytes.saturating_sub(first.bytes)),
+            drop_rate: if events + dropped == 0 {
+                0.0
+            } else {
+                dropped as f64 / (events + dropped) as f64
+            },
+            ports,
+        }
+    }
+}
+
+impl Default for RateWindow {
+    fn default() -> Self {
+        Self::new(STATS_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rates_follow_the_window() {
+        let start = Instant::now();
+        let mut stats = SessionStats::default();
+        let mut window = RateWindow::new(Duration::from_secs(2));
+        window.reset(start, &stats);
+
+        // 100 events/s on port 2 for two seconds, then nothing
+        for second in 1..=2 {
+            stats.events_processed += 100;
+            stats.bytes_processed += 500;
+            stats.ports.entry(2).or_default().events += 100;
+            window.update(start + Duration::from_secs(second), &stats);
+        }
+        let rates = window.update(start + Duration::from_secs(2), &stats);
+        assert_eq!(rates.events_per_sec, 100.0);
+        assert_eq!(rates.bytes_per_sec, 500.0);
+        assert_eq!(rates.ports[0].events_per_sec, 100.0);
+
+        stats.dropped_events += 1;
+        window.update(start + Duration::from_secs(3), &stats);
+        let rates = window.update(start + Duration::from_secs(4), &stats);
+        assert_eq!(rates.events_per_sec, 0.0);
+        assert_eq!(rates.drop_rate, 1.0);
+        assert_eq!(rates.ports[0].events, 200);
+    }
+}
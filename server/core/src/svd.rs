@@ -0,0 +1,224 @@
+//! CMSIS-SVD interrupt names
+//!
+//! Maps IRQ numbers to the interrupt names and peripherals declared in the
+//! chip's SVD file, so ISR events read "USART2" rather than "ISR 38".
+
+use crate::dwt::FIRST_IRQ_EXCEPTION;
+use anyhow::{bail, Context, Result};
+use callisto_protocol::{InterruptInfo, TraceEvent};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Interrupts of one device, keyed by IRQ number
+#[derive(Debug, Clone, Default)]
+pub struct InterruptMap {
+    device: Option<String>,
+    interrupts: BTreeMap<u32, InterruptInfo>,
+}
+
+impl InterruptMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load an SVD file
+    pub fn load(path: &Path) -> Result<Self> {
+        let xml = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&xml).with_context(|| format!("Invalid SVD file {}", path.display()))
+    }
+
+    /// Parse the interrupts declared by the peripherals of an SVD file
+    pub fn parse(xml: &str) -> Result<Self> {
+        let document = roxmltree::Document::parse(xml)?;
+        let device = document.root_element();
+        if !device.has_tag_name("device") {
+            bail!("Expected a <device> root element");
+        }
+
+        let mut map = Self {
+            device: child_text(device, "name").map(str::to_string),
+            interrupts: BTreeMap::new(),
+        };
+
+        let peripherals = device
+            .children()
+            .filter(|n| n.has_tag_name("peripherals"))
+            .flat_map(|n| n.children())
+            .filter(|n| n.has_tag_name("peripheral"));
+
+        for peripheral in peripherals {
+            let Some(peripheral_name) = child_text(peripheral, "name") else {
+                continue;
+            };
+
+            for interrupt in peripheral
+                .children()
+                .filter(|n| n.has_tag_name("interrupt"))
+            {
+                let (Some(name), Some(value)) = (
+                    child_text(interrupt, "name"),
+                    child_text(interrupt, "value"),
+                ) else {
+                    bail!("Interrupt in {} has no name or value", peripheral_name);
+                };
+                let irq = parse_number(value)
+                    .with_context(|| format!("Interrupt {} has invalid value `{}`", name, value))?;
+
+                let info = map.interrupts.entry(irq).or_insert_with(|| InterruptInfo {
+                    irq,
+                    name: name.to_string(),
+                    description: child_text(interrupt, "description")
+                        .map(|d| d.split_whitespace().collect::<Vec<_>>().join(" ")),
+                    peripherals: Vec::new(),
+                });
+                if !info.peripherals.iter().any(|p| p == peripheral_name) {
+                    info.peripherals.push(peripheral_name.to_string());
+                }
+            }
+        }
+
+        Ok(map)
+    }
+
+    /// Device name from the SVD file
+    pub fn device(&self) -> Option<&str> {
+        self.device.as_deref()
+    }
+
+    pub fn get(&self, irq: u32) -> Option<&InterruptInfo> {
+        self.interrupts.get(&irq)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.interrupts.is_empty()
+    }
+
+    /// All interrupts in IRQ order, for `Meta`
+    pub fn interrupts(&self) -> Vec<InterruptInfo> {
+        self.interrupts.values().cloned().collect()
+    }
+
+    /// Fill in interrupt names and peripherals on ISR and exception events
+    ///
+    /// ISR events from the RTOS port carry IRQ numbers; DWT exception
+    /// events carry exception numbers, where IRQs start at 16.
+    pub fn apply(&self, event: &mut TraceEvent) {
+        match event {
+            TraceEvent::IsrEnter {
+                isr_id,
+                name,
+                peripheral,
+            }
+            | TraceEvent::IsrExit {
+                isr_id,
+                name,
+                peripheral,
+            } => {
+                if let Some(info) = self.get(*isr_id) {
+                    *name = Some(info.name.clone());
+                    *peripheral = Some(info.peripherals.join(", "));
+                }
+            }
+            TraceEvent::ExceptionEnter { exception, name } if *exception >= FIRST_IRQ_EXCEPTION => {
+                if let Some(info) = self.get(*exception - FIRST_IRQ_EXCEPTION) {
+                    *name = Some(info.name.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, tag: &str) -> Option<&'a str> {
+    node.children()
+        .find(|n| n.has_tag_name(tag))
+        .and_then(|n| n.text())
+        .map(str::trim)
+}
+
+/// SVD scaled non-negative integer: decimal, `0x` hex or `#` binary
+fn parse_number(text: &str) -> Result<u32> {
+    let text = text.trim();
+    Ok(
+        if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            u32::from_str_radix(hex, 16)?
+        } else if let Some(binary) = text.strip_prefix('#') {
+            u32::from_str_radix(binary, 2)?
+        } else {
+            text.parse()?
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SVD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<device schemaVersion="1.1">
+  <name>STM32F407</name>
+  <peripherals>
+    <peripheral>
+      <name>USART2</name>
+      <baseAddress>0x40004400</baseAddress>
+      <interrupt>
+        <name>USART2</name>
+        <description>USART2 global
+          interrupt</description>
+        <value>38</value>
+      </interrupt>
+    </peripheral>
+    <peripheral>
+      <name>TIM1</name>
+      <interrupt><name>TIM1_UP_TIM10</name><value>0x19</value></interrupt>
+    </peripheral>
+    <peripheral derivedFrom="TIM1">
+      <name>TIM10</name>
+      <interrupt><name>TIM1_UP_TIM10</name><value>25</value></interrupt>
+    </peripheral>
+  </peripherals>
+</device>"#;
+
+    #[test]
+    fn test_parse_interrupts() {
+        let map = InterruptMap::parse(SVD).unwrap();
+        assert_eq!(map.device(), Some("STM32F407"));
+
+        let usart = map.get(38).unwrap();
+        assert_eq!(usart.name, "USART2");
+        assert_eq!(
+            usart.description.as_deref(),
+            Some("USART2 global interrupt")
+        );
+        assert_eq!(map.get(25).unwrap().peripherals, ["TIM1", "TIM10"]);
+        assert!(InterruptMap::parse("<device><peripherals><peripheral><name>X</name><interrupt><name>X</name></interrupt></peripheral></peripherals></device>").is_err());
+    }
+
+    #[test]
+    fn test_isr_events_are_named() {
+        let map = InterruptMap::parse(SVD).unwrap();
+
+        let mut event = TraceEvent::IsrEnter {
+            isr_id: 38,
+            name: Some("ISR 38".to_string()),
+            peripheral: None,
+        };
+        map.apply(&mut event);
+        assert!(matches!(
+            &event,
+            TraceEvent::IsrEnter { name: Some(name), peripheral: Some(p), .. }
+                if name == "USART2" && p == "USART2"
+        ));
+
+        let mut event = TraceEvent::ExceptionEnter {
+            exception: 38 + FIRST_IRQ_EXCEPTION,
+            name: Some("IRQ38".to_string()),
+        };
+        map.apply(&mut event);
+        assert!(matches!(
+            &event,
+            TraceEvent::ExceptionEnter { name: Some(name), .. } if name == "USART2"
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+//! Per-session RTOS task table
+//!
+//! Tracks task names and priorities from lifecycle events so task switches
+//! can be shown with readable names.
+
+use callisto_protocol::TraceEvent;
+use std::collections::HashMap;
+
+/// What is known about one task
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub name: Option<String>,
+    pub priority: Option<u32>,
+}
+
+/// Tasks seen in the current session, keyed by task ID
+#[derive(Debug, Clone, Default)]
+pub struct TaskTable {
+    tasks: HashMap<u32, TaskInfo>,
+}
+
+impl TaskTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, task_id: u32) -> Option<&TaskInfo> {
+        self.tasks.get(&task_id)
+    }
+
+    /// Name of a task, if it has been named
+    pub fn name(&self, task_id: u32) -> Option<&str> {
+        self.get(task_id)?.name.as_deref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Update the table from a lifecycle event, and fill in task names and
+    /// priorities on a task switch, and the name on an activation
+    pub fn apply(&mut self, event: &mut TraceEvent) {
+        match event {
+            TraceEvent::TaskCreate { task_id, priority } => {
+                self.tasks.insert(
+                    *task_id,
+                    TaskInfo {
+                        name: None,
+                        priority: Some(*priority),
+                    },
+                );
+            }
+            TraceEvent::TaskDelete { task_id } => {
+                self.tasks.remove(task_id);
+            }
+            TraceEvent::TaskRename { task_id, name } => {
+                self.tasks.entry(*task_id).or_default().name = Some(name.clone());
+            }
+            TraceEvent::TaskPriority { task_id, priority } => {
+                self.tasks.entry(*task_id).or_default().priority = Some(*priority);
+            }
+            TraceEvent::TaskReady { task_id, name } => {
+                if let Some(task) = self.tasks.get(task_id) {
+                    name.clone_from(&task.name);
+                }
+            }
+            TraceEvent::TaskSwitch {
+                from_task,
+                to_task,
+                from_name,
+                to_name,
+                from_priority,
+                to_priority,
+            } => {
+                if let Some(task) = self.tasks.get(from_task) {
+                    from_name.clone_from(&task.name);
+                    *from_priority = task.priority;
+                }
+                if let Some(task) = self.tasks.get(to_task) {
+                    to_name.clone_from(&task.name);
+                    *to_priority = task.priority;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.tasks.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn switch(from_task: u32, to_task: u32) -> TraceEvent {
+        TraceEvent::TaskSwitch {
+            from_task,
+            to_task,
+            from_name: None,
+            to_name: None,
+            from_priority: None,
+            to_priority: None,
+        }
+    }
+
+    #[test]
+    fn test_switch_gets_names_and_priorities() {
+        let mut table = TaskTable::new();
+        for mut event in [
+            TraceEvent::TaskCreate {
+                task_id: 3,
+                priority: 2,
+            },
+            TraceEvent::TaskRename {
+                task_id: 3,
+                name: "sensor".to_string(),
+            },
+            TraceEvent::TaskPriority {
+                task_id: 3,
+                priority: 5,
+            },
+        ] {
+            table.apply(&mut event);
+        }
+
+        let mut event = switch(7, 3);
+        table.apply(&mut event);
+        let TraceEvent::TaskSwitch {
+            from_name,
+            to_name,
+            to_priority,
+            ..
+        } = event
+        else {
+            unreachable!()
+        };
+        assert_eq!(from_name, None);
+        assert_eq!(to_name.as_deref(), Some("sensor"));
+        assert_eq!(to_priority, Some(5));
+
+        let mut event = TraceEvent::TaskReady {
+            task_id: 3,
+            name: None,
+        };
+        table.apply(&mut event);
+        assert!(matches!(event, TraceEvent::TaskReady { name: Some(name), .. } if name == "sensor"));
+
+        table.apply(&mut TraceEvent::TaskDelete { task_id: 3 });
+        assert!(table.is_empty());
+    }
+}
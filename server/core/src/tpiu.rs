@@ -0,0 +1,235 @@
+//! TPIU formatter deframing
+//!
+//! With the TPIU formatter enabled, trace sources share the port in
+//! 16-byte frames. Even bytes are either data or an ATB source ID change,
+//! odd bytes are always data, and byte 15 carries the low bit of each even
+//! data byte (or, for ID changes, whether the change is delayed by a byte).
+
+use std::collections::HashMap;
+use tracing::debug;
+
+/// ATB ID the ITM uses when ITM_TCR.TraceBusID is left at 1
+pub const DEFAULT_ITM_ATB_ID: u8 = 1;
+
+/// Frame size in bytes
+const FRAME_SIZE: usize = 16;
+
+/// Full frame synchronization packet
+const FULL_SYNC: [u8; 4] = [0xFF, 0xFF, 0xFF, 0x7F];
+
+/// Half-word synchronization used as padding on the parallel trace port
+const HALF_SYNC: [u8; 2] = [0xFF, 0x7F];
+
+/// ATB IDs that carry no trace data
+const NULL_ID: u8 = 0x00;
+const RESERVED_ID: u8 = 0x7F;
+
+/// Splits a TPIU formatted stream into per-source byte streams
+pub struct TpiuDeframer {
+    buffer: Vec<u8>,
+    synchronized: bool,
+    current_id: u8,
+}
+
+impl TpiuDeframer {
+    pub fn new() -> Self {
+        Self {
+            buffer: Vec::new(),
+            synchronized: false,
+            current_id: NULL_ID,
+        }
+    }
+
+    /// Whether frame alignment has been found
+    pub fn is_synchronized(&self) -> bool {
+        self.synchronized
+    }
+
+    /// Strip framing from raw TPIU bytes and return the payload of each
+    /// trace source, keyed by ATB ID
+    ///
+    /// Data before the first full sync packet is discarded, since frame
+    /// boundaries are unknown until then. A full sync inside a frame
+    /// re-aligns the stream after lost bytes.
+    pub fn process_data(&mut self, data: &[u8]) -> HashMap<u8, Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+
+        let mut sources = HashMap::new();
+        let mut pos = 0;
+
+        if !self.synchronized {
+            match find_full_sync(&self.buffer) {
+                Some(end) => {
+                    debug!("TPIU frame sync found after {} byte(s)", end);
+                    self.synchronized = true;
+                    pos = end;
+                }
+                None => {
+                    // Keep a possible partial sync at the tail
+                    let keep = self.buffer.len().min(FULL_SYNC.len() - 1);
+                    self.buffer.drain(..self.buffer.len() - keep);
+                    return sources;
+                }
+            }
+        }
+
+        loop {
+            let rest = &self.buffer[pos..];
+            if rest.starts_with(&FULL_SYNC) {
+                pos += FULL_SYNC.len();
+            } else if rest.starts_with(&HALF_SYNC) {
+                pos += HALF_SYNC.len();
+            } else if rest.len() >= FRAME_SIZE {
+                // A full sync starting inside the frame means bytes were
+                // lost and the frame boundary is wrong
+                let lookahead = FRAME_SIZE + FULL_SYNC.len() - 1;
+                let window = &rest[..rest.len().min(lookahead)];
+                if let Some(end) = find_full_sync(&window[1..]) {
+                    debug!("TPIU full sync inside a frame, re-aligning");
+                    pos += 1 + end;
+                    continue;
+                }
+                if window.len() < lookahead && ends_with_partial_sync(window) {
+                    // Wait to see whether a sync straddles the frame end
+                    break;
+                }
+
+                let mut frame = [0u8; FRAME_SIZE];
+                frame.copy_from_slice(&rest[..FRAME_SIZE]);
+                self.decode_frame(&frame, &mut sources);
+                pos += FRAME_SIZE;
+            } else {
+                break;
+            }
+        }
+
+        self.buffer.drain(..pos);
+        sources
+    }
+
+    fn decode_frame(&mut self, frame: &[u8; FRAME_SIZE], sources: &mut HashMap<u8, Vec<u8>>) {
+        let aux = frame[FRAME_SIZE - 1];
+
+        for i in 0..(FRAME_SIZE / 2) {
+            let byte = frame[2 * i];
+            let aux_bit = (aux >> i) & 1;
+            let mut delayed_id = None;
+
+            if byte & 1 != 0 {
+                let id = byte >> 1;
+                if aux_bit != 0 {
+                    // New ID applies after the following data byte
+                    delayed_id = Some(id);
+                } else {
+                    self.current_id = id;
+                }
+            } else {
+                self.push(byte | aux_bit, sources);
+            }
+
+            // Byte 15 is the auxiliary byte, not data
+            if 2 * i + 1 < FRAME_SIZE - 1 {
+                self.push(frame[2 * i + 1], sources);
+            }
+
+            if let Some(id) = delayed_id {
+                self.current_id = id;
+            }
+        }
+    }
+
+    fn push(&self, byte: u8, sources: &mut HashMap<u8, Vec<u8>>) {
+        if self.current_id != NULL_ID && self.current_id != RESERVED_ID {
+            sources.entry(self.current_id).or_default().push(byte);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.synchronized = false;
+        self.current_id = NULL_ID;
+    }
+}
+
+impl Default for TpiuDeframer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Position just past the first full sync packet
+fn find_full_sync(bytes: &[u8]) -> Option<usize> {
+    bytes
+        .windows(FULL_SYNC.len())
+        .position(|window| window == FULL_SYNC)
+        .map(|start| start + FULL_SYNC.len())
+}
+
+/// Whether the bytes end with the start of a full sync packet
+fn ends_with_partial_sync(bytes: &[u8]) -> bool {
+    (1..FULL_SYNC.len()).any(|len| bytes.ends_with(&FULL_SYNC[..len]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> Vec<u8> {
+        vec![
+            0x03, 0x11, // ID 1, data
+            0x42, 0x44, // data (LSB from aux), data
+            0x05, 0x55, // delayed change to ID 2, data still for ID 1
+            0x66, 0x77, // data for ID 2
+            0x03, 0x99, // ID 1, data
+            0x01, 0xAA, // null ID, data ignored
+            0xBA, 0xCC, // ignored
+            0xDC, // ignored
+            0x06, // aux: LSB of byte 2 set, byte 4 change delayed
+        ]
+    }
+
+    #[test]
+    fn test_demultiplexes_sources() {
+        let mut deframer = TpiuDeframer::new();
+        let mut stream = vec![0x12, 0x34];
+        stream.extend_from_slice(&FULL_SYNC);
+        stream.extend(frame());
+
+        let sources = deframer.process_data(&stream);
+        assert_eq!(sources[&1], vec![0x11, 0x43, 0x44, 0x55, 0x99]);
+        assert_eq!(sources[&2], vec![0x66, 0x77]);
+        assert!(!sources.contains_key(&0));
+    }
+
+    #[test]
+    fn test_frame_split_across_reads() {
+        let mut deframer = TpiuDeframer::new();
+        let mut stream = FULL_SYNC.to_vec();
+        stream.extend(frame());
+        stream.extend_from_slice(&FULL_SYNC);
+
+        assert!(deframer.process_data(&stream[..10]).is_empty());
+        assert!(deframer.is_synchronized());
+
+        let sources = deframer.process_data(&stream[10..]);
+        assert_eq!(sources[&1].len(), 5);
+        assert_eq!(sources[&2].len(), 2);
+    }
+
+    #[test]
+    fn test_realigns_after_dropped_byte() {
+        let mut deframer = TpiuDeframer::new();
+        let mut stream = FULL_SYNC.to_vec();
+        stream.extend(frame());
+        let mut damaged = frame();
+        damaged.remove(5);
+        stream.extend(damaged);
+        stream.extend_from_slice(&FULL_SYNC);
+        stream.extend(frame());
+
+        // The damaged frame is discarded and the next one decodes cleanly
+        let sources = deframer.process_data(&stream);
+        assert_eq!(sources[&1], [0x11, 0x43, 0x44, 0x55, 0x99].repeat(2));
+        assert_eq!(sources[&2], [0x66, 0x77].repeat(2));
+    }
+}
@@ -0,0 +1,374 @@
+//! CPU utilization from scheduling events
+//!
+//! The cycles between consecutive events are charged to the context that
+//! was running: the innermost active ISR, otherwise the current task.
+//! ISRs come from the firmware's events until DWT exception trace is seen,
+//! as in the ISR timing.
+//! Time is idle between `IdleEnter` and `IdleExit`, or while the RTOS idle
+//! task runs. Totals are kept per stats interval so the reported shares
+//! cover a sliding window like the other stats.
+
+use crate::dwt::{exception_name, FIRST_IRQ_EXCEPTION};
+use crate::stats::STATS_WINDOW;
+use crate::svd::InterruptMap;
+use crate::tasks::TaskTable;
+use callisto_protocol::{IsrUtilization, TaskUtilization, TraceEvent};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Cycles charged to each context during one interval
+#[derive(Debug, Clone, Default)]
+struct Bucket {
+    total: u64,
+    idle: u64,
+    tasks: BTreeMap<u32, u64>,
+    /// Keyed by exception number
+    isrs: BTreeMap<u32, u64>,
+}
+
+impl Bucket {
+    fn add(&mut self, other: &Bucket) {
+        self.total += other.total;
+        self.idle += other.idle;
+        for (&task_id, &cycles) in &other.tasks {
+            *self.tasks.entry(task_id).or_default() += cycles;
+        }
+        for (&exception, &cycles) in &other.isrs {
+            *self.isrs.entry(exception).or_default() += cycles;
+        }
+    }
+}
+
+/// Utilization over the current window
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Utilization {
+    pub window_cycles: u64,
+    /// `None` until idle time has been seen in the trace
+    pub cpu_load: Option<f64>,
+    pub tasks: Vec<TaskUtilization>,
+    pub isrs: Vec<IsrUtilization>,
+}
+
+/// Follows the running context and charges elapsed cycles to it
+pub struct UtilizationTracker {
+    window: Duration,
+    current: Bucket,
+    buckets: VecDeque<(Instant, Bucket)>,
+    last_timestamp: Option<u64>,
+    task: Option<u32>,
+    /// Active exception numbers, innermost last
+    isr_stack: Vec<u32>,
+    /// DWT exception trace seen; firmware ISR events are ignored
+    exception_trace: bool,
+    idle: bool,
+    idle_tasks: HashSet<u32>,
+    /// Idle events or an idle task were seen, so CPU load is known
+    idle_traced: bool,
+    /// Any scheduling event was seen
+    active: bool,
+}
+
+impl UtilizationTracker {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            current: Bucket::default(),
+            buckets: VecDeque::new(),
+            last_timestamp: None,
+            task: None,
+            isr_stack: Vec::new(),
+            exception_trace: false,
+            idle: false,
+            idle_tasks: HashSet::new(),
+            idle_traced: false,
+            active: false,
+        }
+    }
+
+    /// Whether the trace has any events utilization is computed from
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Charge the time up to `timestamp` and follow context changes
+    ///
+    /// Expects events with task and ISR names already applied.
+    pub fn observe(&mut self, timestamp: u64, event: &TraceEvent) {
+        if let Some(last) = self.last_timestamp {
+            self.charge(timestamp.saturating_sub(last));
+        }
+        self.last_timestamp = Some(timestamp);
+
+        match event {
+            TraceEvent::TaskSwitch {
+                to_task, to_name, ..
+            } => {
+                if to_name.as_deref().is_some_and(is_idle_name) {
+                    self.idle_tasks.insert(*to_task);
+                }
+                self.task = Some(*to_task);
+                // Idle hooks run in the idle task and end with it
+                self.idle = false;
+            }
+            TraceEvent::TaskRename { task_id, name } => {
+                if is_idle_name(name) {
+                    self.idle_tasks.insert(*task_id);
+                } else {
+                    self.idle_tasks.remove(task_id);
+                }
+            }
+            TraceEvent::IsrEnter { isr_id, .. } if !self.exception_trace => {
+                self.isr_stack
+                    .push(isr_id.saturating_add(FIRST_IRQ_EXCEPTION));
+            }
+            TraceEvent::IsrExit { isr_id, .. } if !self.exception_trace => {
+                self.exit(isr_id.saturating_add(FIRST_IRQ_EXCEPTION));
+            }
+            TraceEvent::ExceptionEnter { exception, .. } => {
+                self.start_exception_trace();
+                self.isr_stack.push(*exception);
+            }
+            TraceEvent::ExceptionExit { exception } => {
+                self.start_exception_trace();
+                self.exit(*exception);
+            }
+            TraceEvent::IdleEnter => self.idle = true,
+            TraceEvent::IdleExit => self.idle = false,
+            _ => return,
+        }
+        self.active = true;
+    }
+
+    fn start_exception_trace(&mut self) {
+        if !self.exception_trace {
+            self.exception_trace = true;
+            self.isr_stack.clear();
+        }
+    }
+
+    fn exit(&mut self, exception: u32) {
+        // Anything entered after it must have exited unseen
+        if let Some(pos) = self.isr_stack.iter().rposition(|&id| id == exception) {
+            self.isr_stack.truncate(pos);
+        }
+    }
+
+    fn charge(&mut self, cycles: u64) {
+        let bucket = &mut self.current;
+        bucket.total += cycles;
+        if let Some(&exception) = self.isr_stack.last() {
+            *bucket.isrs.entry(exception).or_default() += cycles;
+            return;
+        }
+        if let Some(task_id) = self.task {
+            *bucket.tasks.entry(task_id).or_default() += cycles;
+        }
+        if self.idle
+            || self
+                .task
+                .is_some_and(|task| self.idle_tasks.contains(&task))
+        {
+            bucket.idle += cycles;
+            self.idle_traced = true;
+        }
+    }
+
+    /// Close the current interval and compute utilization over the window
+    pub fn update(
+        &mut self,
+        now: Instant,
+        tasks: &TaskTable,
+        interrupts: &InterruptMap,
+    ) -> Utilization {
+        self.buckets
+            .push_back((now, std::mem::take(&mut self.current)));
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(at, _)| now.saturating_duration_since(*at) >= self.window)
+        {
+            self.buckets.pop_front();
+        }
+
+        let mut window = Bucket::default();
+        for (_, bucket) in &self.buckets {
+            window.add(bucket);
+        }
+        let share = |cycles: u64| {
+            if window.total == 0 {
+                0.0
+            } else {
+                cycles as f64 / window.total as f64
+            }
+        };
+
+        Utilization {
+            window_cycles: window.total,
+            cpu_load: (self.idle_traced && window.total > 0).then(|| 1.0 - share(window.idle)),
+            tasks: window
+                .tasks
+                .iter()
+                .map(|(&task_id, &cycles)| TaskUtilization {
+                    task_id,
+                    name: tasks.name(task_id).map(str::to_string),
+                    cycles,
+                    share: share(cycles),
+                })
+                .collect(),
+            isrs: window
+                .isrs
+                .iter()
+                .map(|(&exception, &cycles)| {
+                    let irq = exception.checked_sub(FIRST_IRQ_EXCEPTION);
+                    IsrUtilization {
+                        exception,
+                        irq,
+                        name: irq
+                            .and_then(|irq| interrupts.get(irq))
+                            .map(|info| info.name.clone())
+                            .unwrap_or_else(|| exception_name(exception)),
+                        cycles,
+                        share: share(cycles),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.window);
+    }
+}
+
+impl Default for UtilizationTracker {
+    fn default() -> Self {
+        Self::new(STATS_WINDOW)
+    }
+}
+
+/// RTOS idle tasks: `IDLE` on FreeRTOS, `idle` on Zephyr and others
+fn is_idle_name(name: &str) -> bool {
+    name.eq_ignore_ascii_case("idle")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn switch(to_task: u32, to_name: &str) -> TraceEvent {
+        TraceEvent::TaskSwitch {
+            from_task: 0,
+            to_task,
+            from_name: None,
+            to_name: Some(to_name.to_string()),
+            from_priority: None,
+            to_priority: None,
+        }
+    }
+
+    fn isr(enter: bool, isr_id: u32) -> TraceEvent {
+        if enter {
+            TraceEvent::IsrEnter {
+                isr_id,
+                name: None,
+                peripheral: None,
+            }
+        } else {
+            TraceEvent::IsrExit {
+                isr_id,
+                name: None,
+                peripheral: None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_isr_time_is_taken_from_the_preempted_task() {
+        let mut tracker = UtilizationTracker::new(Duration::from_secs(2));
+        let start = Instant::now();
+        for (timestamp, event) in [
+            (0, switch(2, "worker")),
+            (100, isr(true, 5)),
+            (110, isr(true, 6)),
+            (130, isr(false, 6)),
+            (150, isr(false, 5)),
+            (400, switch(1, "IDLE")),
+            (1000, switch(2, "worker")),
+        ] {
+            tracker.observe(timestamp, &event);
+        }
+
+        let tasks = TaskTable::new();
+        let interrupts = InterruptMap::new();
+        let utilization = tracker.update(start, &tasks, &interrupts);
+        assert_eq!(utilization.window_cycles, 1000);
+        assert_eq!(utilization.cpu_load, Some(0.4));
+        let cycles = |list: &[TaskUtilization]| -> Vec<(u32, u64)> {
+            list.iter().map(|t| (t.task_id, t.cycles)).collect()
+        };
+        assert_eq!(cycles(&utilization.tasks), [(1, 600), (2, 350)]);
+        let isrs: Vec<_> = utilization.isrs.iter().map(|i| (i.irq, i.cycles)).collect();
+        assert_eq!(isrs, [(Some(5), 30), (Some(6), 20)]);
+        assert_eq!(utilization.isrs[0].name, "IRQ5");
+
+        // Half idle in the next interval; the first drops out of the window
+        tracker.observe(2000, &TraceEvent::IdleEnter);
+        tracker.observe(3000, &TraceEvent::IdleExit);
+        tracker.update(start + Duration::from_secs(1), &tasks, &interrupts);
+        let utilization = tracker.update(start + Duration::from_secs(2), &tasks, &interrupts);
+        assert_eq!(utilization.window_cycles, 2000);
+        assert_eq!(utilization.cpu_load, Some(0.5));
+        assert_eq!(cycles(&utilization.tasks), [(2, 2000)]);
+    }
+
+    #[test]
+    fn test_no_cpu_load_without_idle_events() {
+        let mut tracker = UtilizationTracker::default();
+        tracker.observe(0, &switch(2, "worker"));
+        tracker.observe(500, &switch(3, "comms"));
+        let utilization = tracker.update(Instant::now(), &TaskTable::new(), &InterruptMap::new());
+        assert!(tracker.is_active());
+        assert_eq!(utilization.cpu_load, None);
+        assert_eq!(utilization.tasks[0].share, 1.0);
+    }
+
+    #[test]
+    fn test_exception_trace_replaces_firmware_events() {
+        let mut tracker = UtilizationTracker::new(Duration::from_secs(2));
+        let exception = |enter: bool, exception: u32| {
+            if enter {
+                TraceEvent::ExceptionEnter {
+                    exception,
+                    name: None,
+                }
+            } else {
+                TraceEvent::ExceptionExit { exception }
+            }
+        };
+        for (timestamp, event) in [
+            (0, switch(2, "worker")),
+            (100, exception(true, 15)),
+            (110, isr(true, 28)),
+            (120, exception(true, 44)),
+            (150, exception(false, 44)),
+            (160, isr(false, 28)),
+            (200, exception(false, 15)),
+            (1000, switch(1, "IDLE")),
+        ] {
+            tracker.observe(timestamp, &event);
+        }
+
+        let utilization = tracker.update(Instant::now(), &TaskTable::new(), &InterruptMap::new());
+        assert!(tracker.is_active());
+        assert_eq!(utilization.tasks[0].cycles, 900);
+        let isrs: Vec<_> = utilization
+            .isrs
+            .iter()
+            .map(|i| (i.exception, i.irq, i.name.as_str(), i.cycles))
+            .collect();
+        assert_eq!(
+            isrs,
+            [(15, None, "SysTick", 70), (44, Some(28), "IRQ28", 30)]
+        );
+    }
+}
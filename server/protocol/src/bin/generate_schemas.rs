@@ -73,7 +73,7 @@ fn extract_definitions(schema: &serde_json::Value) -> Option<serde_json::Map<Str
 }
 
 /// Creates a root schema object without the definitions
-fn create_root_schema(mut schema: serde_json::Value, schema_name: &str) -> serde_json::Value {
+fn create_root_schema(mut schema: serde_json::Value, _schema_name: &str) -> serde_json::Value {
     // Remove definitions from the root schema since we'll put them in the combined definitions
     if let Some(obj) = schema.as_object_mut() {
         obj.remove("definitions");
@@ -31,6 +31,9 @@ pub enum ServerMessage {
         ports_map: HashMap<u8, PortConfig>,
         cpu_hz: Option<u64>,
         dwt_available: bool,
+        /// Interrupts from the chip's SVD file, if one is loaded
+        #[serde(default)]
+        interrupts: Vec<InterruptInfo>,
     },
     /// ITM trace events (decoded)
     Event {
@@ -50,6 +53,43 @@ pub enum ServerMessage {
         bytes_per_sec: f64,
         drop_rate: f64,
         cpu_load: Option<f64>,
+        /// Share of the SWO link's capacity in use, when tracing from a probe
+        #[serde(default)]
+        swo_utilization: Option<f64>,
+        /// Traffic per port, in port order
+        #[serde(default)]
+        ports: Vec<PortStats>,
+        #[serde(default)]
+        queues: QueueDepths,
+    },
+    /// CPU time share per task and ISR over the recent window, from the
+    /// traced task switches, ISR enters and exits and idle events
+    Utilization {
+        timestamp: DateTime<Utc>,
+        /// Target cycles covered by the window
+        window_cycles: u64,
+        /// Share of the window not spent idle, if idle time is traced
+        cpu_load: Option<f64>,
+        /// Time in each task, not counting ISRs that preempted it
+        tasks: Vec<TaskUtilization>,
+        isrs: Vec<IsrUtilization>,
+    },
+    /// Scheduling analysis per task since tracing started
+    SchedulingReport {
+        timestamp: DateTime<Utc>,
+        tasks: Vec<TaskSchedule>,
+    },
+    /// Duration and arrival histograms per interrupt since tracing started
+    IsrTimingReport {
+        timestamp: DateTime<Utc>,
+        isrs: Vec<IsrTiming>,
+    },
+    /// Statistical profile built from DWT PC samples
+    Profile {
+        timestamp: DateTime<Utc>,
+        total_samples: u64,
+        sleep_fraction: f64,
+        hot_spots: Vec<ProfileEntry>,
     },
     /// Error messages
     Error {
@@ -73,6 +113,15 @@ pub enum ClientMessage {
     Start {
         allow_mask: u32, // Bitmask for ports 0-31
         baud_rate: Option<u32>,
+        /// Firmware ELF used by decoders that need its symbol tables
+        #[serde(default)]
+        elf_path: Option<String>,
+        /// Generated JSON dictionary of deferred printf format strings
+        #[serde(default)]
+        printf_dictionary: Option<String>,
+        /// CMSIS-SVD file for the chip, used to name interrupts
+        #[serde(default)]
+        svd_path: Option<String>,
     },
     /// Stop ITM tracing
     Stop,
@@ -81,6 +130,16 @@ pub enum ClientMessage {
         port_mask: Option<u32>,
         event_types: Option<Vec<String>>,
     },
+    /// Change how a single port is decoded
+    SetPortConfig { config: PortConfig },
+    /// Change how several ports are decoded, all or nothing
+    SetPortConfigs { ports: Vec<PortConfig> },
+    /// Set the expected timing of periodic tasks, checked for deadline misses
+    SetTaskDeadlines { tasks: Vec<TaskDeadline> },
+    /// Ask for a `SchedulingReport` for the current session
+    GetSchedulingReport,
+    /// Ask for an `IsrTimingReport` for the current session
+    GetIsrTimingReport,
 }
 
 /// Configuration for an ITM port
@@ -103,6 +162,11 @@ pub enum DecoderType {
     TaskIsr,
     /// Performance counters
     Counter,
+    /// defmt log frames, decoded with the firmware ELF's `.defmt` table
+    Defmt,
+    /// Deferred printf: a format string ID and raw argument words, formatted
+    /// on the host from the firmware ELF or a printf dictionary
+    Printf,
     /// User-defined format
     User { format: String },
 }
@@ -115,28 +179,363 @@ pub enum TraceEvent {
     Text { message: String },
     /// Marker with ID
     Marker { id: u32, name: Option<String> },
-    /// Task switch event
-    TaskSwitch { from_task: u32, to_task: u32 },
+    /// Task switch event, with names and priorities from the session's
+    /// task table when known
+    TaskSwitch {
+        from_task: u32,
+        to_task: u32,
+        #[serde(default)]
+        from_name: Option<String>,
+        #[serde(default)]
+        to_name: Option<String>,
+        #[serde(default)]
+        from_priority: Option<u32>,
+        #[serde(default)]
+        to_priority: Option<u32>,
+    },
+    /// Task created
+    TaskCreate { task_id: u32, priority: u32 },
+    /// Task deleted
+    TaskDelete { task_id: u32 },
+    /// Task named or renamed
+    TaskRename { task_id: u32, name: String },
+    /// Task priority changed
+    TaskPriority { task_id: u32, priority: u32 },
+    /// Task made ready to run (activated), e.g. released by a timer or
+    /// unblocked by a queue
+    TaskReady {
+        task_id: u32,
+        #[serde(default)]
+        name: Option<String>,
+    },
+    /// Start of a timed code region
+    SpanBegin { span_id: u32, name: Option<String> },
+    /// End of a timed code region
+    SpanEnd { span_id: u32, name: Option<String> },
+    /// Completed span, reported at its end
+    Span {
+        span_id: u32,
+        name: Option<String>,
+        context: SpanContext,
+        /// Number of enclosing spans in the same context
+        depth: u32,
+        /// Timestamp of the matching `SpanBegin`
+        start: u64,
+        /// Timestamp units from begin to end
+        duration: u64,
+    },
+    /// Span that could not be paired
+    SpanDiagnostic {
+        span_id: u32,
+        name: Option<String>,
+        context: SpanContext,
+        problem: SpanProblem,
+    },
     /// ISR enter event
-    IsrEnter { isr_id: u32, name: Option<String> },
+    IsrEnter {
+        isr_id: u32,
+        name: Option<String>,
+        /// Peripheral(s) raising the interrupt, from the SVD file
+        #[serde(default)]
+        peripheral: Option<String>,
+    },
     /// ISR exit event
-    IsrExit { isr_id: u32 },
+    IsrExit {
+        isr_id: u32,
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        peripheral: Option<String>,
+    },
     /// Idle state enter
     IdleEnter,
     /// Idle state exit
     IdleExit,
     /// Counter value
-    Counter { counter_id: u32, value: u64 },
+    Counter {
+        counter_id: u32,
+        value: u64,
+        #[serde(default)]
+        name: Option<String>,
+    },
+    /// ITM FIFO overflow, trace data was lost at this point
+    Overflow,
+    /// Exception entry reported by DWT exception trace
+    ExceptionEnter { exception: u32, name: Option<String> },
+    /// Exception exit reported by DWT exception trace
+    ExceptionExit { exception: u32 },
+    /// Return to an exception (or thread mode, 0) after an exit
+    ExceptionReturn { exception: u32 },
+    /// Periodic PC sample, `None` when the core was sleeping
+    PcSample { pc: Option<u32> },
+    /// DWT comparator match on a watched data access
+    DataTrace {
+        comparator: u8,
+        access: DataAccess,
+        value: Option<u32>,
+        pc: Option<u32>,
+        address_offset: Option<u16>,
+    },
+    /// Structured log message
+    Log {
+        level: Option<LogLevel>,
+        message: String,
+        module_path: Option<String>,
+        file: Option<String>,
+        line: Option<u32>,
+        /// Target timestamp as formatted by the firmware, if it sends one
+        device_time: Option<String>,
+    },
+    /// Record decoded with a port's user-defined format
+    Record { fields: Vec<RecordField> },
     /// Raw data (fallback)
     Raw { data: Vec<u8> },
 }
 
+/// Traffic on one ITM port
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PortStats {
+    pub port: u8,
+    /// Events decoded since tracing started
+    pub events: u64,
+    /// Stimulus or hardware packet payload bytes since tracing started
+    pub bytes: u64,
+    pub events_per_sec: f64,
+    pub bytes_per_sec: f64,
+}
+
+/// CPU time spent in one task
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TaskUtilization {
+    pub task_id: u32,
+    pub name: Option<String>,
+    pub cycles: u64,
+    /// Share of the window's cycles
+    pub share: f64,
+}
+
+/// CPU time spent in one interrupt or exception handler
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct IsrUtilization {
+    /// Exception number; IRQs start at 16
+    pub exception: u32,
+    /// IRQ number, for external interrupts
+    pub irq: Option<u32>,
+    /// Name from the SVD file, or the exception's architectural name
+    pub name: String,
+    pub cycles: u64,
+    /// Share of the window's cycles
+    pub share: f64,
+}
+
+/// Expected timing of a periodic task, in timestamp units
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TaskDeadline {
+    /// Task name, as sent by the target
+    pub task: String,
+    /// Time between activations
+    pub period: u64,
+    /// Latest completion after activation; the period if unset
+    #[serde(default)]
+    pub deadline: Option<u64>,
+}
+
+/// Summary of a set of durations, in timestamp units
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TimingSummary {
+    pub count: u64,
+    pub min: u64,
+    pub max: u64,
+    pub mean: f64,
+    /// Percentiles over the most recent samples
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+}
+
+/// Scheduling behaviour of one task
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TaskSchedule {
+    pub task_id: u32,
+    pub name: Option<String>,
+    pub priority: Option<u32>,
+    /// Jobs completed: activation to blocking again
+    pub jobs: u64,
+    /// Activation to first running
+    pub activation_latency: Option<TimingSummary>,
+    /// Time running per job, not counting preemptions or ISRs
+    pub execution_time: Option<TimingSummary>,
+    /// Activation to completion
+    pub response_time: Option<TimingSummary>,
+    /// Time between consecutive activations
+    pub activation_period: Option<TimingSummary>,
+    /// Times the task was switched out while still ready
+    pub preemptions: u64,
+    /// Time spent blocked, between completing a job and the next activation
+    pub blocked_cycles: u64,
+    /// Expected timing, if configured for this task
+    pub deadline: Option<TaskDeadline>,
+    pub deadline_misses: u64,
+}
+
+/// Distribution of durations, in timestamp units
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Histogram {
+    pub count: u64,
+    pub min: u64,
+    pub max: u64,
+    pub mean: f64,
+    /// Non-empty buckets in ascending order
+    pub buckets: Vec<HistogramBucket>,
+}
+
+/// Values from `lower` up to but not including `upper`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct HistogramBucket {
+    pub lower: u64,
+    pub upper: u64,
+    pub count: u64,
+}
+
+/// Timing of one interrupt or exception
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct IsrTiming {
+    /// Exception number; IRQs start at 16
+    pub exception: u32,
+    /// IRQ number, for external interrupts
+    pub irq: Option<u32>,
+    /// Name from the SVD file, or the exception's architectural name
+    pub name: String,
+    /// Time in the handler per run, not counting nested interrupts
+    pub duration: Histogram,
+    /// Time between consecutive entries
+    pub inter_arrival: Histogram,
+    /// Change in inter-arrival time from one entry to the next
+    pub jitter: Histogram,
+    /// Runs interrupted by a higher priority interrupt
+    pub preemptions: u64,
+    /// Enters or exits without a match, e.g. after lost trace data
+    pub unpaired: u64,
+}
+
+/// Backlog in the server's internal queues
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct QueueDepths {
+    /// Captured chunks waiting to be decoded, if the source buffers them
+    pub source: Option<u64>,
+    /// Messages waiting to be sent to clients
+    pub outgoing: Option<u64>,
+}
+
+/// One address in a PC sample profile
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ProfileEntry {
+    pub pc: u32,
+    pub samples: u64,
+    pub fraction: f64,
+}
+
+/// Kind of access reported by DWT data trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum DataAccess {
+    /// Watched location was read
+    Read,
+    /// Watched location was written
+    Write,
+    /// Comparator matched but no data value was traced
+    Match,
+}
+
+/// Execution context spans nest in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type")]
+pub enum SpanContext {
+    /// No task switch seen yet, or no RTOS events on the target
+    Thread,
+    Task { task_id: u32 },
+    Isr { isr_id: u32 },
+    /// System exception from DWT exception trace, e.g. 15 for SysTick
+    Exception { exception: u32 },
+}
+
+/// Why a span could not be paired
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type")]
+pub enum SpanProblem {
+    /// Never ended: a span opened inside it ended first, its context
+    /// ended (ISR exit or task deletion), or tracing stopped
+    Unclosed,
+    /// Ended while a different span was innermost, and it is not open
+    /// in this context
+    Mismatched { open_span: u32 },
+    /// Ended with no span open in this context
+    UnmatchedEnd,
+}
+
+/// Interrupt described by the chip's SVD file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct InterruptInfo {
+    /// IRQ number (exception number - 16)
+    pub irq: u32,
+    pub name: String,
+    pub description: Option<String>,
+    /// Peripherals that declare this interrupt
+    pub peripherals: Vec<String>,
+}
+
+/// Severity of a log message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// Named field of a user-defined record
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RecordField {
+    pub name: String,
+    /// Type as written in the format, e.g. `u32` or `i16[3]`
+    pub field_type: String,
+    pub value: FieldValue,
+}
+
+/// Value of a record field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    Array(Vec<FieldValue>),
+}
+
 /// Raw ITM frame data
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ItmFrame {
     pub port: u8,
     pub data: Vec<u8>,
     pub timestamp: Option<u64>, // DWT CYCCNT if available
+    #[serde(default)]
+    pub timestamp_quality: TimestampQuality,
+}
+
+/// How closely a reconstructed timestamp matches the traced event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+pub enum TimestampQuality {
+    /// Timestamp is synchronous to the event
+    #[default]
+    Exact,
+    /// Timestamp packet was delayed relative to the event
+    TimestampDelayed,
+    /// Event packet was delayed in the ITM FIFO
+    EventDelayed,
+    /// Both the timestamp and the event packet were delayed
+    BothDelayed,
+    /// No timestamp followed the event; last known time was used
+    Inexact,
 }
 
 /// Probe information for listing available probes
@@ -204,6 +603,10 @@ impl PortConfig {
 pub mod standard_ports {
     use super::*;
 
+    /// Pseudo-port for events produced by the ITM/DWT hardware itself
+    /// rather than by a stimulus port write
+    pub const HARDWARE: u8 = 255;
+
     pub fn default_config() -> HashMap<u8, PortConfig> {
         let mut ports = HashMap::new();
         